@@ -0,0 +1,117 @@
+use super::vm::*;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// how control gets from one block to the next
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    // the jump of a `Br` or `Gt`
+    Taken,
+    // falling off the end of the block
+    Fallthrough,
+}
+
+impl Edge {
+    pub fn name(self) -> &'static str {
+        match self {
+            Edge::Taken => "taken",
+            Edge::Fallthrough => "fallthrough",
+        }
+    }
+}
+
+// a basic block of a `vm::Decl`: its instructions `start..end`, and the
+// blocks control goes to after it, by index
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub succs: Vec<(usize, Edge)>,
+}
+
+// splits the instructions of `decl` into basic blocks; a block starts at
+// a `Label` and ends after a `Br` or `Gt`, and the last one returns
+pub fn blocks(decl: &Decl) -> Vec<Block> {
+    let mut starts = vec![];
+    for (i, (instr, _)) in decl.instrs.iter().enumerate() {
+        let after_jump = i > 0 && matches!(decl.instrs[i - 1].0, Instr::Br(..) | Instr::Gt(_));
+        if i == 0 || after_jump || matches!(instr, Instr::Label(_)) {
+            starts.push(i);
+        }
+    }
+    let labels: HashMap<&String, usize> = starts
+        .iter()
+        .enumerate()
+        .filter_map(|(block, &start)| match &decl.instrs[start].0 {
+            Instr::Label(label) => Some((label, block)),
+            _ => None,
+        })
+        .collect();
+    let mut blocks = vec![];
+    for (block, &start) in starts.iter().enumerate() {
+        let end = starts.get(block + 1).copied().unwrap_or(decl.instrs.len());
+        let next = Some(block + 1).filter(|&next| next < starts.len());
+        let mut succs = vec![];
+        match &decl.instrs[end - 1].0 {
+            Instr::Br(_, label) => {
+                succs.extend(labels.get(label).map(|&to| (to, Edge::Taken)));
+                succs.extend(next.map(|to| (to, Edge::Fallthrough)));
+            }
+            Instr::Gt(label) => succs.extend(labels.get(label).map(|&to| (to, Edge::Taken))),
+            _ => succs.extend(next.map(|to| (to, Edge::Fallthrough))),
+        }
+        blocks.push(Block { start, end, succs });
+    }
+    blocks
+}
+
+// `text` as a left-justified Graphviz label
+fn dot_label(text: &str) -> String {
+    let mut label = String::new();
+    for line in text.lines() {
+        label.push_str(&line.trim().replace('\\', "\\\\").replace('"', "\\\""));
+        label.push_str("\\l");
+    }
+    label
+}
+
+// the control-flow graph of each function of `program` in Graphviz
+// format; `allocated` prints the real registers, as `vm-alloc` does
+pub fn dot(program: &Program, allocated: bool) -> String {
+    let mut out = String::from("digraph vm {\n  node [shape=box, fontname=monospace];\n");
+    for decl in &program.decls {
+        let node = |block: usize| format!("\"{}.{}\"", decl.funlb, block);
+        writeln!(out, "  subgraph \"cluster_{}\" {{", decl.funlb).unwrap();
+        writeln!(out, "    label=\"{}\";", decl.funlb).unwrap();
+        let blocks = blocks(decl);
+        for (i, block) in blocks.iter().enumerate() {
+            let text: String = decl.instrs[block.start..block.end]
+                .iter()
+                .map(|(instr, _)| {
+                    if allocated {
+                        format!("{:#}", instr)
+                    } else {
+                        instr.to_string()
+                    }
+                })
+                .collect();
+            writeln!(out, "    {} [label=\"{}\"];", node(i), dot_label(&text)).unwrap();
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            for (to, edge) in &block.succs {
+                writeln!(
+                    out,
+                    "    {} -> {} [label=\"{}\"];",
+                    node(i),
+                    node(*to),
+                    edge.name()
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("  }\n");
+    }
+    out.push_str("}\n");
+    out
+}
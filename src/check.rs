@@ -0,0 +1,161 @@
+use super::parser::*;
+use super::*;
+
+use std::fmt;
+
+// what the syntax tells about the tuple structure of a value
+#[derive(Clone, Debug)]
+enum Shape {
+    Scalar,
+    Pair(Box<Shape>, Box<Shape>),
+    Unknown,
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Shape::Pair(s1, s2) => write!(f, "({}, {})", s1, s2),
+            Shape::Scalar | Shape::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+impl Shape {
+    fn of(ast: &Ast) -> Self {
+        match ast {
+            Ast::ILit(..) | Ast::BLit(..) | Ast::Binop(..) => Shape::Scalar,
+            Ast::Tuple(ast1, ast2, _) => {
+                Shape::Pair(Box::new(Shape::of(ast1)), Box::new(Shape::of(ast2)))
+            }
+            Ast::Let(_, _, ast2, _) | Ast::Rec(_, _, _, ast2, _) => Shape::of(ast2),
+            Ast::If(_, ast2, ast3, _) => match Shape::of(ast2) {
+                Shape::Unknown => Shape::of(ast3),
+                shape => shape,
+            },
+            _ => Shape::Unknown,
+        }
+    }
+    fn matches(&self, other: &Shape) -> bool {
+        match (self, other) {
+            (Shape::Unknown, _) | (_, Shape::Unknown) | (Shape::Scalar, Shape::Scalar) => true,
+            (Shape::Pair(a1, b1), Shape::Pair(a2, b2)) => a1.matches(a2) && b1.matches(b2),
+            _ => false,
+        }
+    }
+}
+
+struct LoopScope {
+    id: Id,
+    shape: Shape,
+    init: Span,
+    span: Span,
+}
+
+struct Checker {
+    // loops of the function being checked, innermost last
+    loops: Vec<LoopScope>,
+    fun: Option<Span>,
+}
+
+impl Checker {
+    // the body of `fun` or `let rec` starts a new function, whose
+    // `recur` cannot reach the loops around it
+    fn function(&mut self, ast: &Ast, span: Span) -> CompileResult<()> {
+        let loops = std::mem::take(&mut self.loops);
+        let fun = self.fun.replace(span);
+        let result = self.check(ast, false);
+        self.loops = loops;
+        self.fun = fun;
+        result
+    }
+    fn recur(&mut self, ast: &Ast, tail: bool, span: Span) -> CompileResult<()> {
+        let scope = match self.loops.last() {
+            Some(scope) => scope,
+            None => {
+                let note = self
+                    .fun
+                    .map(|fspan| (fspan, String::from("the enclosing function starts here.")));
+                return Err(CompileError {
+                    kind: ErrorKind::Loop,
+                    severity: Severity::Error,
+                    message: String::from("`recur` is not inside a loop of the same function."),
+                    span: Some(span),
+                    note,
+                    more: vec![],
+                });
+            }
+        };
+        if !tail {
+            return Err(CompileError::new(
+                ErrorKind::Loop,
+                String::from("`recur` must be in tail position of its loop."),
+                span,
+            )
+            .with_note(scope.span, String::from("the loop is here.")));
+        }
+        let shape = Shape::of(ast);
+        if !shape.matches(&scope.shape) {
+            return Err(CompileError::new(
+                ErrorKind::Loop,
+                format!(
+                    "`recur` value has shape {}, but loop variable `{}` has shape {}.",
+                    shape, scope.id, scope.shape
+                ),
+                ast.span(),
+            )
+            .with_note(
+                scope.init,
+                format!("loop variable `{}` is bound here.", scope.id),
+            ));
+        }
+        self.check(ast, false)
+    }
+    // `tail` tells whether the value of `ast` is the value of the innermost loop
+    fn check(&mut self, ast: &Ast, tail: bool) -> CompileResult<()> {
+        match ast {
+            Ast::Nonaexpr | Ast::Error(..) | Ast::ILit(..) | Ast::BLit(..) | Ast::Var(..) => Ok(()),
+            Ast::Binop(_, ast1, ast2, _) | Ast::App(ast1, ast2, _) | Ast::Tuple(ast1, ast2, _) => {
+                self.check(ast1, false)?;
+                self.check(ast2, false)
+            }
+            Ast::If(ast1, ast2, ast3, _) => {
+                self.check(ast1, false)?;
+                self.check(ast2, tail)?;
+                self.check(ast3, tail)
+            }
+            Ast::Fun(_, ast1, span) => self.function(ast1, *span),
+            Ast::Let(_, ast1, ast2, _) => {
+                self.check(ast1, false)?;
+                self.check(ast2, tail)
+            }
+            Ast::Rec(_, _, ast1, ast2, span) => {
+                self.function(ast1, *span)?;
+                self.check(ast2, tail)
+            }
+            Ast::Loop((id, _), ast1, ast2, span) => {
+                self.check(ast1, false)?;
+                self.loops.push(LoopScope {
+                    id: id.clone(),
+                    shape: Shape::of(ast1),
+                    init: ast1.span(),
+                    span: *span,
+                });
+                let result = self.check(ast2, true);
+                self.loops.pop();
+                result
+            }
+            Ast::Recur(ast1, span) => self.recur(ast1, tail, *span),
+            Ast::Proj(ast1, _, _) => self.check(ast1, false),
+        }
+    }
+}
+
+// every `recur` must be in tail position of a loop of its own function
+// and pass a value shaped like the loop variable
+pub fn check_loops(ast: &Ast) -> CompileResult<()> {
+    let mut checker = Checker {
+        loops: vec![],
+        fun: None,
+    };
+    checker.check(ast, false)
+}
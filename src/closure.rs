@@ -1,21 +1,14 @@
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
-
-use super::normal::{Bintype, Value};
+use super::normal::{values_from_json, values_json, Bintype, Value};
 use super::*;
 
-pub static FRESH_COUNT: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
+use std::fmt;
 
-fn get_fresh_function_var(s: &str, fresh_char: char) -> String {
-    let num = *FRESH_COUNT.lock().unwrap();
-    *FRESH_COUNT.lock().unwrap() = num + 1;
-    let mut ss = String::from("$");
-    ss.push(fresh_char);
-    ss.push('_');
-    let numstr = &num.to_string();
-    ss.push_str(s);
-    ss.push_str(numstr);
-    ss
+// converts one program; `fresh` numbers the variables it introduces
+struct Converter<'a> {
+    link: &'a Linkage,
+    fresh: i32,
+    // closures built at run time, one per local function
+    closures: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -26,57 +19,31 @@ pub enum Cexp {
     If(Value, Box<Exp>, Box<Exp>),
     Tuple(Vec<Value>),
     Proj(Value, i32),
+    Extcall(String, Vec<Value>),
 }
 
-impl Cexp {
-    pub fn program_display(self) {
+impl fmt::Display for Cexp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Cexp::*;
         match self {
-            Val(Value::Var(id)) => {
-                print!("{}", id);
-            }
-            Val(Value::Intv(v)) => {
-                print!("{}", v);
+            Val(val) => write!(f, "{}", val),
+            Binop(tty, val1, val2) => write!(f, "{}{}{}", val1, tty.bintype_signal(), val2),
+            App(val, vals) => {
+                write!(f, "{} (", val)?;
+                comma_separated(f, vals)?;
+                write!(f, ")")
             }
-            Binop(tty, val1, val2) => {
-                Val(val1).program_display();
-                print!("{}", tty.bintype_signal());
-                Val(val2).program_display();
-            }
-            App(val1, mut valls) => {
-                Val(val1).program_display();
-                print!(" (");
-                for i in 0..valls.len() {
-                    Val(std::mem::replace(&mut valls[i], Value::Intv(-1))).program_display();
-                    if i + 1 == valls.len() {
-                        break;
-                    }
-                    print!(", ");
-                }
-                print!(")")
+            If(val, exp1, exp2) => write!(f, "if {} then {} else {}", val, exp1, exp2),
+            Tuple(vals) => {
+                write!(f, "(")?;
+                comma_separated(f, vals)?;
+                write!(f, ")")
             }
-            If(val, exp1, exp2) => {
-                print!("if ");
-                Val(val).program_display();
-                print!(" then ");
-                exp1.program_display();
-                print!(" else ");
-                exp2.program_display();
-            }
-            Tuple(mut valls) => {
-                print!(" (");
-                for i in 0..valls.len() {
-                    Val(std::mem::replace(&mut valls[i], Value::Intv(-1))).program_display();
-                    if i + 1 == valls.len() {
-                        break;
-                    }
-                    print!(", ");
-                }
-                print!(")")
-            }
-            Proj(val, i) => {
-                Val(val).program_display();
-                print!(".{}", i);
+            Proj(val, i) => write!(f, "{}.{}", val, i),
+            Extcall(symbol, vals) => {
+                write!(f, "{} (", symbol)?;
+                comma_separated(f, vals)?;
+                write!(f, ")")
             }
         }
     }
@@ -84,80 +51,65 @@ impl Cexp {
 
 #[derive(Clone, Debug)]
 pub enum Exp {
-    Compexp(Box<Cexp>),
-    Let(Id, Box<Cexp>, Box<Exp>),
-    Loop(Id, Box<Cexp>, Box<Exp>),
-    Letrec(Id, Vec<Id>, Box<Exp>, Box<Exp>),
-    Recur(Value),
+    Compexp(Box<Cexp>, Span),
+    Let(Id, Box<Cexp>, Box<Exp>, Span),
+    Loop(Id, Box<Cexp>, Box<Exp>, Span),
+    Letrec(Id, Vec<Id>, Box<Exp>, Box<Exp>, Span),
+    Recur(Value, Span),
 }
 
 impl Exp {
-    pub fn program_display(self) {
+    pub fn span(&self) -> Span {
+        match self {
+            Exp::Compexp(_, span)
+            | Exp::Let(_, _, _, span)
+            | Exp::Loop(_, _, _, span)
+            | Exp::Letrec(_, _, _, _, span)
+            | Exp::Recur(_, span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Exp::*;
         match self {
-            Compexp(cexp) => {
-                cexp.program_display();
-            }
-            Let(id, cexp, exp) => {
-                print!("let {} = ", id);
-                cexp.program_display();
-                print!(" in\n");
-                exp.program_display();
-            }
-            Loop(id, cexp, exp) => {
-                print!("loop {} = ", id);
-                cexp.program_display();
-                print!(" in\n");
-                exp.program_display();
-            }
-            Letrec(id1, idls, exp1, exp2) => {
-                print!("let rec {} ", id1);
-                print!("(");
-                for i in 0..idls.len() {
-                    print!("{}", idls[i]);
-                    if i + 1 == idls.len() {
-                        break;
-                    }
-                    print!(", ");
-                }
-                print!(") = ");
-                match *exp1 {
-                    Let(..) | Letrec(..) | Loop(..) => {
-                        print!("\n");
-                    }
-                    _ => {}
+            Compexp(cexp, _) => write!(f, "{}", cexp),
+            Let(id, cexp, exp, _) => write!(f, "let {} = {} in\n{}", id, cexp, exp),
+            Loop(id, cexp, exp, _) => write!(f, "loop {} = {} in\n{}", id, cexp, exp),
+            Letrec(id, args, exp1, exp2, _) => {
+                write!(f, "let rec {} (", id)?;
+                comma_separated(f, args)?;
+                write!(f, ") = ")?;
+                if let Let(..) | Letrec(..) | Loop(..) = **exp1 {
+                    writeln!(f)?;
                 }
-                exp1.program_display();
-                print!(" in\n");
-                exp2.program_display();
-            }
-            Recur(val) => {
-                print!("recur ");
-                Cexp::Val(val).program_display();
+                write!(f, "{} in\n{}", exp1, exp2)
             }
+            Recur(val, _) => write!(f, "recur {}", val),
         }
     }
 }
 
-fn ef(cexp: Cexp) -> Exp {
-    Exp::Compexp(Box::new(cexp))
+fn ef(cexp: Cexp, span: Span) -> Exp {
+    Exp::Compexp(Box::new(cexp), span)
 }
 
-struct AsgFun(Box<dyn FnOnce(Cexp) -> Exp>);
+struct AsgFun(Box<dyn FnOnce(Cexp, Span) -> Exp>);
 
 impl AsgFun {
-    fn new(f: Box<dyn FnOnce(Cexp) -> Exp>) -> Self {
+    fn new(f: Box<dyn FnOnce(Cexp, Span) -> Exp>) -> Self {
         Self(f)
     }
-    fn apply(&mut self) -> Box<dyn FnOnce(Cexp) -> Exp> {
+    fn apply(&mut self) -> Box<dyn FnOnce(Cexp, Span) -> Exp> {
         std::mem::replace(&mut self.0, Box::new(ef))
     }
 }
 
-fn extract_v(asv: &Vec<String>, val: Value) -> Vec<String> {
+fn extract_v(link: &Linkage, asv: &[String], val: Value) -> Vec<String> {
     match val {
         Value::Var(v) => {
-            if !asv.contains(&v) {
+            if !asv.contains(&v) && !link.is_global(&v) {
                 vec![v.clone()]
             } else {
                 vec![]
@@ -169,193 +121,383 @@ fn extract_v(asv: &Vec<String>, val: Value) -> Vec<String> {
     }
 }
 
-fn sub_find_fv(nmce: normal::Cexp, asv: &mut Vec<String>) -> (Vec<String>, normal::Cexp) {
+fn sub_find_fv(
+    nmce: normal::Cexp,
+    link: &Linkage,
+    asv: &mut Vec<String>,
+) -> (Vec<String>, normal::Cexp) {
     use normal::Cexp::*;
     match nmce {
-        Val(val) => (extract_v(&asv, val.clone()), Val(val)),
+        Val(val) => (extract_v(link, asv, val.clone()), Val(val)),
         Binop(btype, val1, val2) => {
-            let mut fv = extract_v(&asv, val1.clone());
-            fv.append(&mut extract_v(&asv, val2.clone()));
+            let mut fv = extract_v(link, asv, val1.clone());
+            fv.append(&mut extract_v(link, asv, val2.clone()));
             (fv, Binop(btype, val1, val2))
         }
         App(val1, val2) => {
-            let mut fv = extract_v(&asv, val1.clone());
-            fv.append(&mut extract_v(&asv, val2.clone()));
+            let mut fv = extract_v(link, asv, val1.clone());
+            fv.append(&mut extract_v(link, asv, val2.clone()));
             (fv, App(val1, val2))
         }
         Tuple(val1, val2) => {
-            let mut fv = extract_v(&asv, val1.clone());
-            fv.append(&mut extract_v(&asv, val2.clone()));
+            let mut fv = extract_v(link, asv, val1.clone());
+            fv.append(&mut extract_v(link, asv, val2.clone()));
             (fv, Tuple(val1, val2))
         }
         If(val, exp1, exp2) => {
-            let mut fv = extract_v(&asv, val.clone());
-            let (mut fv1, nexp1) = find_fv(*exp1, asv);
-            let (mut fv2, nexp2) = find_fv(*exp2, asv);
+            let mut fv = extract_v(link, asv, val.clone());
+            let (mut fv1, nexp1) = find_fv(*exp1, link, asv);
+            let (mut fv2, nexp2) = find_fv(*exp2, link, asv);
             fv.append(&mut fv1);
             fv.append(&mut fv2);
             (fv, If(val, Box::new(nexp1), Box::new(nexp2)))
         }
-        Proj(val1, c) => (extract_v(&asv, val1.clone()), Proj(val1, c)),
+        Proj(val1, c) => (extract_v(link, asv, val1.clone()), Proj(val1, c)),
+        Extcall(symbol, vals) => {
+            let mut fv = vec![];
+            for val in &vals {
+                fv.append(&mut extract_v(link, asv, val.clone()));
+            }
+            (fv, Extcall(symbol, vals))
+        }
     }
 }
 
-fn find_fv(normexp: normal::Exp, asv: &mut Vec<String>) -> (Vec<String>, normal::Exp) {
+fn find_fv(
+    normexp: normal::Exp,
+    link: &Linkage,
+    asv: &mut Vec<String>,
+) -> (Vec<String>, normal::Exp) {
     use normal::Exp::*;
 
     match normexp {
-        Compexp(nmce) => {
-            let (fv, _nmce) = sub_find_fv(*nmce, asv);
-            (fv, Compexp(Box::new(_nmce)))
+        Compexp(nmce, span) => {
+            let (fv, _nmce) = sub_find_fv(*nmce, link, asv);
+            (fv, Compexp(Box::new(_nmce), span))
         }
-        Let(id, nmce, nme) => {
+        Let(id, nmce, nme, span) => {
             asv.push(id.clone());
-            let (mut fv, _nmce) = sub_find_fv(*nmce, asv);
-            let (mut fv2, _nme) = find_fv(*nme, asv);
+            let (mut fv, _nmce) = sub_find_fv(*nmce, link, asv);
+            let (mut fv2, _nme) = find_fv(*nme, link, asv);
             fv.append(&mut fv2);
-            (fv, Let(id, Box::new(_nmce), Box::new(_nme)))
+            (fv, Let(id, Box::new(_nmce), Box::new(_nme), span))
         }
-        Loop(id, nmce, nme) => {
+        Loop(id, nmce, nme, span) => {
             asv.push(id.clone());
-            let (mut fv, _nmce) = sub_find_fv(*nmce, asv);
-            let (mut fv2, _nme) = find_fv(*nme, asv);
+            let (mut fv, _nmce) = sub_find_fv(*nmce, link, asv);
+            let (mut fv2, _nme) = find_fv(*nme, link, asv);
             fv.append(&mut fv2);
-            (fv, Loop(id, Box::new(_nmce), Box::new(_nme)))
+            (fv, Loop(id, Box::new(_nmce), Box::new(_nme), span))
         }
-        Letrec(id1, id2, nme1, nme2) => {
+        Letrec(id1, id2, nme1, nme2, span) => {
             asv.push(id1.clone());
             asv.push(id2.clone());
-            let (mut fv, _nme1) = find_fv(*nme1, asv);
-            let (mut fv2, _nme2) = find_fv(*nme2, asv);
+            let (mut fv, _nme1) = find_fv(*nme1, link, asv);
+            let (mut fv2, _nme2) = find_fv(*nme2, link, asv);
             fv.append(&mut fv2);
-            (fv, Letrec(id1, id2, Box::new(_nme1), Box::new(_nme2)))
+            (fv, Letrec(id1, id2, Box::new(_nme1), Box::new(_nme2), span))
         }
-        Recur(val) => (extract_v(&asv, val.clone()), Recur(val)),
+        Recur(val, span) => (extract_v(link, asv, val.clone()), Recur(val, span)),
     }
 }
 
-fn nce2cce(nce: normal::Cexp) -> Cexp {
+fn nce2cce(nce: normal::Cexp) -> CompileResult<Cexp> {
     use normal::Cexp::*;
-    match nce {
+    Ok(match nce {
         Val(val) => Cexp::Val(val),
         Binop(btype, val1, val2) => Cexp::Binop(btype, val1, val2),
         App(val1, val2) => Cexp::App(val1, vec![val2]),
         Tuple(val1, val2) => Cexp::Tuple(vec![val1, val2]),
-        Proj(val, c) => Cexp::Proj(val, c),
+        // slots are counted from 0 from here on, as in the closures
+        // conversion builds, while the source counts from 1
+        Proj(val, c) => Cexp::Proj(val, c - 1),
+        Extcall(symbol, vals) => Cexp::Extcall(symbol, vals),
         If(..) => {
-            panic!("nce2cce error.")
+            return Err(CompileError::internal("nce2cce error."));
         }
+    })
+}
+
+impl Converter<'_> {
+    fn get_fresh_function_var(&mut self, s: &str, fresh_char: char) -> String {
+        let num = self.fresh;
+        self.fresh = num + 1;
+        let mut ss = String::from("$");
+        ss.push(fresh_char);
+        ss.push('_');
+        let numstr = &num.to_string();
+        ss.push_str(s);
+        ss.push_str(numstr);
+        ss
+    }
+    fn convert(
+        &mut self,
+        normexp: normal::Exp,
+        fid: usize,
+        fs: &mut Vec<AsgFun>,
+    ) -> CompileResult<Exp> {
+        use normal::Cexp::*;
+        use normal::Exp::*;
+        Ok(match normexp {
+            Compexp(nmce, span) => match *nmce {
+                If(val, nme1, nme2) => fs[fid].apply()(
+                    Cexp::If(
+                        val,
+                        Box::new(self.convert(*nme1, 0, fs)?),
+                        Box::new(self.convert(*nme2, 0, fs)?),
+                    ),
+                    span,
+                ),
+                App(Value::Var(v), val2) => {
+                    let appv = self.get_fresh_function_var(&v[..], 'r');
+                    Exp::Let(
+                        appv.clone(),
+                        Box::new(Cexp::Proj(Value::Var(v.clone()), 0)),
+                        Box::new(fs[fid].apply()(
+                            Cexp::App(Value::Var(appv), vec![Value::Var(v), val2]),
+                            span,
+                        )),
+                        span,
+                    )
+                }
+                ce => fs[fid].apply()(nce2cce(ce)?, span),
+            },
+            Let(id, nmce, nme, span) => match *nmce {
+                If(val, nme1, nme2) => Exp::Let(
+                    id,
+                    Box::new(Cexp::If(
+                        val,
+                        Box::new(self.convert(*nme1, 0, fs)?),
+                        Box::new(self.convert(*nme2, 0, fs)?),
+                    )),
+                    Box::new(self.convert(*nme, fid, fs)?),
+                    span,
+                ),
+                App(Value::Var(v), val2) => {
+                    let _cme2 = self.convert(*nme, fid, fs)?;
+                    fs.push(AsgFun::new(Box::new(|ce, span| {
+                        Exp::Let(id, Box::new(ce), Box::new(_cme2), span)
+                    })));
+                    self.convert(
+                        Compexp(Box::new(App(Value::Var(v), val2)), span),
+                        fs.len() - 1,
+                        fs,
+                    )?
+                }
+                ce => Exp::Let(
+                    id,
+                    Box::new(nce2cce(ce)?),
+                    Box::new(self.convert(*nme, fid, fs)?),
+                    span,
+                ),
+            },
+            Loop(id, nmce, nme, span) => match *nmce {
+                If(val, nme1, nme2) => Exp::Loop(
+                    id,
+                    Box::new(Cexp::If(
+                        val,
+                        Box::new(self.convert(*nme1, 0, fs)?),
+                        Box::new(self.convert(*nme2, 0, fs)?),
+                    )),
+                    Box::new(self.convert(*nme, fid, fs)?),
+                    span,
+                ),
+                App(Value::Var(v), val2) => {
+                    let _cme2 = self.convert(*nme, fid, fs)?;
+                    fs.push(AsgFun::new(Box::new(|ce, span| {
+                        Exp::Loop(id, Box::new(ce), Box::new(_cme2), span)
+                    })));
+                    self.convert(
+                        Compexp(Box::new(App(Value::Var(v), val2)), span),
+                        fs.len() - 1,
+                        fs,
+                    )?
+                }
+                ce => Exp::Loop(
+                    id,
+                    Box::new(nce2cce(ce)?),
+                    Box::new(self.convert(*nme, fid, fs)?),
+                    span,
+                ),
+            },
+            Letrec(id1, id2, nme1, nme2, span) => {
+                let mut args = vec![id1.clone(), id2.clone()];
+                let (mut fvs, nme1) = find_fv(*nme1, self.link, &mut args);
+                let mut csexp1 = self.convert(nme1, fs.len() - 1, fs)?;
+                for (i, fv) in fvs.iter().enumerate() {
+                    csexp1 = Exp::Let(
+                        fv.clone(),
+                        Box::new(Cexp::Proj(Value::Var(id1.clone()), i as i32 + 1)),
+                        Box::new(csexp1),
+                        span,
+                    );
+                }
+                // a global function captures nothing and has a static closure
+                if self.link.is_global(&id1) {
+                    return Ok(Exp::Letrec(
+                        unit::code_symbol(&id1),
+                        vec![id1, id2],
+                        Box::new(csexp1),
+                        Box::new(self.convert(*nme2, fid, fs)?),
+                        span,
+                    ));
+                }
+                // binders are unique since renaming, so the name of the
+                // function is enough to tell its code apart
+                let ffv = format!("$b_{}", id1);
+                self.closures += 1;
+                fvs.insert(0, ffv.clone());
+                let mut cs_varset = vec![];
+                for v in fvs {
+                    cs_varset.push(Value::Var(v.clone()));
+                }
+                Exp::Letrec(
+                    ffv.clone(),
+                    vec![id1.clone(), id2],
+                    Box::new(csexp1),
+                    Box::new(Exp::Let(
+                        id1,
+                        Box::new(Cexp::Tuple(cs_varset)),
+                        Box::new(self.convert(*nme2, fid, fs)?),
+                        span,
+                    )),
+                    span,
+                )
+            }
+            Recur(val, span) => Exp::Recur(val, span),
+        })
     }
 }
 
-pub fn duplicate_var(nv: String) -> String {
-    let mut s = String::from("@v");
-    s.push_str(&nv[2..]);
-    s
+// the converted program and the number of closures it allocates
+pub fn closure(normexp: normal::Exp, link: &Linkage) -> CompileResult<(Exp, usize)> {
+    let mut fs = vec![AsgFun::new(Box::new(ef))];
+    let mut converter = Converter {
+        link,
+        fresh: 0,
+        closures: 0,
+    };
+    let exp = converter.convert(normexp, 0, &mut fs)?;
+    Ok((exp, converter.closures))
 }
 
-fn convert(normexp: normal::Exp, fid: usize, fs: &mut Vec<AsgFun>) -> Exp {
-    use normal::Cexp::*;
-    use normal::Exp::*;
-    match normexp {
-        Compexp(nmce) => match *nmce {
-            If(val, nme1, nme2) => fs[fid].apply()(Cexp::If(
-                val,
-                Box::new(convert(*nme1, 0, fs)),
-                Box::new(convert(*nme2, 0, fs)),
-            )),
-            App(Value::Var(v), val2) => {
-                let appv = get_fresh_function_var(&v[..], 'r');
-                Exp::Let(
-                    appv.clone(),
-                    Box::new(Cexp::Proj(Value::Var(v.clone()), 0)),
-                    Box::new(fs[fid].apply()(Cexp::App(
-                        Value::Var(appv),
-                        vec![Value::Var(v), val2],
-                    ))),
-                )
-            }
-            ce => fs[fid].apply()(nce2cce(ce)),
-        },
-        Let(id, nmce, nme) => match *nmce {
-            If(val, nme1, nme2) => Exp::Let(
-                id,
-                Box::new(Cexp::If(
-                    val,
-                    Box::new(convert(*nme1, 0, fs)),
-                    Box::new(convert(*nme2, 0, fs)),
-                )),
-                Box::new(convert(*nme, fid, fs)),
+// the JSON form is that of the normal IR, except that functions take a
+// list of parameters and tuples any number of values
+impl Cexp {
+    pub fn to_json(&self) -> Json {
+        use Cexp::*;
+        match self {
+            Val(val) => Json::node("Val", vec![("value", val.to_json())]),
+            Binop(btype, val1, val2) => Json::node(
+                "Binop",
+                vec![
+                    ("op", btype.to_json()),
+                    ("lhs", val1.to_json()),
+                    ("rhs", val2.to_json()),
+                ],
             ),
-            App(Value::Var(v), val2) => {
-                let _cme2 = convert(*nme, fid, fs);
-                fs.push(AsgFun::new(Box::new(|ce| {
-                    Exp::Let(id, Box::new(ce), Box::new(_cme2))
-                })));
-                convert(
-                    Compexp(Box::new(App(Value::Var(v), val2))),
-                    fs.len() - 1,
-                    fs,
-                )
-            }
-            ce => Exp::Let(id, Box::new(nce2cce(ce)), Box::new(convert(*nme, fid, fs))),
-        },
-        Loop(id, nmce, nme) => match *nmce {
-            If(val, nme1, nme2) => Exp::Loop(
-                id,
-                Box::new(Cexp::If(
-                    val,
-                    Box::new(convert(*nme1, 0, fs)),
-                    Box::new(convert(*nme2, 0, fs)),
-                )),
-                Box::new(convert(*nme, fid, fs)),
+            App(val, vals) => Json::node(
+                "App",
+                vec![("fun", val.to_json()), ("args", values_json(vals))],
+            ),
+            If(val, exp1, exp2) => Json::node(
+                "If",
+                vec![
+                    ("cond", val.to_json()),
+                    ("then", exp1.to_json()),
+                    ("else", exp2.to_json()),
+                ],
+            ),
+            Tuple(vals) => Json::node("Tuple", vec![("values", values_json(vals))]),
+            Proj(val, i) => Json::node(
+                "Proj",
+                vec![("tuple", val.to_json()), ("index", Json::int(*i))],
+            ),
+            Extcall(symbol, vals) => Json::node(
+                "Extcall",
+                vec![("symbol", Json::str(symbol)), ("args", values_json(vals))],
             ),
-            App(Value::Var(v), val2) => {
-                let _cme2 = convert(*nme, fid, fs);
-                fs.push(AsgFun::new(Box::new(|ce| {
-                    Exp::Loop(id, Box::new(ce), Box::new(_cme2))
-                })));
-                convert(
-                    Compexp(Box::new(App(Value::Var(v), val2))),
-                    fs.len() - 1,
-                    fs,
-                )
-            }
-            ce => Exp::Loop(id, Box::new(nce2cce(ce)), Box::new(convert(*nme, fid, fs))),
-        },
-        Letrec(id1, id2, nme1, nme2) => {
-            let mut args = vec![id1.clone(), id2.clone()];
-            let (mut fvs, nme1) = find_fv(*nme1, &mut args);
-            let mut csexp1 = convert(nme1, fs.len() - 1, fs);
-            for i in 0..fvs.len() {
-                csexp1 = Exp::Let(
-                    fvs[i].clone(),
-                    Box::new(Cexp::Proj(Value::Var(id1.clone()), i as i32 + 1)),
-                    Box::new(csexp1),
-                );
-            }
-            let ffv = get_fresh_function_var(&id1[..], 'b');
-            fvs.insert(0, ffv.clone());
-            let mut cs_varset = vec![];
-            for v in fvs {
-                cs_varset.push(Value::Var(v.clone()));
-            }
-            Exp::Letrec(
-                ffv.clone(),
-                vec![id1.clone(), id2],
-                Box::new(csexp1),
-                Box::new(Exp::Let(
-                    id1,
-                    Box::new(Cexp::Tuple(cs_varset)),
-                    Box::new(convert(*nme2, fid, fs)),
-                )),
-            )
         }
-        Recur(val) => Exp::Recur(val),
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Cexp::*;
+        let value = |key| Value::from_json(json.field(key)?);
+        let values = |key| values_from_json(json.field(key)?);
+        Ok(match json.kind()? {
+            "Val" => Val(value("value")?),
+            "Binop" => Binop(
+                Bintype::from_json(json.field("op")?)?,
+                value("lhs")?,
+                value("rhs")?,
+            ),
+            "App" => App(value("fun")?, values("args")?),
+            "If" => If(
+                value("cond")?,
+                Box::new(Exp::from_json(json.field("then")?)?),
+                Box::new(Exp::from_json(json.field("else")?)?),
+            ),
+            "Tuple" => Tuple(values("values")?),
+            "Proj" => Proj(value("tuple")?, json.field("index")?.as_int()?),
+            "Extcall" => Extcall(json.field("symbol")?.as_id()?, values("args")?),
+            kind => return Err(json::unknown_kind("closure Cexp", kind)),
+        })
     }
 }
 
-pub fn closure(normexp: normal::Exp) -> Exp {
-    let mut fs = vec![AsgFun::new(Box::new(ef))];
-    convert(normexp, 0, &mut fs)
+impl Exp {
+    pub fn to_json(&self) -> Json {
+        use Exp::*;
+        match self {
+            Compexp(cexp, span) => Json::node(
+                "Compexp",
+                vec![("exp", cexp.to_json()), ("span", Json::span(*span))],
+            ),
+            Let(id, cexp, exp, span) | Loop(id, cexp, exp, span) => Json::node(
+                if let Let(..) = self { "Let" } else { "Loop" },
+                vec![
+                    ("name", Json::str(id)),
+                    ("value", cexp.to_json()),
+                    ("body", exp.to_json()),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Letrec(id, args, exp1, exp2, span) => Json::node(
+                "Letrec",
+                vec![
+                    ("name", Json::str(id)),
+                    ("params", Json::strs(args)),
+                    ("body", exp1.to_json()),
+                    ("cont", exp2.to_json()),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Recur(val, span) => Json::node(
+                "Recur",
+                vec![("value", val.to_json()), ("span", Json::span(*span))],
+            ),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Exp::*;
+        let span = json.span_field()?;
+        let exp =
+            |key| -> CompileResult<Box<Exp>> { Ok(Box::new(Exp::from_json(json.field(key)?)?)) };
+        let cexp =
+            |key| -> CompileResult<Box<Cexp>> { Ok(Box::new(Cexp::from_json(json.field(key)?)?)) };
+        let name = || json.field("name")?.as_id();
+        Ok(match json.kind()? {
+            "Compexp" => Compexp(cexp("exp")?, span),
+            "Let" => Let(name()?, cexp("value")?, exp("body")?, span),
+            "Loop" => Loop(name()?, cexp("value")?, exp("body")?, span),
+            "Letrec" => Letrec(
+                name()?,
+                json.field("params")?.as_ids()?,
+                exp("body")?,
+                exp("cont")?,
+                span,
+            ),
+            "Recur" => Recur(Value::from_json(json.field("value")?)?, span),
+            kind => return Err(json::unknown_kind("closure Exp", kind)),
+        })
+    }
 }
@@ -1,6 +1,16 @@
 use super::vm::*;
 use super::*;
 
+use std::fmt::Write;
+
+// appends a line to the assembly being built; writing to a `String`
+// cannot fail
+macro_rules! asm {
+    ($out: ident, $($arg: tt)*) => {
+        writeln!($out, $($arg)*).unwrap()
+    };
+}
+
 macro_rules! emit_reg {
     ($r: ident) => {
         if $r.byte == 4 {
@@ -11,137 +21,202 @@ macro_rules! emit_reg {
     };
 }
 
-pub fn codegen(program: vm::Program) {
-    print!(".text\n");
-    print!("\t.global _toplevel\n");
+// x16, the intra-procedure-call scratch register, is never allocated
+const SCRATCH: i32 = 16;
+
+// the register moves `moves`, pairs of destination and source, done as if
+// all at once: a move waits until no other one reads its destination, and
+// a cycle is broken by saving one destination in the scratch register
+fn parallel_move(out: &mut String, moves: Vec<(i32, i32)>) {
+    let mut moves: Vec<(i32, i32)> = moves.into_iter().filter(|(dst, src)| dst != src).collect();
+    while !moves.is_empty() {
+        match moves
+            .iter()
+            .position(|&(dst, _)| moves.iter().all(|&(_, src)| src != dst))
+        {
+            Some(i) => {
+                let (dst, src) = moves.remove(i);
+                asm!(out, "\tmov x{}, x{}", dst, src);
+            }
+            None => {
+                let saved = moves[0].0;
+                asm!(out, "\tmov x{}, x{}", SCRATCH, saved);
+                for (_, src) in moves.iter_mut().filter(|(_, src)| *src == saved) {
+                    *src = SCRATCH;
+                }
+            }
+        }
+    }
+}
+
+pub fn codegen(program: vm::Program, link: &Linkage) -> CompileResult<String> {
+    let globals = &link.globals;
+    let mut out = String::new();
+    asm!(out, ".text");
+    if program.decls.iter().any(|decl| decl.funlb == "_toplevel") {
+        asm!(out, "\t.global _toplevel");
+    }
+    for global in globals.iter().filter(|g| g.exported) {
+        asm!(out, "\t.global {}", unit::code_symbol(&global.name));
+        asm!(out, "\t.global {}", unit::closure_symbol(&global.name));
+    }
     for decl in program.decls {
         let mut spofs = 16 * ((decl.vc * 4 + 15) / 16);
-        print!("{}:\n", decl.funlb);
+        asm!(out, "{}:", decl.funlb);
         if decl.haveapp {
             spofs += 16;
-            print!("\tstp, x29, x30, [sp, -{}]!\n", spofs);
-            print!("\tmov, x29, sp\n");
+            asm!(out, "\tstp x29, x30, [sp, #-{}]!", spofs);
+            asm!(out, "\tmov x29, sp");
         } else if spofs > 0 {
-            print!("\tsub sp, sp, #{}\n", spofs);
+            asm!(out, "\tsub sp, sp, #{}", spofs);
         }
-        for instr in decl.instrs {
+        for (instr, _) in decl.instrs {
             use normal::Bintype::*;
             use Instr::*;
             match instr {
                 Move(r, op) => {
                     if let Operand::Intv(v) = op {
-                        print!("\tmov {}, #{}\n", emit_reg!(r), v);
+                        asm!(out, "\tmov {}, #{}", emit_reg!(r), v);
                     } else {
-                        panic!("codegen Move error. {:?}", op);
+                        return Err(CompileError::internal(&format!(
+                            "codegen Move error. {:?}",
+                            op
+                        )));
                     }
                 }
                 Mover(r1, r2) => {
-                    print!("\tmov {}, {}\n", emit_reg!(r1), emit_reg!(r2));
+                    asm!(out, "\tmov {}, {}", emit_reg!(r1), emit_reg!(r2));
                 }
                 Store(ofs, r) => {
-                    print!("\tstr {}, [sp, {}]\n", emit_reg!(r), spofs - 4 * ofs);
+                    asm!(out, "\tstr {}, [sp, {}]", emit_reg!(r), spofs - 4 * ofs);
                 }
                 Load(r, ofs) => {
-                    print!("\tldr {}, [sp, {}]\n", emit_reg!(r), spofs - 4 * ofs);
+                    asm!(out, "\tldr {}, [sp, {}]", emit_reg!(r), spofs - 4 * ofs);
                 }
                 Loadf(r, id) => {
-                    print!("\tadrp {}, {}\n", emit_reg!(r), id);
-                    print!("\tadd {}, {}, :lo12:{}\n", emit_reg!(r), emit_reg!(r), id);
+                    asm!(out, "\tadrp {}, {}", emit_reg!(r), id);
+                    asm!(
+                        out,
+                        "\tadd {}, {}, :lo12:{}",
+                        emit_reg!(r),
+                        emit_reg!(r),
+                        id
+                    );
                 }
                 Argst(ofs, op) => {
                     if let Operand::Param(i) = op {
-                        print!("\tstr x{}, [sp, {}]\n", i, 4 * ofs);
+                        asm!(out, "\tstr x{}, [sp, {}]", i, 4 * ofs);
                     } else {
-                        panic!("codegen Argst error.");
+                        return Err(CompileError::internal("codegen Argst error."));
                     }
                 }
                 Binop(btype, r1, r2) => match btype {
                     Plus => {
-                        print!(
-                            "\tadd {}, {}, {}\n",
+                        asm!(
+                            out,
+                            "\tadd {}, {}, {}",
                             emit_reg!(r1),
                             emit_reg!(r1),
                             emit_reg!(r2)
                         );
                     }
                     Mult => {
-                        print!(
-                            "\tmul {}, {}, {}\n",
+                        asm!(
+                            out,
+                            "\tmul {}, {}, {}",
                             emit_reg!(r1),
                             emit_reg!(r1),
                             emit_reg!(r2)
                         );
                     }
                     Lt => {
-                        print!("\tcmp {}, {}\n", emit_reg!(r1), emit_reg!(r2));
-                        print!("\tcset {}, lt\n", emit_reg!(r1));
-                        print!("\tand {}, {}, 255\n", emit_reg!(r1), emit_reg!(r1));
+                        asm!(out, "\tcmp {}, {}", emit_reg!(r1), emit_reg!(r2));
+                        asm!(out, "\tcset {}, lt", emit_reg!(r1));
+                        asm!(out, "\tand {}, {}, 255", emit_reg!(r1), emit_reg!(r1));
                     }
                     Eq => {
-                        print!("\tcmp {}, {}\n", emit_reg!(r1), emit_reg!(r2));
-                        print!("\tcset {}, eq\n", emit_reg!(r1));
-                        print!("\tand {}, {}, 255\n", emit_reg!(r1), emit_reg!(r1));
+                        asm!(out, "\tcmp {}, {}", emit_reg!(r1), emit_reg!(r2));
+                        asm!(out, "\tcset {}, eq", emit_reg!(r1));
+                        asm!(out, "\tand {}, {}, 255", emit_reg!(r1), emit_reg!(r1));
                     }
                 },
                 Label(lb) => {
-                    print!("{}:\n", lb);
+                    asm!(out, "{}:", lb);
                 }
                 Br(r, lb) => {
-                    print!("\tcmp {}, #1\n", emit_reg!(r));
-                    print!("\tbeq {}\n", lb);
+                    asm!(out, "\tcmp {}, #1", emit_reg!(r));
+                    asm!(out, "\tbeq {}", lb);
                 }
                 Gt(lb) => {
-                    print!("\tb {}\n", lb);
+                    asm!(out, "\tb {}", lb);
                 }
                 Call(r, args) => {
                     for i in 0..args.len() {
-                        print!("\tmov x{}, x{}\n", i, args[i].rm);
+                        asm!(out, "\tmov x{}, x{}", i, args[i].rm);
                     }
-                    print!("\tblr {}\n", emit_reg!(r));
-                    print!("\tmov {}, w0\n", emit_reg!(r));
+                    asm!(out, "\tblr {}", emit_reg!(r));
+                    asm!(out, "\tmov {}, w0", emit_reg!(r));
+                }
+                Callext(r, symbol, args) => {
+                    // AAPCS64: arguments in x0..x7, result in x0
+                    let moves = args.iter().enumerate().map(|(i, arg)| (i as i32, arg.rm));
+                    parallel_move(&mut out, moves.collect());
+                    asm!(out, "\tbl {}", symbol);
+                    asm!(out, "\tmov {}, w0", emit_reg!(r));
                 }
                 Ret(r1, r2) => {
-                    print!("\tmov {}, {}\n", emit_reg!(r1), emit_reg!(r2));
+                    asm!(out, "\tmov {}, {}", emit_reg!(r1), emit_reg!(r2));
                 }
                 Malloc(r, data) => {
-                    print!("\tsub sp, sp, #8\n");
-                    print!("\tstr x0, [sp, 8]\n");
+                    asm!(out, "\tsub sp, sp, #8");
+                    asm!(out, "\tstr x0, [sp, 8]");
                     let mut datasize = 0;
                     for d in &data {
                         datasize += d.byte;
                     }
-                    print!("\tmov w0, {}\n", datasize);
-                    print!("\tbl mymalloc\n");
+                    asm!(out, "\tmov w0, {}", datasize);
+                    asm!(out, "\tbl mymalloc");
                     let mut ofs = 0;
                     for dr in data {
-                        print!("\tstr {}, [x0, {}]\n", emit_reg!(dr), ofs);
+                        asm!(out, "\tstr {}, [x0, {}]", emit_reg!(dr), ofs);
                         ofs += dr.byte;
                     }
-                    print!("\tmov x{}, x0\n", r.rm);
-                    print!("\tldr x0, [sp, 8]\n");
-                    print!("\tadd sp, sp #8\n");
+                    asm!(out, "\tmov x{}, x0", r.rm);
+                    asm!(out, "\tldr x0, [sp, 8]");
+                    asm!(out, "\tadd sp, sp, #8");
                 }
                 Read(mut r, (ofs, byte)) => {
-                    assert_eq!(r.byte, 8);
-                    let wxr = if byte == 4 {
-                        r.byte = 4;
-                        "w"
-                    } else if byte == 8 {
-                        r.byte = 8;
-                        "x"
-                    } else {
-                        "panic_reg"
+                    let wxr = match (r.byte, byte) {
+                        (8, 4) => "w",
+                        (8, 8) => "x",
+                        _ => {
+                            return Err(CompileError::internal(&format!(
+                                "cannot read {} bytes into a {} byte register.",
+                                byte, r.byte
+                            )));
+                        }
                     };
-                    print!("\tldr {}{}, [x{}, {}]\n", wxr, r.rm, r.rm, ofs);
+                    r.byte = byte;
+                    asm!(out, "\tldr {}{}, [x{}, {}]", wxr, r.rm, r.rm, ofs);
                 }
                 Begin(..) | End(..) | Kill(..) | Dummy => {}
             }
         }
         if decl.haveapp {
-            print!("\tldp x29, x30, [sp], {}\n", spofs);
+            asm!(out, "\tldp x29, x30, [sp], {}", spofs);
         } else {
-            print!("\tadd sp, sp, #{}\n", spofs);
+            asm!(out, "\tadd sp, sp, #{}", spofs);
         }
-        print!("\tret\n");
+        asm!(out, "\tret");
+    }
+    // static closures of the functions defined in this unit
+    if globals.iter().any(|g| g.defined) {
+        asm!(out, ".data");
+        asm!(out, "\t.p2align 3");
+    }
+    for global in globals.iter().filter(|g| g.defined) {
+        asm!(out, "{}:", unit::closure_symbol(&global.name));
+        asm!(out, "\t.quad {}", unit::code_symbol(&global.name));
     }
+    Ok(out)
 }
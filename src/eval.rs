@@ -0,0 +1,562 @@
+use super::lexer::*;
+use super::normal::Bintype;
+use super::parser::*;
+use super::*;
+
+use std::rc::Rc;
+
+// a value of the source language; functions close over the syntax tree
+// they are evaluated from
+#[derive(Clone, Debug)]
+pub enum Value<'a> {
+    Int(i32),
+    Bool(bool),
+    Tuple(Rc<(Value<'a>, Value<'a>)>),
+    Closure(Rc<Closure<'a>>),
+}
+
+// `fun param -> body` with the bindings it was built in; a function of
+// `let rec` also binds its own name when it is applied
+#[derive(Debug)]
+pub struct Closure<'a> {
+    pub name: Option<&'a Id>,
+    pub param: &'a Id,
+    pub body: &'a Ast,
+    env: Env<'a, Value<'a>>,
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Tuple(pair) => write!(f, "({}, {})", pair.0, pair.1),
+            Value::Closure(_) => write!(f, "<fun>"),
+        }
+    }
+}
+
+impl Value<'_> {
+    // `==` compares integers and booleans by value, and tuples and
+    // functions by identity like the pointers of compiled code
+    fn same(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(n1), Value::Int(n2)) => n1 == n2,
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::Tuple(t1), Value::Tuple(t2)) => std::ptr::eq(&**t1, &**t2),
+            (Value::Closure(c1), Value::Closure(c2)) => std::ptr::eq(&**c1, &**c2),
+            _ => false,
+        }
+    }
+}
+
+// the bindings in scope, innermost first; closures share the tail they
+// were built in
+#[derive(Debug)]
+struct Env<'a, V>(Option<Rc<(&'a str, V, Env<'a, V>)>>);
+
+impl<V> Clone for Env<'_, V> {
+    fn clone(&self) -> Self {
+        Env(self.0.clone())
+    }
+}
+
+impl<V> Default for Env<'_, V> {
+    fn default() -> Self {
+        Env(None)
+    }
+}
+
+impl<'a, V> Env<'a, V> {
+    fn bind(&self, id: &'a str, value: V) -> Self {
+        Env(Some(Rc::new((id, value, self.clone()))))
+    }
+    fn lookup(&self, id: &str) -> Option<&V> {
+        let mut env = self;
+        while let Some(node) = &env.0 {
+            if node.0 == id {
+                return Some(&node.1);
+            }
+            env = &node.2;
+        }
+        None
+    }
+}
+
+// how an expression ends: with a value, or with `recur` going back to
+// the start of the innermost loop
+enum Outcome<V> {
+    Value(V),
+    Recur(V),
+}
+
+fn internal<T>(message: &str, span: Span) -> CompileResult<T> {
+    Err(CompileError::new(
+        ErrorKind::Internal,
+        message.to_string(),
+        span,
+    ))
+}
+
+struct Evaluator<'a> {
+    link: &'a Linkage,
+}
+
+impl<'a> Evaluator<'a> {
+    // `ast` as an operand, which `check_loops` keeps `recur` out of
+    fn value(&self, ast: &'a Ast, env: &Env<'a, Value<'a>>) -> CompileResult<Value<'a>> {
+        match self.expr(ast, env)? {
+            Outcome::Value(value) => Ok(value),
+            Outcome::Recur(_) => internal("`recur` is not in tail position.", ast.span()),
+        }
+    }
+    fn int(&self, ast: &'a Ast, env: &Env<'a, Value<'a>>) -> CompileResult<i32> {
+        match self.value(ast, env)? {
+            Value::Int(n) => Ok(n),
+            _ => internal("expected an int.", ast.span()),
+        }
+    }
+    fn var(&self, id: &Id, span: Span, env: &Env<'a, Value<'a>>) -> CompileResult<Value<'a>> {
+        if let Some(value) = env.lookup(id) {
+            return Ok(value.clone());
+        }
+        // the front end has bound every other name to one of these
+        let message = match self.link.find_external(id) {
+            Some(external) => format!("cannot call external `{}`.", external.symbol),
+            None if self.link.is_global(id) => {
+                format!("cannot run `{}`, which is defined in another unit.", id)
+            }
+            None => return internal(&format!("`{}` is not bound.", id), span),
+        };
+        Err(CompileError::new(ErrorKind::Eval, message, span))
+    }
+    fn apply(&self, fun: Value<'a>, arg: Value<'a>, span: Span) -> CompileResult<Value<'a>> {
+        let closure = match &fun {
+            Value::Closure(closure) => closure.clone(),
+            _ => return internal("expected a function.", span),
+        };
+        let mut env = closure.env.clone();
+        if let Some(name) = closure.name {
+            env = env.bind(name, fun);
+        }
+        self.value(closure.body, &env.bind(closure.param, arg))
+    }
+    fn expr(&self, ast: &'a Ast, env: &Env<'a, Value<'a>>) -> CompileResult<Outcome<Value<'a>>> {
+        let value = match ast {
+            Ast::Nonaexpr | Ast::Error(_) => return internal("nothing to evaluate.", ast.span()),
+            Ast::ILit(n, _) => Value::Int(*n),
+            Ast::BLit(b, _) => Value::Bool(*b),
+            Ast::Var(id, span) => self.var(id, *span, env)?,
+            // arithmetic wraps around like the 32-bit registers it is
+            // compiled to
+            Ast::Binop(TokenType::Eq, ast1, ast2, _) => {
+                let value1 = self.value(ast1, env)?;
+                Value::Bool(value1.same(&self.value(ast2, env)?))
+            }
+            Ast::Binop(TokenType::Lt, ast1, ast2, _) => {
+                Value::Bool(self.int(ast1, env)? < self.int(ast2, env)?)
+            }
+            Ast::Binop(TokenType::Plus, ast1, ast2, _) => {
+                Value::Int(self.int(ast1, env)?.wrapping_add(self.int(ast2, env)?))
+            }
+            Ast::Binop(TokenType::Mult, ast1, ast2, _) => {
+                Value::Int(self.int(ast1, env)?.wrapping_mul(self.int(ast2, env)?))
+            }
+            Ast::Binop(_, _, _, span) => return internal("unknown operator.", *span),
+            Ast::If(ast1, ast2, ast3, span) => {
+                return match self.value(ast1, env)? {
+                    Value::Bool(true) => self.expr(ast2, env),
+                    Value::Bool(false) => self.expr(ast3, env),
+                    _ => internal("expected a bool.", *span),
+                }
+            }
+            Ast::Fun((id, _), ast1, _) => Value::Closure(Rc::new(Closure {
+                name: None,
+                param: id,
+                body: ast1,
+                env: env.clone(),
+            })),
+            Ast::Let((id, _), ast1, ast2, _) => {
+                let value1 = self.value(ast1, env)?;
+                return self.expr(ast2, &env.bind(id, value1));
+            }
+            Ast::Rec((id1, _), (id2, _), ast1, ast2, _) => {
+                let closure = Value::Closure(Rc::new(Closure {
+                    name: Some(id1),
+                    param: id2,
+                    body: ast1,
+                    env: env.clone(),
+                }));
+                return self.expr(ast2, &env.bind(id1, closure));
+            }
+            Ast::Loop((id, _), ast1, ast2, _) => {
+                let mut value = self.value(ast1, env)?;
+                loop {
+                    match self.expr(ast2, &env.bind(id, value))? {
+                        Outcome::Recur(next) => value = next,
+                        outcome => return Ok(outcome),
+                    }
+                }
+            }
+            Ast::Recur(ast1, _) => return Ok(Outcome::Recur(self.value(ast1, env)?)),
+            Ast::App(ast1, ast2, span) => {
+                let fun = self.value(ast1, env)?;
+                let arg = self.value(ast2, env)?;
+                self.apply(fun, arg, *span)?
+            }
+            Ast::Tuple(ast1, ast2, _) => {
+                let value1 = self.value(ast1, env)?;
+                Value::Tuple(Rc::new((value1, self.value(ast2, env)?)))
+            }
+            Ast::Proj(ast1, i, span) => match (self.value(ast1, env)?, i) {
+                (Value::Tuple(pair), 1) => pair.0.clone(),
+                (Value::Tuple(pair), 2) => pair.1.clone(),
+                _ => return internal("expected a tuple.", *span),
+            },
+        };
+        Ok(Outcome::Value(value))
+    }
+}
+
+// the value of `ast` as the source language defines it, which is what
+// the compiled program must compute; `ast` has been through the checks
+// of `Session::check`, and calling an external or a function of another
+// unit is an error
+pub fn eval<'a>(ast: &'a Ast, link: &'a Linkage) -> CompileResult<Value<'a>> {
+    Evaluator { link }.value(ast, &Env::default())
+}
+
+// a value of the closure-converted and flat IRs, where booleans are 0
+// and 1 and a closure is a tuple of its code and what it captures
+#[derive(Clone, Debug)]
+pub enum Word {
+    Int(i32),
+    Tuple(Rc<Vec<Word>>),
+    // the label of a function's code
+    Code(Id),
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Word::Int(n) => write!(f, "{}", n),
+            Word::Tuple(words) => {
+                write!(f, "(")?;
+                comma_separated(f, words)?;
+                write!(f, ")")
+            }
+            Word::Code(label) => write!(f, "&{}", label),
+        }
+    }
+}
+
+impl Word {
+    fn int(&self, span: Span) -> CompileResult<i32> {
+        match self {
+            Word::Int(n) => Ok(*n),
+            _ => internal(&format!("expected an int, found `{}`.", self), span),
+        }
+    }
+    fn binop(btype: &Bintype, word1: Word, word2: Word, span: Span) -> CompileResult<Word> {
+        Ok(match btype {
+            Bintype::Plus => Word::Int(word1.int(span)?.wrapping_add(word2.int(span)?)),
+            Bintype::Mult => Word::Int(word1.int(span)?.wrapping_mul(word2.int(span)?)),
+            Bintype::Lt => Word::Int((word1.int(span)? < word2.int(span)?) as i32),
+            Bintype::Eq => {
+                let same = match (&word1, &word2) {
+                    (Word::Int(n1), Word::Int(n2)) => n1 == n2,
+                    (Word::Tuple(t1), Word::Tuple(t2)) => Rc::ptr_eq(t1, t2),
+                    (Word::Code(l1), Word::Code(l2)) => l1 == l2,
+                    _ => false,
+                };
+                Word::Int(same as i32)
+            }
+        })
+    }
+    fn proj(&self, i: i32, span: Span) -> CompileResult<Word> {
+        match self {
+            Word::Tuple(words) if 0 <= i && (i as usize) < words.len() => {
+                Ok(words[i as usize].clone())
+            }
+            _ => internal(&format!("`{}` has no slot {}.", self, i), span),
+        }
+    }
+}
+
+fn external_error<T>(symbol: &str, span: Span) -> CompileResult<T> {
+    Err(CompileError::new(
+        ErrorKind::Eval,
+        format!("cannot call external `{}`.", symbol),
+        span,
+    ))
+}
+
+// the static closure of global function `name`, whose code is only there
+// if it is defined in the unit being evaluated
+fn static_closure(name: &str, link: &Linkage, span: Span) -> CompileResult<Word> {
+    match link.find_global(name) {
+        Some(global) if global.defined => {
+            let code = Word::Code(unit::code_symbol(name));
+            Ok(Word::Tuple(Rc::new(vec![code])))
+        }
+        Some(_) => Err(CompileError::new(
+            ErrorKind::Eval,
+            format!("cannot run `{}`, which is defined in another unit.", name),
+            span,
+        )),
+        None => internal(&format!("`{}` is not bound.", name), span),
+    }
+}
+
+// runs `closure::Exp`; a `Letrec` makes its code known by its label, and
+// a function is applied to the closure it is taken from and its argument
+struct ClosureEvaluator<'a> {
+    link: &'a Linkage,
+    codes: HashMap<&'a str, (&'a [Id], &'a closure::Exp)>,
+}
+
+impl<'a> ClosureEvaluator<'a> {
+    fn value(&self, val: &NV, env: &Env<'a, Word>, span: Span) -> CompileResult<Word> {
+        match val {
+            NV::Intv(n) => Ok(Word::Int(*n)),
+            NV::Var(id) => match env.lookup(id) {
+                Some(word) => Ok(word.clone()),
+                None if self.codes.contains_key(id.as_str()) => Ok(Word::Code(id.clone())),
+                None => static_closure(id, self.link, span),
+            },
+        }
+    }
+    fn values(&self, vals: &[NV], env: &Env<'a, Word>, span: Span) -> CompileResult<Vec<Word>> {
+        vals.iter().map(|val| self.value(val, env, span)).collect()
+    }
+    fn apply(&mut self, fun: Word, args: Vec<Word>, span: Span) -> CompileResult<Word> {
+        let (params, body) = match &fun {
+            Word::Code(label) => match self.codes.get(label.as_str()) {
+                Some(&code) => code,
+                None => return internal(&format!("no code has the label `{}`.", label), span),
+            },
+            _ => return internal(&format!("`{}` is not a function label.", fun), span),
+        };
+        if params.len() != args.len() {
+            return internal(
+                &format!("`{}` takes {} arguments.", fun, params.len()),
+                span,
+            );
+        }
+        let mut env = Env::default();
+        for (param, arg) in params.iter().zip(args) {
+            env = env.bind(param, arg);
+        }
+        match self.exp(body, &env)? {
+            Outcome::Value(word) => Ok(word),
+            Outcome::Recur(_) => internal("`recur` is not in a loop.", body.span()),
+        }
+    }
+    fn cexp(
+        &mut self,
+        cexp: &'a closure::Cexp,
+        env: &Env<'a, Word>,
+        span: Span,
+    ) -> CompileResult<Outcome<Word>> {
+        use closure::Cexp::*;
+        let word = match cexp {
+            Val(val) => self.value(val, env, span)?,
+            Binop(btype, val1, val2) => {
+                let word1 = self.value(val1, env, span)?;
+                Word::binop(btype, word1, self.value(val2, env, span)?, span)?
+            }
+            App(val, vals) => {
+                let fun = self.value(val, env, span)?;
+                let args = self.values(vals, env, span)?;
+                self.apply(fun, args, span)?
+            }
+            If(val, exp1, exp2) => {
+                return if self.value(val, env, span)?.int(span)? != 0 {
+                    self.exp(exp1, env)
+                } else {
+                    self.exp(exp2, env)
+                }
+            }
+            Tuple(vals) => Word::Tuple(Rc::new(self.values(vals, env, span)?)),
+            Proj(val, i) => self.value(val, env, span)?.proj(*i, span)?,
+            Extcall(symbol, _) => return external_error(symbol, span),
+        };
+        Ok(Outcome::Value(word))
+    }
+    fn exp(&mut self, exp: &'a closure::Exp, env: &Env<'a, Word>) -> CompileResult<Outcome<Word>> {
+        use closure::Exp::*;
+        match exp {
+            Compexp(cexp, span) => self.cexp(cexp, env, *span),
+            // a `recur` in a bound `if` jumps to the enclosing loop
+            Let(id, cexp, exp, span) => match self.cexp(cexp, env, *span)? {
+                Outcome::Value(word) => self.exp(exp, &env.bind(id, word)),
+                recur => Ok(recur),
+            },
+            Loop(id, cexp, exp, span) => {
+                let mut word = match self.cexp(cexp, env, *span)? {
+                    Outcome::Value(word) => word,
+                    recur => return Ok(recur),
+                };
+                loop {
+                    match self.exp(exp, &env.bind(id, word))? {
+                        Outcome::Recur(next) => word = next,
+                        outcome => return Ok(outcome),
+                    }
+                }
+            }
+            Letrec(label, params, body, exp, _) => {
+                self.codes.insert(label, (params, body));
+                self.exp(exp, env)
+            }
+            Recur(val, span) => Ok(Outcome::Recur(self.value(val, env, *span)?)),
+        }
+    }
+}
+
+// the value `exp` computes; code runs with nothing but its parameters
+// in scope, so a variable that conversion failed to capture is an
+// internal error
+pub fn eval_closure(exp: &closure::Exp, link: &Linkage) -> CompileResult<Word> {
+    let mut evaluator = ClosureEvaluator {
+        link,
+        codes: HashMap::new(),
+    };
+    match evaluator.exp(exp, &Env::default())? {
+        Outcome::Value(word) => Ok(word),
+        Outcome::Recur(_) => internal("`recur` is not in a loop.", exp.span()),
+    }
+}
+
+// runs `flat::Program` from `_toplevel`; `&f` is the code of `Recdecl`
+// `f`, or the static closure of a global function
+struct FlatEvaluator<'a> {
+    link: &'a Linkage,
+    recs: HashMap<&'a str, &'a flat::Recdecl>,
+}
+
+impl<'a> FlatEvaluator<'a> {
+    fn value(&self, val: &FV, env: &Env<'a, Word>, span: Span) -> CompileResult<Word> {
+        match val {
+            FV::Intv(n) => Ok(Word::Int(*n)),
+            FV::Var(id) => match env.lookup(id) {
+                Some(word) => Ok(word.clone()),
+                None => internal(&format!("`{}` is not bound.", id), span),
+            },
+            FV::Fun(label) if self.recs.contains_key(label.as_str()) => {
+                Ok(Word::Code(label.clone()))
+            }
+            FV::Fun(label) => {
+                let global = self
+                    .link
+                    .globals
+                    .iter()
+                    .find(|global| unit::closure_symbol(&global.name) == *label);
+                match global {
+                    Some(global) => static_closure(&global.name, self.link, span),
+                    None => internal(&format!("no code has the label `{}`.", label), span),
+                }
+            }
+        }
+    }
+    fn values(&self, vals: &[FV], env: &Env<'a, Word>, span: Span) -> CompileResult<Vec<Word>> {
+        vals.iter().map(|val| self.value(val, env, span)).collect()
+    }
+    fn apply(&self, fun: Word, args: Vec<Word>, span: Span) -> CompileResult<Word> {
+        let rec = match &fun {
+            Word::Code(label) => match self.recs.get(label.as_str()) {
+                Some(&rec) => rec,
+                None => return internal(&format!("no code has the label `{}`.", label), span),
+            },
+            _ => return internal(&format!("`{}` is not a function label.", fun), span),
+        };
+        let flat::Recdecl(_, params, body, _) = rec;
+        if params.len() != args.len() {
+            return internal(
+                &format!("`{}` takes {} arguments.", fun, params.len()),
+                span,
+            );
+        }
+        let mut env = Env::default();
+        for (param, arg) in params.iter().zip(args) {
+            env = env.bind(param, arg);
+        }
+        match self.exp(body, &env)? {
+            Outcome::Value(word) => Ok(word),
+            Outcome::Recur(_) => internal("`recur` is not in a loop.", body.span()),
+        }
+    }
+    fn cexp(
+        &self,
+        cexp: &'a flat::Cexp,
+        env: &Env<'a, Word>,
+        span: Span,
+    ) -> CompileResult<Outcome<Word>> {
+        use flat::Cexp::*;
+        let word = match cexp {
+            Val(val) => self.value(val, env, span)?,
+            Binop(btype, val1, val2) => {
+                let word1 = self.value(val1, env, span)?;
+                Word::binop(btype, word1, self.value(val2, env, span)?, span)?
+            }
+            App(val, vals) => {
+                let fun = self.value(val, env, span)?;
+                self.apply(fun, self.values(vals, env, span)?, span)?
+            }
+            If(val, exp1, exp2) => {
+                return if self.value(val, env, span)?.int(span)? != 0 {
+                    self.exp(exp1, env)
+                } else {
+                    self.exp(exp2, env)
+                }
+            }
+            Tuple(vals) => Word::Tuple(Rc::new(self.values(vals, env, span)?)),
+            Proj(val, i) => self.value(val, env, span)?.proj(*i, span)?,
+            Extcall(symbol, _) => return external_error(symbol, span),
+        };
+        Ok(Outcome::Value(word))
+    }
+    fn exp(&self, exp: &'a flat::Exp, env: &Env<'a, Word>) -> CompileResult<Outcome<Word>> {
+        use flat::Exp::*;
+        match exp {
+            Compexp(cexp, span) => self.cexp(cexp, env, *span),
+            // a `recur` in a bound `if` jumps to the enclosing loop
+            Let(id, cexp, exp, span) => match self.cexp(cexp, env, *span)? {
+                Outcome::Value(word) => self.exp(exp, &env.bind(id, word)),
+                recur => Ok(recur),
+            },
+            Loop(id, cexp, exp, span) => {
+                let mut word = match self.cexp(cexp, env, *span)? {
+                    Outcome::Value(word) => word,
+                    recur => return Ok(recur),
+                };
+                loop {
+                    match self.exp(exp, &env.bind(id, word))? {
+                        Outcome::Recur(next) => word = next,
+                        outcome => return Ok(outcome),
+                    }
+                }
+            }
+            Recur(val, span) => Ok(Outcome::Recur(self.value(val, env, *span)?)),
+        }
+    }
+}
+
+// the value `_toplevel` of `program` returns
+pub fn eval_flat(program: &flat::Program, link: &Linkage) -> CompileResult<Word> {
+    let evaluator = FlatEvaluator {
+        link,
+        recs: program
+            .recs
+            .iter()
+            .map(|rec| (rec.0.as_str(), rec))
+            .collect(),
+    };
+    let toplevel = Word::Code(String::from("_toplevel"));
+    if !evaluator.recs.contains_key("_toplevel") {
+        return Err(CompileError::unlocated(
+            ErrorKind::Eval,
+            String::from("a unit has no `_toplevel` to run."),
+        ));
+    }
+    evaluator.apply(toplevel, vec![], Span::default())
+}
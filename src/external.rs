@@ -0,0 +1,158 @@
+use super::parser::*;
+use super::typing::Type;
+use super::*;
+
+// a C function declared by `external name : type = "symbol"`
+#[derive(Clone, Debug)]
+pub struct External {
+    pub name: Id,
+    pub ty: Type,
+    pub symbol: String,
+}
+
+impl External {
+    pub fn arity(&self) -> usize {
+        let mut ty = &self.ty;
+        let mut n = 0;
+        while let Type::Fun(_, ret) = ty {
+            n += 1;
+            ty = ret;
+        }
+        n
+    }
+}
+
+// arguments are passed in x0..x7
+const MAX_ARGS: usize = 8;
+
+fn scalar(ty: &Type) -> bool {
+    matches!(ty, Type::Int | Type::Bool)
+}
+
+impl Linkage {
+    // registers an external under the internal name `name`, which cannot
+    // collide with a source identifier
+    pub fn add_external(
+        &mut self,
+        name: &str,
+        ty: Type,
+        symbol: String,
+        span: Span,
+    ) -> CompileResult<()> {
+        let external = External {
+            name: name.to_string(),
+            ty,
+            symbol,
+        };
+        let mut ty = &external.ty;
+        while let Type::Fun(arg, ret) = ty {
+            if !scalar(arg) {
+                return Err(CompileError::new(
+                    ErrorKind::Type,
+                    String::from("external arguments must be int or bool."),
+                    span,
+                ));
+            }
+            ty = ret;
+        }
+        if !scalar(ty) {
+            return Err(CompileError::new(
+                ErrorKind::Type,
+                String::from("external results must be int or bool."),
+                span,
+            ));
+        }
+        if external.arity() == 0 {
+            return Err(CompileError::new(
+                ErrorKind::Type,
+                String::from("an external must be a function."),
+                span,
+            ));
+        }
+        if external.arity() > MAX_ARGS {
+            return Err(CompileError::new(
+                ErrorKind::Type,
+                format!("an external takes at most {} arguments.", MAX_ARGS),
+                span,
+            ));
+        }
+        self.externals.push(external);
+        Ok(())
+    }
+
+    pub fn find_external(&self, name: &str) -> Option<&External> {
+        self.externals.iter().find(|e| e.name == name)
+    }
+}
+
+// the external called by `ast` with its arguments, if `ast` is an
+// application whose head is an external
+pub fn external_call<'a>(ast: &'a Ast, link: &'a Linkage) -> Option<(&'a External, Vec<&'a Ast>)> {
+    let mut args = vec![];
+    let mut head = ast;
+    while let Ast::App(ast1, ast2, _) = head {
+        args.push(&**ast2);
+        head = ast1;
+    }
+    if let Ast::Var(id, _) = head {
+        let external = link.find_external(id)?;
+        args.reverse();
+        return Some((external, args));
+    }
+    None
+}
+
+// externals are called directly, so every use must pass all arguments
+pub fn check_calls(ast: &Ast, link: &Linkage) -> CompileResult<()> {
+    if let Some((external, args)) = external_call(ast, link) {
+        if args.len() != external.arity() {
+            return Err(CompileError::new(
+                ErrorKind::Type,
+                format!(
+                    "external `{}` must be applied to {} arguments.",
+                    external.name.rsplit('.').next().unwrap(),
+                    external.arity()
+                ),
+                ast.span(),
+            ));
+        }
+        for arg in args {
+            check_calls(arg, link)?;
+        }
+        return Ok(());
+    }
+    match ast {
+        Ast::Var(id, span) => {
+            if let Some(external) = link.find_external(id) {
+                return Err(CompileError::new(
+                    ErrorKind::Type,
+                    format!(
+                        "external `{}` must be applied to {} arguments.",
+                        external.name.rsplit('.').next().unwrap(),
+                        external.arity()
+                    ),
+                    *span,
+                ));
+            }
+            Ok(())
+        }
+        Ast::Nonaexpr | Ast::Error(..) | Ast::ILit(..) | Ast::BLit(..) => Ok(()),
+        Ast::Binop(_, ast1, ast2, _)
+        | Ast::App(ast1, ast2, _)
+        | Ast::Tuple(ast1, ast2, _)
+        | Ast::Let(_, ast1, ast2, _)
+        | Ast::Rec(_, _, ast1, ast2, _)
+        | Ast::Loop(_, ast1, ast2, _) => {
+            check_calls(ast1, link)?;
+            check_calls(ast2, link)
+        }
+        Ast::If(ast1, ast2, ast3, _) => {
+            check_calls(ast1, link)?;
+            check_calls(ast2, link)?;
+            check_calls(ast3, link)
+        }
+        Ast::Fun(_, ast1, _) | Ast::Recur(ast1, _) | Ast::Proj(ast1, _, _) => {
+            check_calls(ast1, link)
+        }
+    }
+}
@@ -1,39 +1,27 @@
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
-
 use super::normal::Bintype;
+use super::reader::Reader;
 use super::*;
 
-pub static PROG: Lazy<Mutex<Program>> = Lazy::new(|| Mutex::new(Program::new()));
+use std::fmt;
 
 #[derive(Clone, Debug)]
-pub struct Recdecl(pub Id, pub Vec<Id>, pub Box<Exp>);
+pub struct Recdecl(pub Id, pub Vec<Id>, pub Box<Exp>, pub Span);
 
 impl Recdecl {
-    fn new(id: Id, args: Vec<Id>, exp: Exp) -> Self {
-        Self(id, args, Box::new(exp))
-    }
-    pub fn program_display(self) {
-        let funid = self.0;
-        let args = self.1;
-        let body = *self.2;
-        print!("let rec {} ", funid);
-        print!("(");
-        for i in 0..args.len() {
-            print!("{}", args[i]);
-            if i + 1 == args.len() {
-                break;
-            }
-            print!(", ");
-        }
-        print!(") = ");
-        match body {
-            Exp::Let(..) | Exp::Loop(..) => {
-                print!("\n");
-            }
-            _ => {}
+    fn new(id: Id, args: Vec<Id>, exp: Exp, span: Span) -> Self {
+        Self(id, args, Box::new(exp), span)
+    }
+}
+
+impl fmt::Display for Recdecl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "let rec {} (", self.0)?;
+        comma_separated(f, &self.1)?;
+        write!(f, ") = ")?;
+        if let Exp::Let(..) | Exp::Loop(..) = *self.2 {
+            writeln!(f)?;
         }
-        body.program_display();
+        write!(f, "{}", self.2)
     }
 }
 
@@ -49,18 +37,17 @@ impl Program {
     fn add(&mut self, decl: Recdecl) {
         self.recs.push(decl)
     }
-    pub fn program_display(self) {
-        let mut recs = self.recs;
-        recs.reverse();
-        loop {
-            if let Some(decl) = recs.pop() {
-                decl.program_display();
-            }
-            if recs.is_empty() {
-                break;
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, decl) in self.recs.iter().enumerate() {
+            if i > 0 {
+                writeln!(f, " in")?;
             }
-            print!(" in\n");
+            write!(f, "{}", decl)?;
         }
+        Ok(())
     }
 }
 
@@ -71,6 +58,17 @@ pub enum Value {
     Intv(i32),
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Var(id) => write!(f, "{}", id),
+            // a function label rather than a variable holding one
+            Value::Fun(id) => write!(f, "&{}", id),
+            Value::Intv(v) => write!(f, "{}", v),
+        }
+    }
+}
+
 impl Value {
     pub fn nval2fval(nval: &NV, valf: bool) -> Value {
         use normal::Value::*;
@@ -95,57 +93,31 @@ pub enum Cexp {
     If(Value, Box<Exp>, Box<Exp>),
     Tuple(Vec<Value>),
     Proj(Value, i32),
+    Extcall(String, Vec<Value>),
 }
 
-impl Cexp {
-    pub fn program_display(self) {
+impl fmt::Display for Cexp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Cexp::*;
         match self {
-            Val(Value::Var(id)) | Val(Value::Fun(id)) => {
-                print!("{}", id);
-            }
-            Val(Value::Intv(v)) => {
-                print!("{}", v);
-            }
-            Binop(tty, val1, val2) => {
-                Val(val1).program_display();
-                print!("{}", tty.bintype_signal());
-                Val(val2).program_display();
+            Val(val) => write!(f, "{}", val),
+            Binop(tty, val1, val2) => write!(f, "{}{}{}", val1, tty.bintype_signal(), val2),
+            App(val, vals) => {
+                write!(f, "{} (", val)?;
+                comma_separated(f, vals)?;
+                write!(f, ")")
             }
-            App(val1, mut valls) => {
-                Val(val1).program_display();
-                print!(" (");
-                for i in 0..valls.len() {
-                    Val(std::mem::replace(&mut valls[i], Value::Intv(-1))).program_display();
-                    if i + 1 == valls.len() {
-                        break;
-                    }
-                    print!(", ");
-                }
-                print!(")")
-            }
-            If(val, exp1, exp2) => {
-                print!("if ");
-                Val(val).program_display();
-                print!(" then ");
-                exp1.program_display();
-                print!(" else ");
-                exp2.program_display();
-            }
-            Tuple(mut valls) => {
-                print!(" (");
-                for i in 0..valls.len() {
-                    Val(std::mem::replace(&mut valls[i], Value::Intv(-1))).program_display();
-                    if i + 1 == valls.len() {
-                        break;
-                    }
-                    print!(", ");
-                }
-                print!(")")
+            If(val, exp1, exp2) => write!(f, "if {} then {} else {}", val, exp1, exp2),
+            Tuple(vals) => {
+                write!(f, "(")?;
+                comma_separated(f, vals)?;
+                write!(f, ")")
             }
-            Proj(val, i) => {
-                Val(val).program_display();
-                print!(".{}", i);
+            Proj(val, i) => write!(f, "{}.{}", val, i),
+            Extcall(symbol, vals) => {
+                write!(f, "extern {} (", symbol)?;
+                comma_separated(f, vals)?;
+                write!(f, ")")
             }
         }
     }
@@ -153,125 +125,395 @@ impl Cexp {
 
 #[derive(Clone, Debug)]
 pub enum Exp {
-    Compexp(Box<Cexp>),
-    Let(Id, Box<Cexp>, Box<Exp>),
-    Loop(Id, Box<Cexp>, Box<Exp>),
-    Recur(Value),
+    Compexp(Box<Cexp>, Span),
+    Let(Id, Box<Cexp>, Box<Exp>, Span),
+    Loop(Id, Box<Cexp>, Box<Exp>, Span),
+    Recur(Value, Span),
 }
 
 impl Exp {
-    pub fn program_display(self) {
+    pub fn span(&self) -> Span {
+        match self {
+            Exp::Compexp(_, span)
+            | Exp::Let(_, _, _, span)
+            | Exp::Loop(_, _, _, span)
+            | Exp::Recur(_, span) => *span,
+        }
+    }
+}
+
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Exp::*;
         match self {
-            Compexp(cexp) => {
-                cexp.program_display();
-            }
-            Let(id, cexp, exp) => {
-                print!("let {} = ", id);
-                cexp.program_display();
-                print!(" in\n");
-                exp.program_display();
-            }
-            Loop(id, cexp, exp) => {
-                print!("loop {} = ", id);
-                cexp.program_display();
-                print!(" in\n");
-                exp.program_display();
-            }
-            Recur(val) => {
-                print!("recur ");
-                Cexp::Val(val).program_display();
-            }
+            Compexp(cexp, _) => write!(f, "{}", cexp),
+            Let(id, cexp, exp, _) => write!(f, "let {} = {} in\n{}", id, cexp, exp),
+            Loop(id, cexp, exp, _) => write!(f, "loop {} = {} in\n{}", id, cexp, exp),
+            Recur(val, _) => write!(f, "recur {}", val),
         }
     }
 }
 
-fn cce2fce(ccexp: closure::Cexp, env: &Env<NV, Value>) -> Cexp {
+fn cce2fce(ccexp: closure::Cexp, env: &Env<NV, Value>) -> CompileResult<Cexp> {
     use closure::Cexp::*;
-    match ccexp {
-        Val(val) => Cexp::Val(env.efind(&val)),
-        Binop(btype, val1, val2) => Cexp::Binop(btype, env.efind(&val1), env.efind(&val2)),
-        App(val1, vals) => {
-            let mut fvals = vec![];
-            for val in vals {
-                fvals.push(env.efind(&val));
-            }
-            Cexp::App(env.efind(&val1), fvals)
-        }
-        Tuple(vals) => {
-            let mut fvals = vec![];
-            for val in vals {
-                fvals.push(env.efind(&val));
-            }
-            Cexp::Tuple(fvals)
-        }
-        Proj(val, c) => Cexp::Proj(env.efind(&val), c),
+    let fvals = |vals: Vec<NV>| -> CompileResult<Vec<Value>> {
+        vals.iter().map(|val| env.efind(val)).collect()
+    };
+    Ok(match ccexp {
+        Val(val) => Cexp::Val(env.efind(&val)?),
+        Binop(btype, val1, val2) => Cexp::Binop(btype, env.efind(&val1)?, env.efind(&val2)?),
+        App(val1, vals) => Cexp::App(env.efind(&val1)?, fvals(vals)?),
+        Tuple(vals) => Cexp::Tuple(fvals(vals)?),
+        Proj(val, c) => Cexp::Proj(env.efind(&val)?, c),
+        Extcall(symbol, vals) => Cexp::Extcall(symbol, fvals(vals)?),
         If(..) => {
-            panic!("cce2fce error.")
+            return Err(CompileError::internal("cce2fce error."));
         }
-    }
+    })
 }
 
-fn sub_flatten(ccexp: closure::Cexp, env: &mut Env<NV, Value>) -> Cexp {
+fn sub_flatten(
+    ccexp: closure::Cexp,
+    env: &mut Env<NV, Value>,
+    prog: &mut Program,
+) -> CompileResult<Cexp> {
     use closure::Cexp::*;
     match ccexp {
-        If(val, clexp1, clexp2) => Cexp::If(
-            env.efind(&val),
-            Box::new(flatten(*clexp1, env)),
-            Box::new(flatten(*clexp2, env)),
-        ),
+        If(val, clexp1, clexp2) => Ok(Cexp::If(
+            env.efind(&val)?,
+            Box::new(flatten(*clexp1, env, prog)?),
+            Box::new(flatten(*clexp2, env, prog)?),
+        )),
         _ => cce2fce(ccexp, env),
     }
 }
 
-fn flatten(clexp: closure::Exp, env: &mut Env<NV, Value>) -> Exp {
+// functions are lifted out into `prog` as they are met
+fn flatten(
+    clexp: closure::Exp,
+    env: &mut Env<NV, Value>,
+    prog: &mut Program,
+) -> CompileResult<Exp> {
     use closure::Exp::*;
-    match clexp {
-        Compexp(ccexp) => Exp::Compexp(Box::new(sub_flatten(*ccexp, env))),
-        Let(id, ccexp, clexp) => {
+    Ok(match clexp {
+        Compexp(ccexp, span) => Exp::Compexp(Box::new(sub_flatten(*ccexp, env, prog)?), span),
+        Let(id, ccexp, clexp, span) => {
             env.inc();
-            let fcexp = sub_flatten(*ccexp, env);
+            let fcexp = sub_flatten(*ccexp, env, prog);
             env.dec();
             let nvalue = NV::Var(id.clone());
             let value = Value::nval2fval(&nvalue, true);
             env.addval(nvalue, value);
-            Exp::Let(id, Box::new(fcexp), Box::new(flatten(*clexp, env)))
+            Exp::Let(
+                id,
+                Box::new(fcexp?),
+                Box::new(flatten(*clexp, env, prog)?),
+                span,
+            )
         }
-        Loop(id, ccexp, clexp) => {
+        Loop(id, ccexp, clexp, span) => {
             env.inc();
-            let fcexp = sub_flatten(*ccexp, env);
+            let fcexp = sub_flatten(*ccexp, env, prog);
             env.dec();
             let nvalue = NV::Var(id.clone());
             let value = Value::nval2fval(&nvalue, true);
             env.addval(nvalue, value);
-            Exp::Loop(id, Box::new(fcexp), Box::new(flatten(*clexp, env)))
+            Exp::Loop(
+                id,
+                Box::new(fcexp?),
+                Box::new(flatten(*clexp, env, prog)?),
+                span,
+            )
         }
-        Letrec(id1, args, clexp1, clexp2) => {
+        Letrec(id1, args, clexp1, clexp2, span) => {
             env.inc();
             for arg in &args {
                 let nvalue = NV::Var(arg.clone());
                 let value = Value::nval2fval(&nvalue, true);
                 env.addval(nvalue, value);
             }
-            let fclexp1 = flatten(*clexp1, env);
+            let fclexp1 = flatten(*clexp1, env, prog);
             env.dec();
-            PROG.lock()
-                .unwrap()
-                .add(Recdecl::new(id1.clone(), args, fclexp1));
+            prog.add(Recdecl::new(id1.clone(), args, fclexp1?, span));
             let nvalue = NV::Var(id1);
             let value = Value::nval2fval(&nvalue, false);
             env.addval(nvalue, value);
-            flatten(*clexp2, env)
+            flatten(*clexp2, env, prog)?
         }
-        Recur(val) => Exp::Recur(env.efind(&val)),
-    }
+        Recur(val, span) => Exp::Recur(env.efind(&val)?, span),
+    })
 }
 
-pub fn flat(clexp: closure::Exp) -> Program {
+pub fn flat(clexp: closure::Exp, link: &Linkage) -> CompileResult<Program> {
     let mut env = Env::new();
-    let toplevel = flatten(clexp, &mut env);
-    PROG.lock()
-        .unwrap()
-        .add(Recdecl::new(String::from("_toplevel"), vec![], toplevel));
-    std::mem::replace(&mut PROG.lock().unwrap(), Program::new())
+    for global in &link.globals {
+        let symbol = unit::closure_symbol(&global.name);
+        env.addval(NV::Var(global.name.clone()), Value::Fun(symbol));
+    }
+    let mut program = Program::new();
+    let toplevel = flatten(clexp, &mut env, &mut program)?;
+    program.add(Recdecl::new(
+        String::from("_toplevel"),
+        vec![],
+        toplevel,
+        Span::default(),
+    ));
+    Ok(program)
+}
+
+fn read_value(reader: &mut Reader) -> CompileResult<Value> {
+    if reader.at_int() {
+        Ok(Value::Intv(reader.int()?))
+    } else if reader.eat("&") {
+        Ok(Value::Fun(reader.name()?))
+    } else {
+        Ok(Value::Var(reader.name()?))
+    }
+}
+
+fn read_cexp(reader: &mut Reader) -> CompileResult<Cexp> {
+    if reader.keyword("if") {
+        let val = read_value(reader)?;
+        reader.expect_keyword("then")?;
+        let exp1 = read_exp(reader)?;
+        reader.expect_keyword("else")?;
+        let exp2 = read_exp(reader)?;
+        return Ok(Cexp::If(val, Box::new(exp1), Box::new(exp2)));
+    }
+    if reader.keyword("extern") {
+        let symbol = reader.name()?;
+        return Ok(Cexp::Extcall(symbol, reader.list(read_value)?));
+    }
+    reader.skip();
+    if reader.text[reader.pos..].starts_with('(') {
+        return Ok(Cexp::Tuple(reader.list(read_value)?));
+    }
+    let val = read_value(reader)?;
+    // what follows on the same line tells the forms apart
+    let pos = reader.pos;
+    if reader.eat("(") {
+        reader.pos = pos;
+        return Ok(Cexp::App(val, reader.list(read_value)?));
+    }
+    if reader.eat(".") {
+        return Ok(Cexp::Proj(val, reader.int()?));
+    }
+    if let Some(btype) = reader.bintype() {
+        return Ok(Cexp::Binop(btype, val, read_value(reader)?));
+    }
+    Ok(Cexp::Val(val))
+}
+
+fn read_exp(reader: &mut Reader) -> CompileResult<Exp> {
+    let looping = reader.keyword("loop");
+    if looping || reader.keyword("let") {
+        let id = reader.name()?;
+        reader.expect("=")?;
+        reader.skip();
+        let start = reader.pos;
+        let cexp = read_cexp(reader)?;
+        let span = reader.span_from(start);
+        reader.expect_keyword("in")?;
+        let exp = read_exp(reader)?;
+        return Ok(if looping {
+            Exp::Loop(id, Box::new(cexp), Box::new(exp), span)
+        } else {
+            Exp::Let(id, Box::new(cexp), Box::new(exp), span)
+        });
+    }
+    reader.skip();
+    let start = reader.pos;
+    if reader.keyword("recur") {
+        let val = read_value(reader)?;
+        return Ok(Exp::Recur(val, reader.span_from(start)));
+    }
+    let cexp = read_cexp(reader)?;
+    Ok(Exp::Compexp(Box::new(cexp), reader.span_from(start)))
+}
+
+// the program `text` prints, in the syntax of `Display`; spans point
+// into `text`
+pub fn read_program(text: &str) -> CompileResult<Program> {
+    let mut reader = Reader::new(text);
+    let mut program = Program::new();
+    if reader.eof() {
+        return Ok(program);
+    }
+    loop {
+        let start = reader.pos;
+        reader.expect_keyword("let")?;
+        reader.expect_keyword("rec")?;
+        let id = reader.name()?;
+        let args = reader.list(Reader::name)?;
+        reader.expect("=")?;
+        let exp = read_exp(&mut reader)?;
+        program.add(Recdecl::new(id, args, exp, reader.span_from(start)));
+        if !reader.keyword("in") {
+            break;
+        }
+        reader.skip();
+    }
+    if !reader.eof() {
+        return Err(reader.error("expected `in` or end of input."));
+    }
+    Ok(program)
+}
+
+// the JSON form is that of the closure IR, with a function label written
+// as `{"fun": label}` to tell it from a variable
+impl Value {
+    pub fn to_json(&self) -> Json {
+        match self {
+            Value::Var(id) => Json::str(id),
+            Value::Fun(id) => Json::object(vec![("fun", Json::str(id))]),
+            Value::Intv(v) => Json::int(*v),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        match json {
+            Json::Num(_) => Ok(Value::Intv(json.as_int()?)),
+            Json::Object(_) => Ok(Value::Fun(json.field("fun")?.as_id()?)),
+            _ => Ok(Value::Var(json.as_id()?)),
+        }
+    }
+}
+
+fn values_json(vals: &[Value]) -> Json {
+    Json::Array(vals.iter().map(Value::to_json).collect())
+}
+
+fn values_from_json(json: &Json) -> CompileResult<Vec<Value>> {
+    json.as_array()?.iter().map(Value::from_json).collect()
+}
+
+impl Cexp {
+    pub fn to_json(&self) -> Json {
+        use Cexp::*;
+        match self {
+            Val(val) => Json::node("Val", vec![("value", val.to_json())]),
+            Binop(btype, val1, val2) => Json::node(
+                "Binop",
+                vec![
+                    ("op", btype.to_json()),
+                    ("lhs", val1.to_json()),
+                    ("rhs", val2.to_json()),
+                ],
+            ),
+            App(val, vals) => Json::node(
+                "App",
+                vec![("fun", val.to_json()), ("args", values_json(vals))],
+            ),
+            If(val, exp1, exp2) => Json::node(
+                "If",
+                vec![
+                    ("cond", val.to_json()),
+                    ("then", exp1.to_json()),
+                    ("else", exp2.to_json()),
+                ],
+            ),
+            Tuple(vals) => Json::node("Tuple", vec![("values", values_json(vals))]),
+            Proj(val, i) => Json::node(
+                "Proj",
+                vec![("tuple", val.to_json()), ("index", Json::int(*i))],
+            ),
+            Extcall(symbol, vals) => Json::node(
+                "Extcall",
+                vec![("symbol", Json::str(symbol)), ("args", values_json(vals))],
+            ),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Cexp::*;
+        let value = |key| Value::from_json(json.field(key)?);
+        let values = |key| values_from_json(json.field(key)?);
+        Ok(match json.kind()? {
+            "Val" => Val(value("value")?),
+            "Binop" => Binop(
+                Bintype::from_json(json.field("op")?)?,
+                value("lhs")?,
+                value("rhs")?,
+            ),
+            "App" => App(value("fun")?, values("args")?),
+            "If" => If(
+                value("cond")?,
+                Box::new(Exp::from_json(json.field("then")?)?),
+                Box::new(Exp::from_json(json.field("else")?)?),
+            ),
+            "Tuple" => Tuple(values("values")?),
+            "Proj" => Proj(value("tuple")?, json.field("index")?.as_int()?),
+            "Extcall" => Extcall(json.field("symbol")?.as_id()?, values("args")?),
+            kind => return Err(json::unknown_kind("flat Cexp", kind)),
+        })
+    }
+}
+
+impl Exp {
+    pub fn to_json(&self) -> Json {
+        use Exp::*;
+        match self {
+            Compexp(cexp, span) => Json::node(
+                "Compexp",
+                vec![("exp", cexp.to_json()), ("span", Json::span(*span))],
+            ),
+            Let(id, cexp, exp, span) | Loop(id, cexp, exp, span) => Json::node(
+                if let Let(..) = self { "Let" } else { "Loop" },
+                vec![
+                    ("name", Json::str(id)),
+                    ("value", cexp.to_json()),
+                    ("body", exp.to_json()),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Recur(val, span) => Json::node(
+                "Recur",
+                vec![("value", val.to_json()), ("span", Json::span(*span))],
+            ),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Exp::*;
+        let span = json.span_field()?;
+        let exp =
+            |key| -> CompileResult<Box<Exp>> { Ok(Box::new(Exp::from_json(json.field(key)?)?)) };
+        let cexp =
+            |key| -> CompileResult<Box<Cexp>> { Ok(Box::new(Cexp::from_json(json.field(key)?)?)) };
+        let name = || json.field("name")?.as_id();
+        Ok(match json.kind()? {
+            "Compexp" => Compexp(cexp("exp")?, span),
+            "Let" => Let(name()?, cexp("value")?, exp("body")?, span),
+            "Loop" => Loop(name()?, cexp("value")?, exp("body")?, span),
+            "Recur" => Recur(Value::from_json(json.field("value")?)?, span),
+            kind => return Err(json::unknown_kind("flat Exp", kind)),
+        })
+    }
+}
+
+impl Program {
+    pub fn to_json(&self) -> Json {
+        let recs = self
+            .recs
+            .iter()
+            .map(|Recdecl(id, args, exp, span)| {
+                Json::object(vec![
+                    ("name", Json::str(id)),
+                    ("params", Json::strs(args)),
+                    ("body", exp.to_json()),
+                    ("span", Json::span(*span)),
+                ])
+            })
+            .collect();
+        Json::object(vec![("recdecls", Json::Array(recs))])
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        let mut program = Program::new();
+        for rec in json.field("recdecls")?.as_array()? {
+            program.add(Recdecl::new(
+                rec.field("name")?.as_id()?,
+                rec.field("params")?.as_ids()?,
+                Exp::from_json(rec.field("body")?)?,
+                rec.span_field()?,
+            ));
+        }
+        Ok(program)
+    }
 }
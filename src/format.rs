@@ -0,0 +1,443 @@
+use super::lexer::*;
+use super::parser::*;
+use super::*;
+
+use std::fmt;
+
+// lines are broken where the layout allows once they get longer
+const WIDTH: usize = 80;
+
+// the precedence levels of the parser, loosest first: `expr` takes
+// `if`, `fun` and the bindings, then `eqexpr`, `ltexpr`, `pexpr`,
+// `mexpr`, `appexpr` and `aexpr`
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Level {
+    Expr,
+    Eq,
+    Lt,
+    Plus,
+    Mult,
+    App,
+    Atom,
+}
+
+fn level(ast: &Ast) -> Level {
+    use Ast::*;
+    match ast {
+        If(..) | Fun(..) | Let(..) | Rec(..) | Loop(..) => Level::Expr,
+        Binop(TokenType::Eq, ..) => Level::Eq,
+        Binop(TokenType::Lt, ..) => Level::Lt,
+        Binop(TokenType::Plus, ..) => Level::Plus,
+        Binop(..) => Level::Mult,
+        App(..) | Recur(..) => Level::App,
+        _ => Level::Atom,
+    }
+}
+
+// the levels the operands of `op` are parsed at; `+` is right
+// associative and `*` left associative, and the right operand of `*`
+// must be atomic
+fn operand_levels(op: TokenType) -> (Level, Level) {
+    match op {
+        TokenType::Eq => (Level::Lt, Level::Lt),
+        TokenType::Lt => (Level::Plus, Level::Plus),
+        TokenType::Plus => (Level::Mult, Level::Plus),
+        _ => (Level::Mult, Level::Atom),
+    }
+}
+
+// the subexpressions of `ast`, in source order
+fn children(ast: &Ast) -> Vec<&Ast> {
+    use Ast::*;
+    match ast {
+        Nonaexpr | Error(..) | ILit(..) | BLit(..) | Var(..) => vec![],
+        Binop(_, ast1, ast2, _)
+        | App(ast1, ast2, _)
+        | Tuple(ast1, ast2, _)
+        | Let(_, ast1, ast2, _)
+        | Loop(_, ast1, ast2, _)
+        | Rec(_, _, ast1, ast2, _) => vec![ast1, ast2],
+        If(ast1, ast2, ast3, _) => vec![ast1, ast2, ast3],
+        Fun(_, ast1, _) | Recur(ast1, _) | Proj(ast1, _, _) => vec![ast1],
+    }
+}
+
+// the source of `ast` on one line, with the parentheses the parser needs
+// to read it back as the same tree; each of `comments` inside it stays
+// before the subexpression that follows it, or at the end
+fn inline(ast: &Ast, source: &str, comments: &[Span]) -> String {
+    use Ast::*;
+    let span = ast.span();
+    if let Error(..) = ast {
+        // the source of an error node has its comments already
+        return source.get(span.lo..span.hi).unwrap_or_default().to_string();
+    }
+    let kids = children(ast);
+    // the comments between the end of the previous subexpression and `lo`
+    let between = |lo: usize| -> String {
+        let from = kids
+            .iter()
+            .map(|kid| kid.span().hi)
+            .filter(|&hi| hi <= lo)
+            .max()
+            .unwrap_or(span.lo);
+        comments
+            .iter()
+            .filter(|c| from <= c.lo && c.lo < lo)
+            .map(|c| format!("{} ", &source[c.lo..c.hi]))
+            .collect()
+    };
+    let bare = |ast: &Ast| inline(ast, source, comments);
+    let inline = |ast: &Ast| format!("{}{}", between(ast.span().lo), bare(ast));
+    let operand = |ast: &Ast, min: Level| {
+        // the parser does not take `recur` as a function
+        let tight = match ast {
+            Recur(..) => min < Level::App,
+            _ => level(ast) >= min,
+        };
+        if tight {
+            inline(ast)
+        } else {
+            format!("{}({})", between(ast.span().lo), bare(ast))
+        }
+    };
+    let text = match ast {
+        Nonaexpr | Error(..) => String::new(),
+        ILit(v, _) => v.to_string(),
+        BLit(v, _) => v.to_string(),
+        Var(id, _) => id.clone(),
+        Binop(op, lhs, rhs, _) => {
+            let (lmin, rmin) = operand_levels(*op);
+            format!(
+                "{} {} {}",
+                operand(lhs, lmin),
+                op.text(),
+                operand(rhs, rmin)
+            )
+        }
+        If(cond, then, els, _) => format!(
+            "if {} then {} else {}",
+            inline(cond),
+            inline(then),
+            inline(els)
+        ),
+        Fun((id, _), body, _) => format!("fun {} -> {}", id, inline(body)),
+        Let((id, _), value, body, _) => {
+            format!("let {} = {} in {}", id, inline(value), inline(body))
+        }
+        Loop((id, _), value, body, _) => {
+            format!("loop {} = {} in {}", id, inline(value), inline(body))
+        }
+        Rec((id, _), (param, _), value, body, _) => format!(
+            "let rec {} = fun {} -> {} in {}",
+            id,
+            param,
+            inline(value),
+            inline(body)
+        ),
+        Recur(value, _) => format!("recur {}", operand(value, Level::Atom)),
+        App(fun, arg, _) => format!("{} {}", operand(fun, Level::App), operand(arg, Level::Atom)),
+        Tuple(fst, snd, _) => format!("({}, {})", inline(fst), inline(snd)),
+        // nor a projection of a literal
+        Proj(tuple, i, _) => match **tuple {
+            ILit(..) | BLit(..) => format!("({}).{}", inline(tuple), i),
+            _ => format!("{}.{}", operand(tuple, Level::Atom), i),
+        },
+    };
+    let after = between(span.hi);
+    if after.is_empty() {
+        text
+    } else {
+        format!("{} {}", text, after.trim_end())
+    }
+}
+
+// whether a blank line separates `lo` from what ends at `hi` before it
+fn blank_between(source: &str, hi: usize, lo: usize) -> bool {
+    let lines: Vec<&str> = source.get(hi..lo).unwrap_or_default().split('\n').collect();
+    lines.len() > 2
+        && lines[1..lines.len() - 1]
+            .iter()
+            .any(|l| l.trim().is_empty())
+}
+
+// lays out a program line by line; comments are printed before the
+// expression or item that follows them, or at the end of the line they
+// trail in the source, and those inside an expression printed on one
+// line stay where they are in it
+struct Printer<'a> {
+    source: &'a str,
+    comments: &'a [Span],
+    // the first comment not printed yet
+    next: usize,
+    lines: Vec<String>,
+}
+
+impl Printer<'_> {
+    fn push(&mut self, indent: usize, text: &str) {
+        self.lines
+            .push(format!("{:indent$}{}", "", text, indent = indent));
+    }
+    fn comments_before(&mut self, lo: usize, indent: usize) {
+        while let Some(comment) = self.comments.get(self.next).filter(|c| c.lo < lo) {
+            let text = &self.source[comment.lo..comment.hi];
+            let line_start = self.source[..comment.lo].rfind('\n').map_or(0, |i| i + 1);
+            let trailing = !self.source[line_start..comment.lo].trim().is_empty();
+            match self.lines.last_mut() {
+                Some(last) if trailing && !last.trim().is_empty() => {
+                    last.push(' ');
+                    last.push_str(text);
+                }
+                _ => self.push(indent, text),
+            }
+            self.next += 1;
+        }
+    }
+    // `ast` on one line after the comments before it not printed yet,
+    // which are printed with those inside it
+    fn inline(&mut self, ast: &Ast) -> String {
+        let span = ast.span();
+        let rest = &self.comments[self.next..];
+        let mut text: String = rest
+            .iter()
+            .take_while(|c| c.lo < span.lo)
+            .map(|c| format!("{} ", &self.source[c.lo..c.hi]))
+            .collect();
+        text.push_str(&inline(ast, self.source, rest));
+        self.next += rest.iter().take_while(|c| c.lo < span.hi).count();
+        text
+    }
+    // where the next comment not printed yet starts, if there is one
+    fn next_comment(&self) -> usize {
+        self.comments
+            .get(self.next)
+            .map_or(usize::MAX, |comment| comment.lo)
+    }
+    // `ast` on one line at `indent` after `before`, if it fits and
+    // leaves no comment behind
+    fn fits(&self, ast: &Ast, indent: usize, before: usize) -> Option<String> {
+        let span = ast.span();
+        if self.next_comment() < span.hi {
+            return None;
+        }
+        let text = inline(ast, self.source, &[]);
+        if indent + before + text.len() <= WIDTH {
+            Some(text)
+        } else {
+            None
+        }
+    }
+    // `head value tail`, or `value` on lines of its own under `head` and
+    // above `tail` if it does not fit on one line; a binding as the
+    // value is always broken up
+    fn binding(&mut self, head: &str, value: &Ast, tail: &str, indent: usize) {
+        let bound = match value {
+            Ast::Let(..) | Ast::Loop(..) | Ast::Rec(..) => None,
+            _ => self.fits(value, indent, head.len() + tail.len() + 2),
+        };
+        match bound {
+            Some(text) => self.push(indent, format!("{} {} {}", head, text, tail).trim_end()),
+            None => {
+                self.push(indent, head);
+                self.block(value, indent + 2);
+                if !tail.is_empty() {
+                    self.push(indent, tail);
+                }
+            }
+        }
+    }
+    // `ast` starting on a line of its own; bindings go one per line
+    // with the body below them, and `if` and `fun` that do not fit are
+    // broken up with their branches and body indented
+    fn block(&mut self, ast: &Ast, indent: usize) {
+        use Ast::*;
+        self.comments_before(ast.span().lo, indent);
+        match ast {
+            Let((id, _), value, body, _) => {
+                self.binding(&format!("let {} =", id), value, "in", indent);
+                self.block(body, indent);
+            }
+            Loop((id, _), value, body, _) => {
+                self.binding(&format!("loop {} =", id), value, "in", indent);
+                self.block(body, indent);
+            }
+            Rec((id, _), (param, _), value, body, _) => {
+                self.binding(
+                    &format!("let rec {} = fun {} ->", id, param),
+                    value,
+                    "in",
+                    indent,
+                );
+                self.block(body, indent);
+            }
+            _ => {
+                if let Some(text) = self.fits(ast, indent, 0) {
+                    self.push(indent, &text);
+                    return;
+                }
+                match ast {
+                    If(..) => self.if_block(ast, indent, ""),
+                    Fun((id, _), body, _) => {
+                        self.push(indent, &format!("fun {} ->", id));
+                        self.block(body, indent + 2);
+                    }
+                    // operators and applications are not broken
+                    _ => {
+                        let text = self.inline(ast);
+                        self.push(indent, &text);
+                    }
+                }
+            }
+        }
+    }
+    // an `if` that does not fit on a line; an `else` branch that is an
+    // `if` of its own continues the chain as `else if`, broken up too
+    fn if_block(&mut self, ast: &Ast, indent: usize, prefix: &str) {
+        if let Ast::If(cond, then, els, _) = ast {
+            let cond = self.inline(cond);
+            self.push(indent, &format!("{}if {} then", prefix, cond));
+            self.block(then, indent + 2);
+            match &**els {
+                Ast::If(..) => {
+                    self.comments_before(els.span().lo, indent);
+                    self.if_block(els, indent, "else ");
+                }
+                _ => {
+                    self.push(indent, "else");
+                    self.block(els, indent + 2);
+                }
+            }
+        }
+    }
+    fn item(&mut self, item: &Item, indent: usize) {
+        match item {
+            Item::Let((id, _), value, _) => {
+                self.binding(&format!("let {} =", id), value, "", indent)
+            }
+            Item::Rec((id, _), (param, _), value, _) => self.binding(
+                &format!("let rec {} = fun {} ->", id, param),
+                value,
+                "",
+                indent,
+            ),
+            Item::Module(id, items, span) => {
+                self.push(indent, &format!("module {} = struct", id));
+                self.items(items, indent + 2);
+                self.comments_before(span.hi, indent + 2);
+                self.push(indent, "end");
+            }
+            Item::Open(id, _) => self.push(indent, &format!("open {}", id)),
+            Item::External(id, ty, symbol, _) => self.push(
+                indent,
+                &format!("external {} : {} = \"{}\"", id, ty, symbol),
+            ),
+        }
+    }
+    // items one after the other, keeping one blank line where the
+    // source has any
+    fn items(&mut self, items: &[Item], indent: usize) {
+        let mut hi = None;
+        for item in items {
+            self.separate(hi, item_span(item).lo);
+            self.comments_before(item_span(item).lo, indent);
+            self.item(item, indent);
+            hi = Some(item_span(item).hi);
+        }
+    }
+    fn separate(&mut self, hi: Option<usize>, lo: usize) {
+        if let Some(hi) = hi {
+            if blank_between(self.source, hi, lo.min(self.next_comment())) {
+                self.lines.push(String::new());
+            }
+        }
+    }
+    fn finish(mut self) -> String {
+        self.comments_before(self.source.len(), 0);
+        let mut out = self.lines.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn item_span(item: &Item) -> Span {
+    match item {
+        Item::Let(.., span)
+        | Item::Rec(.., span)
+        | Item::Module(.., span)
+        | Item::Open(.., span)
+        | Item::External(.., span) => *span,
+    }
+}
+
+// the canonical layout of `source`, which is a program or, failing
+// that, a unit; if it is neither, the error of the reading that got
+// further is returned
+pub fn format(source: &str) -> CompileResult<String> {
+    let comments = tokenize(source)?.comments;
+    let mut printer = Printer {
+        source,
+        comments: &comments,
+        next: 0,
+        lines: vec![],
+    };
+    match parse(tokenize(source)?) {
+        Ok(program) => {
+            printer.items(&program.items, 0);
+            let lo = program.body.span().lo;
+            printer.separate(program.items.last().map(|item| item_span(item).hi), lo);
+            printer.block(&program.body, 0);
+            if let Some(last) = printer.lines.last_mut() {
+                last.push_str(";;");
+            }
+            Ok(printer.finish())
+        }
+        Err(err) => match parse_unit(tokenize(source)?) {
+            Ok(items) => {
+                printer.items(&items, 0);
+                Ok(printer.finish())
+            }
+            Err(unit_err) => {
+                let at = |err: &CompileError| err.span.map_or(0, |span| span.lo);
+                Err(if at(&unit_err) > at(&err) {
+                    unit_err
+                } else {
+                    err
+                })
+            }
+        },
+    }
+}
+
+// a tree a pass has rewritten is printed in the same layout, without
+// the comments and blank lines of a source it no longer matches
+fn print(items: &[Item], body: &Ast) -> String {
+    let mut printer = Printer {
+        source: "",
+        comments: &[],
+        next: 0,
+        lines: vec![],
+    };
+    printer.items(items, 0);
+    // a unit is a program whose body is `Nonaexpr`
+    if !matches!(body, Ast::Nonaexpr) {
+        printer.block(body, 0);
+        if let Some(last) = printer.lines.last_mut() {
+            last.push_str(";;");
+        }
+    }
+    printer.finish()
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", print(&self.items, &self.body))
+    }
+}
+
+impl fmt::Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", print(&[], self))
+    }
+}
@@ -0,0 +1,350 @@
+use super::*;
+
+use std::fmt;
+
+// just enough JSON to print and read machine-readable forms without a
+// dependency; numbers are integers only
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(i64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+    // a node of an IR, tagged with its `kind`
+    pub fn node(kind: &str, mut fields: Vec<(&str, Json)>) -> Self {
+        fields.insert(0, ("kind", Json::str(kind)));
+        Json::object(fields)
+    }
+    pub fn str(s: &str) -> Self {
+        Json::Str(s.to_string())
+    }
+    pub fn int(n: i32) -> Self {
+        Json::Num(n as i64)
+    }
+    pub fn strs(items: &[String]) -> Self {
+        Json::Array(items.iter().map(|item| Json::str(item)).collect())
+    }
+    // `[lo, hi]`, the byte offsets of a span
+    pub fn span(span: Span) -> Self {
+        Json::Array(vec![Json::Num(span.lo as i64), Json::Num(span.hi as i64)])
+    }
+    fn what(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Num(_) => "a number",
+            Json::Str(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+    fn expected(&self, what: &str) -> CompileError {
+        json_error(format!("expected {}, found {}.", what, self.what()))
+    }
+    pub fn field(&self, key: &str) -> CompileResult<&Json> {
+        match self {
+            Json::Object(fields) => match fields.iter().find(|(name, _)| name == key) {
+                Some((_, value)) => Ok(value),
+                None => Err(json_error(format!("missing field `{}`.", key))),
+            },
+            _ => Err(self.expected("an object")),
+        }
+    }
+    pub fn as_str(&self) -> CompileResult<&str> {
+        match self {
+            Json::Str(s) => Ok(s),
+            _ => Err(self.expected("a string")),
+        }
+    }
+    pub fn as_id(&self) -> CompileResult<Id> {
+        Ok(self.as_str()?.to_string())
+    }
+    pub fn as_ids(&self) -> CompileResult<Vec<Id>> {
+        self.as_array()?.iter().map(Json::as_id).collect()
+    }
+    pub fn as_bool(&self) -> CompileResult<bool> {
+        match self {
+            Json::Bool(b) => Ok(*b),
+            _ => Err(self.expected("a boolean")),
+        }
+    }
+    pub fn as_int(&self) -> CompileResult<i32> {
+        match self {
+            Json::Num(n) if (i32::MIN as i64..=i32::MAX as i64).contains(n) => Ok(*n as i32),
+            Json::Num(n) => Err(json_error(format!("{} does not fit in 32 bits.", n))),
+            _ => Err(self.expected("a number")),
+        }
+    }
+    pub fn as_array(&self) -> CompileResult<&[Json]> {
+        match self {
+            Json::Array(values) => Ok(values),
+            _ => Err(self.expected("an array")),
+        }
+    }
+    // the `kind` every node of the IRs is tagged with
+    pub fn kind(&self) -> CompileResult<&str> {
+        self.field("kind")?.as_str()
+    }
+    // the `span` field, which may be left out
+    pub fn span_field(&self) -> CompileResult<Span> {
+        if let Json::Object(fields) = self {
+            if !fields.iter().any(|(name, _)| name == "span") {
+                return Ok(Span::default());
+            }
+        }
+        match self.field("span")?.as_array()? {
+            [lo, hi] if lo.as_int()? >= 0 && hi.as_int()? >= lo.as_int()? => {
+                Ok(Span::new(lo.as_int()? as usize, hi.as_int()? as usize))
+            }
+            _ => Err(json_error(String::from("a span should be `[lo, hi]`."))),
+        }
+    }
+    // the value `text` holds
+    pub fn parse(text: &str) -> CompileResult<Json> {
+        let mut parser = Parser { text, pos: 0 };
+        let value = parser.value()?;
+        parser.skip();
+        if parser.pos < text.len() {
+            return Err(parser.error("expected end of input."));
+        }
+        Ok(value)
+    }
+}
+
+// an error in JSON that does not describe what it should
+pub fn json_error(message: String) -> CompileError {
+    CompileError::unlocated(ErrorKind::Syntax, format!("JSON: {}", message))
+}
+
+// an unknown `kind` of the node of `what`
+pub fn unknown_kind(what: &str, kind: &str) -> CompileError {
+    json_error(format!("unknown {} kind `{}`.", what, kind))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+    fn skip(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+    fn error(&self, message: &str) -> CompileError {
+        let width = self.rest().chars().next().map_or(0, char::len_utf8);
+        CompileError::new(
+            ErrorKind::Syntax,
+            format!("JSON: {}", message),
+            Span::new(self.pos, self.pos + width),
+        )
+    }
+    fn eat(&mut self, text: &str) -> bool {
+        self.skip();
+        if self.rest().starts_with(text) {
+            self.pos += text.len();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, text: &str) -> CompileResult<()> {
+        if self.eat(text) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`.", text)))
+        }
+    }
+    // `items` between `open` and `close`, separated by `,`
+    fn seq<T>(
+        &mut self,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> CompileResult<T>,
+    ) -> CompileResult<Vec<T>> {
+        let mut items = vec![];
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(close) {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+    fn value(&mut self) -> CompileResult<Json> {
+        self.skip();
+        if self.eat("null") {
+            Ok(Json::Null)
+        } else if self.eat("true") {
+            Ok(Json::Bool(true))
+        } else if self.eat("false") {
+            Ok(Json::Bool(false))
+        } else if self.eat("[") {
+            Ok(Json::Array(self.seq("]", Self::value)?))
+        } else if self.eat("{") {
+            let fields = self.seq("}", |parser| {
+                parser.skip();
+                let key = parser.string()?;
+                parser.expect(":")?;
+                Ok((key, parser.value()?))
+            })?;
+            Ok(Json::Object(fields))
+        } else if self.rest().starts_with('"') {
+            Ok(Json::Str(self.string()?))
+        } else {
+            self.number()
+        }
+    }
+    fn number(&mut self) -> CompileResult<Json> {
+        let start = self.pos;
+        let sign = self.rest().starts_with('-') as usize;
+        let digits = self.rest()[sign..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest().len() - sign);
+        if digits == 0 {
+            return Err(self.error("expected a value."));
+        }
+        self.pos += sign + digits;
+        if self.rest().starts_with(['.', 'e', 'E']) {
+            return Err(self.error("only integers are supported."));
+        }
+        match self.text[start..self.pos].parse() {
+            Ok(n) => Ok(Json::Num(n)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("number is too large."))
+            }
+        }
+    }
+    fn hex4(&mut self) -> CompileResult<u32> {
+        let code = self
+            .rest()
+            .get(..4)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+        match code {
+            Some(code) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            None => Err(self.error("expected four hex digits.")),
+        }
+    }
+    fn string(&mut self) -> CompileResult<String> {
+        if !self.rest().starts_with('"') {
+            return Err(self.error("expected a string."));
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = match self.rest().chars().next() {
+                Some(c) => c,
+                None => return Err(self.error("string should be closed.")),
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.rest().chars().next();
+                    self.pos += escape.map_or(0, char::len_utf8);
+                    match escape {
+                        Some('"') => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        Some('/') => s.push('/'),
+                        Some('b') => s.push('\u{8}'),
+                        Some('f') => s.push('\u{c}'),
+                        Some('n') => s.push('\n'),
+                        Some('r') => s.push('\r'),
+                        Some('t') => s.push('\t'),
+                        Some('u') => {
+                            let mut code = self.hex4()?;
+                            // a character outside the BMP is a surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.rest().starts_with("\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair."));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => s.push(c),
+                                None => return Err(self.error("invalid character escape.")),
+                            }
+                        }
+                        _ => return Err(self.error("invalid escape.")),
+                    }
+                }
+                c if (c as u32) < 0x20 => {
+                    return Err(self.error("control character in string."));
+                }
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+fn escape(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Num(n) => write!(f, "{}", n),
+            Json::Str(s) => escape(f, s),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    escape(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
@@ -26,6 +26,16 @@ pub enum TokenType {
     Recur,
     True,
     False,
+    Module,
+    Struct,
+    End,
+    Open,
+    Val,
+    Colon,
+    External,
+    Str,
+    // closes every token sequence
+    Eof,
 }
 
 impl From<&str> for TokenType {
@@ -42,31 +52,74 @@ impl From<&str> for TokenType {
             "recur" => TokenType::Recur,
             "true" => TokenType::True,
             "false" => TokenType::False,
+            "module" => TokenType::Module,
+            "struct" => TokenType::Struct,
+            "end" => TokenType::End,
+            "open" => TokenType::Open,
+            "val" => TokenType::Val,
+            "external" => TokenType::External,
             _ => TokenType::Id,
         }
     }
 }
 
+impl TokenType {
+    // how the token is written, for error messages
+    pub fn text(self) -> &'static str {
+        use TokenType::*;
+        match self {
+            Semisemi => ";;",
+            ILit => "integer",
+            Plus => "+",
+            Mult => "*",
+            Lt => "<",
+            Arrow => "->",
+            Assign => "=",
+            Lbrac => "(",
+            Rbrac => ")",
+            Comma => ",",
+            Dot => ".",
+            Eq => "==",
+            Id => "identifier",
+            If => "if",
+            Then => "then",
+            Else => "else",
+            Fun => "fun",
+            Let => "let",
+            In => "in",
+            Rec => "rec",
+            Loop => "loop",
+            Recur => "recur",
+            True => "true",
+            False => "false",
+            Module => "module",
+            Struct => "struct",
+            End => "end",
+            Open => "open",
+            Val => "val",
+            Colon => ":",
+            External => "external",
+            Str => "string",
+            Eof => "end of input",
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Token {
     pub tokentype: TokenType,
     pub num: i32,
     pub id: Option<String>,
-    pub position: (bool, usize, usize),
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(
-        tokentype: TokenType,
-        num: i32,
-        id: Option<String>,
-        position: (bool, usize, usize),
-    ) -> Self {
+    pub fn new(tokentype: TokenType, num: i32, id: Option<String>, span: Span) -> Self {
         Self {
             tokentype,
             num,
             id,
-            position,
+            span,
         }
     }
 }
@@ -74,14 +127,26 @@ impl Token {
 pub struct TokenSet {
     pub tokens: Vec<Token>,
     pub pos: usize,
+    // syntax errors the parser has recovered from
+    pub errors: Vec<CompileError>,
+    // where the comments are, for the formatter to keep them
+    pub comments: Vec<Span>,
 }
 
 impl TokenSet {
-    pub fn assert_ttype(&mut self, ttype: TokenType) {
+    pub fn assert_ttype(&mut self, ttype: TokenType) -> CompileResult<()> {
         if !self.consume_ttype(ttype) {
-            compile_error(&self, "lexer tokenset error");
-            std::process::exit(1);
+            return Err(self.error(&format!(
+                "expected `{}`, found `{}`.",
+                ttype.text(),
+                self.curtype().text()
+            )));
         }
+        Ok(())
+    }
+    // a syntax error at the current token
+    pub fn error(&self, message: &str) -> CompileError {
+        CompileError::new(ErrorKind::Syntax, message.to_string(), self.curspan())
     }
     pub fn consume_ttype(&mut self, ttype: TokenType) -> bool {
         if ttype == self.tokens[self.pos].tokentype {
@@ -97,247 +162,262 @@ impl TokenSet {
     pub fn curnum(&self) -> i32 {
         self.tokens[self.pos].num
     }
-    pub fn curid(&self) -> Option<Id> {
-        if let TokenType::Id = self.curtype() {
-            self.tokens[self.pos].id.clone()
+    pub fn curid(&self) -> CompileResult<Id> {
+        match (self.curtype(), &self.tokens[self.pos].id) {
+            (TokenType::Id, Some(id)) => Ok(id.clone()),
+            _ => Err(self.error(&format!(
+                "expected identifier, found `{}`.",
+                self.curtype().text()
+            ))),
+        }
+    }
+    pub fn nexttype(&self) -> TokenType {
+        self.tokens[(self.pos + 1).min(self.tokens.len() - 1)].tokentype
+    }
+    pub fn curspan(&self) -> Span {
+        self.tokens[self.pos].span
+    }
+    pub fn prevspan(&self) -> Span {
+        self.tokens[self.pos - 1].span
+    }
+    // the span of the tokens from `start` to the current one, exclusive
+    pub fn span_from(&self, start: usize) -> Span {
+        if self.pos > start {
+            self.tokens[start].span.to(self.prevspan())
         } else {
-            compile_error(self, "should be identifier.");
-            std::process::exit(1);
+            Span::new(self.curspan().lo, self.curspan().lo)
         }
     }
     pub fn eof(&self) -> bool {
-        self.pos + 1 == self.tokens.len()
+        self.curtype() == TokenType::Eof
     }
 }
 
-fn signal(program: &str, pos: &mut usize, line: usize, head: bool) -> Option<Token> {
-    if &program[*pos..*pos + 2] == ";;" {
-        *pos += 2;
-        Some(Token::new(
-            TokenType::Semisemi,
-            -1,
-            None,
-            (head, line, *pos - 2),
-        ))
-    } else if &program[*pos..*pos + 2] == "->" {
-        *pos += 2;
-        Some(Token::new(
-            TokenType::Arrow,
-            -1,
-            None,
-            (head, line, *pos - 2),
-        ))
-    } else if &program[*pos..*pos + 2] == "==" {
-        *pos += 2;
-        Some(Token::new(TokenType::Eq, -1, None, (head, line, *pos - 2)))
-    } else if &program[*pos..*pos + 1] == "+" {
-        *pos += 1;
-        Some(Token::new(
-            TokenType::Plus,
-            -1,
-            None,
-            (head, line, *pos - 1),
-        ))
-    } else if &program[*pos..*pos + 1] == "*" {
-        *pos += 1;
-        Some(Token::new(
-            TokenType::Mult,
-            -1,
-            None,
-            (head, line, *pos - 1),
-        ))
-    } else if &program[*pos..*pos + 1] == "<" {
-        *pos += 1;
-        Some(Token::new(TokenType::Lt, -1, None, (head, line, *pos - 1)))
-    } else if &program[*pos..*pos + 1] == "=" {
-        *pos += 1;
-        Some(Token::new(
-            TokenType::Assign,
-            -1,
-            None,
-            (head, line, *pos - 1),
-        ))
-    } else if &program[*pos..*pos + 1] == "(" {
-        *pos += 1;
-        Some(Token::new(
-            TokenType::Lbrac,
-            -1,
-            None,
-            (head, line, *pos - 1),
-        ))
-    } else if &program[*pos..*pos + 1] == ")" {
-        *pos += 1;
-        Some(Token::new(
-            TokenType::Rbrac,
-            -1,
-            None,
-            (head, line, *pos - 1),
-        ))
-    } else if &program[*pos..*pos + 1] == "," {
-        *pos += 1;
-        Some(Token::new(
-            TokenType::Comma,
-            -1,
-            None,
-            (head, line, *pos - 1),
-        ))
-    } else if &program[*pos..*pos + 1] == "." {
-        *pos += 1;
-        Some(Token::new(TokenType::Dot, -1, None, (head, line, *pos - 1)))
-    } else {
-        None
+// positions are byte offsets into `program`, always on a character boundary
+
+const SIGNALS: [(&str, TokenType); 12] = [
+    (";;", TokenType::Semisemi),
+    ("->", TokenType::Arrow),
+    ("==", TokenType::Eq),
+    ("+", TokenType::Plus),
+    ("*", TokenType::Mult),
+    ("<", TokenType::Lt),
+    ("=", TokenType::Assign),
+    ("(", TokenType::Lbrac),
+    (")", TokenType::Rbrac),
+    (",", TokenType::Comma),
+    (":", TokenType::Colon),
+    (".", TokenType::Dot),
+];
+
+fn signal(program: &str, pos: &mut usize) -> Option<Token> {
+    let start = *pos;
+    for (text, ttype) in SIGNALS.iter() {
+        if program[start..].starts_with(text) {
+            *pos += text.len();
+            return Some(Token::new(*ttype, -1, None, Span::new(start, *pos)));
+        }
     }
+    None
 }
 
-fn identify(
-    s: &Vec<char>,
-    program: &str,
-    pos: &mut usize,
-    line: usize,
-    head: bool,
-) -> Option<Token> {
+fn identify(program: &str, pos: &mut usize) -> Option<Token> {
     let start = *pos;
-    // first character should be alphabet
-    if s[*pos].is_ascii_alphabetic() {
-        *pos += 1;
-    } else {
+    // first character should be alphabet, or `_` for names the lint
+    // should not warn about
+    if !program[start..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
         return None;
     }
-    while s[*pos].is_ascii_alphabetic()
-        || s[*pos].is_ascii_digit()
-        || s[*pos] == '_'
-        || s[*pos] == '\''
-    {
-        *pos += 1;
+    *pos += program[start..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '\''))
+        .unwrap_or(program.len() - start);
+    Some(Token::new(
+        TokenType::from(&program[start..*pos]),
+        -1,
+        Some(String::from(&program[start..*pos])),
+        Span::new(start, *pos),
+    ))
+}
+
+fn number(program: &str, pos: &mut usize) -> CompileResult<Option<Token>> {
+    let start = *pos;
+    *pos += program[start..]
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(program.len() - start);
+    if start == *pos {
+        return Ok(None);
     }
-    if start < *pos {
-        Some(Token::new(
-            TokenType::from(&program[start..*pos]),
-            -1,
-            Some(String::from(&program[start..*pos])),
-            (head, line, start),
-        ))
-    } else {
-        None
+    let span = Span::new(start, *pos);
+    match program[start..*pos].parse::<i32>() {
+        Ok(num) => Ok(Some(Token::new(TokenType::ILit, num, None, span))),
+        Err(_) => Err(CompileError::new(
+            ErrorKind::Lex,
+            String::from("integer literal is too large."),
+            span,
+        )),
     }
 }
 
-fn number(s: &Vec<char>, pos: &mut usize, line: usize, head: bool) -> Option<Token> {
+fn string(program: &str, pos: &mut usize) -> CompileResult<Option<Token>> {
     let start = *pos;
-    let mut num = 0;
-    while s[*pos].is_ascii_digit() {
-        num = num * 10 + (s[*pos] as i32 - 48);
-        *pos += 1;
+    if !program[start..].starts_with('"') {
+        return Ok(None);
     }
-    if start < *pos {
-        Some(Token::new(TokenType::ILit, num, None, (head, line, start)))
-    } else {
-        None
+    let len = program[start + 1..]
+        .find(['"', '\n', '\r'])
+        .unwrap_or(program.len() - start - 1);
+    *pos = start + 1 + len;
+    if !program[*pos..].starts_with('"') {
+        return Err(CompileError::new(
+            ErrorKind::Lex,
+            String::from("string should be closed."),
+            Span::new(start, *pos),
+        ));
     }
+    *pos += 1;
+    Ok(Some(Token::new(
+        TokenType::Str,
+        -1,
+        Some(String::from(&program[start + 1..*pos - 1])),
+        Span::new(start, *pos),
+    )))
 }
 
-fn preprocess(pgstr: &mut Vec<char>, program: &mut String) {
-    let expect_f = pgstr.len() >= 6 && &program[0..6] == "EXPECT";
-    if expect_f {
-        let mut colon_i = 0;
-        let pg_string;
-        let ans_string;
-        loop {
-            if &program[colon_i..colon_i + 4] == " => " {
-                pg_string = String::from(&program[7..colon_i - 2]);
-                colon_i += 4;
-                let num_s = colon_i;
-                while pgstr[colon_i].is_ascii_digit() {
-                    colon_i += 1;
-                }
-                assert_eq!(pgstr[colon_i], ')');
-                ans_string = String::from(&program[num_s..colon_i]);
-                break;
+// skips a comment, which may nest, if one starts at `pos`
+fn comment(program: &str, pos: &mut usize) -> CompileResult<bool> {
+    let start = *pos;
+    if !program[start..].starts_with("(*") {
+        return Ok(false);
+    }
+    let mut count = 0;
+    while *pos < program.len() {
+        if program[*pos..].starts_with("(*") {
+            count += 1;
+            *pos += 2;
+        } else if program[*pos..].starts_with("*)") {
+            count -= 1;
+            *pos += 2;
+            if count == 0 {
+                return Ok(true);
             }
-            colon_i += 1;
+        } else {
+            *pos += program[*pos..].chars().next().map_or(1, char::len_utf8);
         }
-        *PROGRAM.lock().unwrap() = format!(
-            "let pg = {} in if pg == {} then 0 else pg;;",
-            pg_string, ans_string
-        );
-        *pgstr = (*PROGRAM).lock().unwrap().chars().collect::<Vec<char>>();
-        *program = std::mem::replace(&mut *PROGRAM.lock().unwrap(), String::new());
     }
+    Err(CompileError::new(
+        ErrorKind::Lex,
+        String::from("comment should be closed."),
+        Span::new(start, start + 2),
+    ))
+}
+
+// `EXPECT(program;; => value):` of the test suite becomes a program
+// returning 0 when `program` evaluates to `value`
+fn preprocess(program: &mut String) -> CompileResult<()> {
+    if !program.starts_with("EXPECT(") {
+        return Ok(());
+    }
+    let expect_error =
+        |message: &str| Err(CompileError::unlocated(ErrorKind::Lex, message.to_string()));
+    let arrow = match program.find(" => ") {
+        Some(arrow) => arrow,
+        None => return expect_error("EXPECT should contain ` => `."),
+    };
+    let pg_string = program[7..arrow].trim_end();
+    let pg_string = pg_string.strip_suffix(";;").unwrap_or(pg_string);
+    let rest = &program[arrow + 4..];
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    if !rest[digits..].starts_with(')') {
+        return expect_error("EXPECT should end with `)`.");
+    }
+    *program = format!(
+        "let pg = {} in if pg == {} then 0 else pg;;",
+        pg_string,
+        &rest[..digits]
+    );
+    Ok(())
 }
 
-pub fn lex() -> TokenSet {
+pub fn tokenize(program: &str) -> CompileResult<TokenSet> {
     let mut tokens = vec![];
+    let mut comments = vec![];
     let mut pos: usize = 0;
-    let mut line: usize = 1;
-    let mut head = true;
-    let mut pgstr = (*PROGRAM).lock().unwrap().chars().collect::<Vec<char>>();
-    let mut program = std::mem::replace(&mut *PROGRAM.lock().unwrap(), String::new());
-    preprocess(&mut pgstr, &mut program);
 
-    while pos < pgstr.len() - 1 {
-        // comment
-        if &program[pos..pos + 2] == "(*" {
-            let mut count = 1;
-            pos += 2;
-            loop {
-                if pos + 1 == pgstr.len() {
-                    panic!("Comment should be closed.");
-                } else if &program[pos..pos + 2] == "(*" {
-                    count += 1;
-                } else if &program[pos..pos + 2] == "*)" {
-                    pos += 2;
-                    count -= 1;
-                    if count == 0 {
-                        break;
-                    }
-                }
-                pos += 1;
-            }
+    while let Some(nchar) = program[pos..].chars().next() {
+        // spaces, tabs and line breaks, including `\r\n`
+        if nchar.is_whitespace() {
+            pos += nchar.len_utf8();
             continue;
         }
 
-        // identifier
-        if let Some(token) = identify(&pgstr, &program[..], &mut pos, line, head) {
-            tokens.push(token);
-            head = false;
+        let start = pos;
+        if comment(program, &mut pos)? {
+            comments.push(Span::new(start, pos));
             continue;
         }
 
-        let nchar = pgstr[pos];
-
-        // newline
-        if nchar == '\n' {
-            line += 1;
-            pos += 1;
-            head = true;
+        // identifier
+        if let Some(token) = identify(program, &mut pos) {
+            tokens.push(token);
             continue;
         }
 
-        // space
-        if nchar == ' ' {
-            pos += 1;
+        // ILit
+        if let Some(token) = number(program, &mut pos)? {
+            tokens.push(token);
             continue;
         }
 
-        // ILit
-        if let Some(token) = number(&pgstr, &mut pos, line, head) {
+        // string
+        if let Some(token) = string(program, &mut pos)? {
             tokens.push(token);
-            head = false;
             continue;
         }
 
         // signal
-        if let Some(token) = signal(&program[..], &mut pos, line, head) {
+        if let Some(token) = signal(program, &mut pos) {
             tokens.push(token);
-            head = false;
             continue;
         }
 
-        panic!("tokenize error.:: {}", program);
+        return Err(CompileError::new(
+            ErrorKind::Lex,
+            format!("unexpected character `{}`.", nchar.escape_debug()),
+            Span::new(pos, pos + nchar.len_utf8()),
+        ));
     }
-    *PROGRAM.lock().unwrap() = program;
-    TokenSet {
-        tokens: tokens,
+    let end = Span::new(program.len(), program.len());
+    tokens.push(Token::new(TokenType::Eof, -1, None, end));
+    Ok(TokenSet {
+        tokens,
         pos: 0,
+        errors: vec![],
+        comments,
+    })
+}
+
+// `tokens` one per line, each with the line and column of `source` it
+// starts at
+pub fn tokens_text(tokens: &[Token], source: &str) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let (line, column) = line_col(source, token.span.lo);
+        out.push_str(&format!("{}:{} {:?}", line, column, token.tokentype));
+        if let Some(id) = &token.id {
+            out.push_str(&format!(" {:?}", id));
+        } else if token.tokentype == TokenType::ILit {
+            out.push_str(&format!(" {}", token.num));
+        }
+        out.push('\n');
     }
+    out
+}
+
+// tokens of `program`, which is rewritten in place if it is an `EXPECT`
+// test so that spans point into what was tokenized
+pub fn lex(program: &mut String) -> CompileResult<TokenSet> {
+    preprocess(program)?;
+    tokenize(program)
 }
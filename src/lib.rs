@@ -1,64 +1,306 @@
-use once_cell::sync::Lazy;
 use std::collections::HashMap;
-use std::env;
+use std::fmt;
 use std::fs;
-use std::sync::Mutex;
+use std::path::PathBuf;
 
+pub mod cfg;
+pub mod check;
 pub mod closure;
 pub mod codegen;
+pub mod eval;
+pub mod external;
 pub mod flat;
+pub mod format;
+pub mod json;
 pub mod lexer;
+pub mod lint;
+pub mod module;
 pub mod normal;
 pub mod parser;
+pub mod reader;
 pub mod regalloc;
+pub mod rename;
+pub mod session;
+pub mod stats;
+pub mod typing;
+pub mod unit;
+pub mod verify;
 pub mod vm;
 
+pub use json::Json;
 use lexer::*;
+pub use session::{Artifact, Session, Stage};
+pub use stats::Stats;
 
 type Id = String;
 type NV = normal::Value;
 type FV = flat::Value;
 
-pub static PROGRAM: Lazy<Mutex<String>> = Lazy::new(|| {
-    let file: String = env::args().collect::<Vec<String>>().last().unwrap().clone();
-    Mutex::new(fs::read_to_string(file).expect("failed to read file."))
-});
+// `items` separated by `, `, as the IR printers write argument lists
+fn comma_separated<T: fmt::Display>(f: &mut fmt::Formatter, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+// what a compilation refers to besides its own bindings: functions of
+// units, C externals and the directory the units are looked up in
+#[derive(Clone, Debug, Default)]
+pub struct Linkage {
+    pub dir: PathBuf,
+    pub globals: Vec<unit::Global>,
+    pub externals: Vec<external::External>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl Span {
+    pub fn new(lo: usize, hi: usize) -> Self {
+        Self { lo, hi }
+    }
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+}
+
+// the nearest character boundary of `program` at or before `offset`
+fn boundary(program: &str, offset: usize) -> usize {
+    let mut offset = offset.min(program.len());
+    while !program.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+// 1-based line and column of byte `offset`, counting characters
+pub fn line_col(program: &str, offset: usize) -> (usize, usize) {
+    let offset = boundary(program, offset);
+    let start = program[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = program[..offset].matches('\n').count() + 1;
+    (line, program[start..offset].chars().count() + 1)
+}
+
+fn snippet(program: &str, header: &str, span: Span) -> String {
+    let lo = boundary(program, span.lo);
+    let start = program[..lo].rfind('\n').map_or(0, |i| i + 1);
+    let end = program[lo..].find('\n').map_or(program.len(), |i| lo + i);
+    let text = program[start..end].trim_end_matches('\r');
+    let (line, column) = line_col(program, lo);
+    let mut out = format!(
+        "{} Line: {}, Column: {}.\n\t{}\n\t",
+        header, line, column, text
+    );
+    // tabs are kept so that the carets line up with the text above
+    for c in program[start..lo].chars() {
+        out.push(if c == '\t' { '\t' } else { ' ' });
+    }
+    let hi = boundary(program, span.hi.clamp(lo, start + text.len()));
+    for _ in 0..program[lo..hi].chars().count().max(1) {
+        out.push('^');
+    }
+    out.push('\n');
+    out
+}
+
+fn json_span(file: &str, program: &str, span: Span, primary: bool, label: Option<&str>) -> Json {
+    let lo = boundary(program, span.lo);
+    let hi = boundary(program, span.hi.max(lo));
+    let (line_start, column_start) = line_col(program, lo);
+    let (line_end, column_end) = line_col(program, hi);
+    Json::object(vec![
+        ("file", Json::str(file)),
+        ("byte_start", Json::Num(lo as i64)),
+        ("byte_end", Json::Num(hi as i64)),
+        ("line_start", Json::Num(line_start as i64)),
+        ("column_start", Json::Num(column_start as i64)),
+        ("line_end", Json::Num(line_end as i64)),
+        ("column_end", Json::Num(column_end as i64)),
+        ("primary", Json::Bool(primary)),
+        ("label", label.map_or(Json::Null, Json::str)),
+    ])
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    Lex,
+    Syntax,
+    Module,
+    // unbound names
+    Name,
+    Loop,
+    Type,
+    Unit,
+    // what the interpreter cannot run
+    Eval,
+    // a broken invariant of the compiler itself
+    Internal,
+    // lints
+    Unused,
+    Shadowed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    // reported without stopping the compilation
+    Warning,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+impl ErrorKind {
+    // the stable name of the kind in JSON diagnostics
+    pub fn code(self) -> &'static str {
+        use ErrorKind::*;
+        match self {
+            Lex => "lex",
+            Syntax => "syntax",
+            Module => "module",
+            Name => "name",
+            Loop => "loop",
+            Type => "type",
+            Unit => "unit",
+            Eval => "eval",
+            Internal => "internal",
+            Unused => "unused",
+            Shadowed => "shadowed",
+        }
+    }
+}
 
-pub fn compile_error(tokenset: &TokenSet, message: &str) {
-    let mut start: usize = 0;
-    let mut end: usize = std::usize::MAX;
+// an error of any stage; `span` is absent when the error is not about
+// a place in the input program
+#[derive(Clone, Debug)]
+pub struct CompileError {
+    pub kind: ErrorKind,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub note: Option<(Span, String)>,
+    // further errors found in the same run, reported after this one
+    pub more: Vec<CompileError>,
+}
 
-    for i in 0..std::usize::MAX {
-        if tokenset.pos - i == 0 || tokenset.tokens[tokenset.pos - i].position.0 == true {
-            start = tokenset.tokens[tokenset.pos - i].position.2;
-            break;
+impl CompileError {
+    pub fn new(kind: ErrorKind, message: String, span: Span) -> Self {
+        Self {
+            kind,
+            severity: Severity::Error,
+            message,
+            span: Some(span),
+            note: None,
+            more: vec![],
         }
     }
-    for i in 0..std::usize::MAX {
-        if tokenset.pos + i == tokenset.tokens.len() - 1 {
-            end = (*PROGRAM).lock().unwrap().len();
-            break;
+    pub fn unlocated(kind: ErrorKind, message: String) -> Self {
+        Self {
+            kind,
+            severity: Severity::Error,
+            message,
+            span: None,
+            note: None,
+            more: vec![],
         }
-        if tokenset.tokens[tokenset.pos + i].position.0 == true {
-            end = tokenset.tokens[tokenset.pos + i].position.2 - 1;
-            break;
+    }
+    pub fn internal(message: &str) -> Self {
+        Self::unlocated(ErrorKind::Internal, message.to_string())
+    }
+    pub fn warning(kind: ErrorKind, message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::new(kind, message, span)
         }
     }
-    println!(
-        "Error: {} Line: {}.",
-        message, tokenset.tokens[tokenset.pos].position.1
-    );
-    println!("\t{}", &(*PROGRAM).lock().unwrap()[start..end]);
-    print!("\t");
-    for _ in 0..tokenset.tokens[tokenset.pos].position.2 - start {
-        print!(" ");
+    pub fn with_note(mut self, span: Span, message: String) -> Self {
+        self.note = Some((span, message));
+        self
+    }
+    // the first of `errors` carrying the rest, if there is any
+    pub fn collect(errors: Vec<CompileError>) -> Option<Self> {
+        let mut errors = errors.into_iter();
+        let mut first = errors.next()?;
+        first.more.extend(errors);
+        Some(first)
+    }
+    // every error of the run, in order
+    pub fn errors(&self) -> Vec<&CompileError> {
+        let mut errors = vec![self];
+        for err in &self.more {
+            errors.extend(err.errors());
+        }
+        errors
+    }
+    // this error alone as text, quoting the lines of `program` it is about
+    pub fn text(&self, program: &str) -> String {
+        let header = match self.severity {
+            Severity::Error => format!("Error: {}", self.message),
+            Severity::Warning => format!("Warning: {}", self.message),
+        };
+        match self.span {
+            Some(span) => {
+                let mut out = snippet(program, &header, span);
+                if let Some((nspan, nmessage)) = &self.note {
+                    out.push_str(&snippet(program, &format!("Note: {}", nmessage), *nspan));
+                }
+                out
+            }
+            None => format!("{}\n", header),
+        }
+    }
+    // this error alone as a JSON diagnostic; the note becomes a secondary
+    // span when it has one
+    pub fn json(&self, file: &str, program: &str) -> Json {
+        let mut spans = vec![];
+        if let Some(span) = self.span {
+            spans.push(json_span(file, program, span, true, None));
+        }
+        if let Some((span, message)) = &self.note {
+            spans.push(json_span(file, program, *span, false, Some(message)));
+        }
+        let notes = match &self.note {
+            Some((_, message)) => vec![Json::str(message)],
+            None => vec![],
+        };
+        Json::object(vec![
+            ("severity", Json::str(self.severity.name())),
+            ("code", Json::str(self.kind.code())),
+            ("message", Json::str(&self.message)),
+            ("spans", Json::Array(spans)),
+            ("notes", Json::Array(notes)),
+        ])
+    }
+    // errors and warnings go to stderr, so stdout holds only the
+    // assembly; `json` prints each one as a JSON object on its own line
+    pub fn emit(&self, file: &str, program: &str, json: bool) {
+        for err in self.errors() {
+            if json {
+                eprintln!("{}", err.json(file, program));
+            } else {
+                eprint!("{}", err.text(program));
+            }
+        }
+    }
+    pub fn report(&self, program: &str) {
+        self.emit("", program, false)
     }
-    println!("^");
 }
 
-pub fn message_error(message: &str) {
-    println!("Error: {}", message);
-}
+pub type CompileResult<T> = Result<T, CompileError>;
 
 #[derive(Debug, Clone)]
 struct Env<T, V> {
@@ -81,7 +323,7 @@ impl<T: std::cmp::Eq + std::hash::Hash + std::fmt::Debug, V: std::fmt::Debug> En
         }
     }
     fn dec(&mut self) {
-        let env = std::mem::replace(&mut (*self).prev, None);
+        let env = self.prev.take();
         *self = *env.unwrap()
     }
     fn addval(&mut self, key: T, value: V) {
@@ -95,8 +337,6 @@ impl<T: std::cmp::Eq + std::hash::Hash + std::fmt::Debug, V: std::fmt::Debug> En
             } else {
                 match nenv.prev {
                     None => {
-                        message_error(&format!(" {:?} is not defined. ", key));
-                        panic!("{:?}", &self);
                         return None;
                     }
                     Some(ref next_env) => {
@@ -106,24 +346,31 @@ impl<T: std::cmp::Eq + std::hash::Hash + std::fmt::Debug, V: std::fmt::Debug> En
             }
         }
     }
+    fn get(&self, key: &T) -> CompileResult<&V> {
+        match self.find(key) {
+            Some(value) => Ok(value),
+            None => Err(CompileError::internal(&format!(
+                "{:?} is not defined.",
+                key
+            ))),
+        }
+    }
 }
 
 impl Env<NV, FV> {
-    fn efind(&self, key: &NV) -> FV {
+    fn efind(&self, key: &NV) -> CompileResult<FV> {
         if let normal::Value::Intv(v) = key {
-            return FV::Intv(*v);
+            return Ok(FV::Intv(*v));
         }
-        self.find(key).unwrap().clone()
+        self.get(key).cloned()
     }
 }
 
 impl Env<String, Vec<vm::Byte>> {
     fn is_dummy(&self) -> bool {
-        let dm = self.find(&String::from("$$$dummy")).unwrap();
-        if dm.len() == 1 && dm[0] == -100 {
-            return true;
-        } else {
-            panic!(" $$$dummy is not defined. ")
+        match self.find(&String::from("$$$dummy")) {
+            Some(dm) => dm.len() == 1 && dm[0] == -100,
+            None => false,
         }
     }
 }
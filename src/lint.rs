@@ -0,0 +1,147 @@
+use super::parser::*;
+use super::*;
+
+struct Binder {
+    name: Id,
+    span: Span,
+    // false while the definition of a `let` or `loop` is being walked
+    visible: bool,
+    used: bool,
+}
+
+struct Lint {
+    scope: Vec<Binder>,
+    warnings: Vec<CompileError>,
+}
+
+// names starting with `_` are deliberately unused or shadowing
+fn silenced(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+impl Lint {
+    fn bind(&mut self, name: &str, span: Span, visible: bool) {
+        if !silenced(name) {
+            if let Some(outer) = self.scope.iter().rev().find(|b| b.name == name) {
+                self.warnings.push(
+                    CompileError::warning(
+                        ErrorKind::Shadowed,
+                        format!("`{}` shadows an earlier binding.", name),
+                        span,
+                    )
+                    .with_note(outer.span, String::from("the earlier binding is here.")),
+                );
+            }
+        }
+        self.scope.push(Binder {
+            name: name.to_string(),
+            span,
+            visible,
+            used: false,
+        });
+    }
+    fn unbind(&mut self, what: &str) {
+        let binder = self.scope.pop().unwrap();
+        if !binder.used && !silenced(&binder.name) {
+            self.warnings.push(CompileError::warning(
+                ErrorKind::Unused,
+                format!(
+                    "unused {} `{}`; prefix it with `_` to silence this.",
+                    what, binder.name
+                ),
+                binder.span,
+            ));
+        }
+    }
+    fn show(&mut self) {
+        self.scope.last_mut().unwrap().visible = true;
+    }
+    fn use_var(&mut self, id: &str) {
+        if let Some(binder) = self
+            .scope
+            .iter_mut()
+            .rev()
+            .find(|b| b.visible && b.name == id)
+        {
+            binder.used = true;
+        }
+    }
+    fn walk(&mut self, ast: &Ast) {
+        match ast {
+            Ast::Nonaexpr | Ast::Error(..) | Ast::ILit(..) | Ast::BLit(..) => {}
+            Ast::Var(id, _) => self.use_var(id),
+            Ast::Binop(_, ast1, ast2, _) | Ast::App(ast1, ast2, _) | Ast::Tuple(ast1, ast2, _) => {
+                self.walk(ast1);
+                self.walk(ast2);
+            }
+            Ast::If(ast1, ast2, ast3, _) => {
+                self.walk(ast1);
+                self.walk(ast2);
+                self.walk(ast3);
+            }
+            Ast::Recur(ast1, _) | Ast::Proj(ast1, _, _) => self.walk(ast1),
+            Ast::Fun((id, span), body, _) => {
+                self.bind(id, *span, true);
+                self.walk(body);
+                self.unbind("parameter");
+            }
+            Ast::Let((id, span), ast1, ast2, _) | Ast::Loop((id, span), ast1, ast2, _) => {
+                self.bind(id, *span, false);
+                self.walk(ast1);
+                self.show();
+                self.walk(ast2);
+                self.unbind("variable");
+            }
+            Ast::Rec((id1, span1), (id2, span2), body, ast2, _) => {
+                self.bind(id1, *span1, true);
+                self.bind(id2, *span2, true);
+                self.walk(body);
+                self.unbind("parameter");
+                // calls from its own body do not make a function used
+                self.scope.last_mut().unwrap().used = false;
+                self.walk(ast2);
+                self.unbind("function");
+            }
+        }
+    }
+    // names bound by items may be used from other modules and units, so
+    // only what is local to their definitions is checked
+    fn items(&mut self, items: &[Item]) {
+        for item in items {
+            match item {
+                Item::Let(_, ast, _) => self.walk(ast),
+                Item::Rec(_, (arg, span), body, _) => {
+                    self.bind(arg, *span, true);
+                    self.walk(body);
+                    self.unbind("parameter");
+                }
+                Item::Module(_, items, _) => self.items(items),
+                Item::Open(..) | Item::External(..) => {}
+            }
+        }
+    }
+    fn finish(mut self) -> Vec<CompileError> {
+        self.warnings.sort_by_key(|w| w.span.map(|span| span.lo));
+        self.warnings
+    }
+}
+
+// warnings about unused and shadowed bindings of `program`
+pub fn lint(program: &Program) -> Vec<CompileError> {
+    let mut lint = Lint {
+        scope: vec![],
+        warnings: vec![],
+    };
+    lint.items(&program.items);
+    lint.walk(&program.body);
+    lint.finish()
+}
+
+pub fn lint_unit(items: &[Item]) -> Vec<CompileError> {
+    let mut lint = Lint {
+        scope: vec![],
+        warnings: vec![],
+    };
+    lint.items(items);
+    lint.finish()
+}
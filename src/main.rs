@@ -1,53 +1,410 @@
 extern crate ruscaml;
 
-use ruscaml::closure::*;
-use ruscaml::codegen::*;
-use ruscaml::flat::*;
-use ruscaml::lexer::*;
-use ruscaml::normal::*;
-use ruscaml::parser::*;
-use ruscaml::regalloc::*;
-use ruscaml::vm::*;
-
-// use std::io::{BufWriter, Write};
-// use std::env;
-// use std::fs;
+use ruscaml::eval::{eval, eval_closure, eval_flat};
+use ruscaml::format::format;
+use ruscaml::session::PASSES;
+use ruscaml::stats::passes_text;
+use ruscaml::{Artifact, CompileError, Session, Stage};
 
-fn main() {
-    // let args: Vec<String> = env::args().collect();
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+const USAGE: &str = "\
+Usage: ruscaml [options] <file>...
+       ruscaml fmt [--check] <file>...
+       ruscaml eval [--stage=<stage>] <file>...
+
+Compiles each file to arm64 assembly. A file named `-` is read from stdin.
+`ruscaml fmt` rewrites each file in its canonical layout, or prints it
+if it is read from stdin; with `--check` it only reports the files that
+are not formatted. `ruscaml eval` runs each program in an interpreter
+and prints its value; `--stage=closure` or `--stage=flat` runs the
+program as that stage leaves it instead of its syntax tree.
+
+Options:
+    -o <file>              write the output to <file> instead of stdout
+    -c                     compile units without `_toplevel`, exporting
+                           their functions
+    --emit=<stage>         stop after <stage> and print what it produced:
+                           tokens, ast, normal, closure, flat, vm,
+                           vm-alloc or asm (the default); vm-dot and
+                           vm-alloc-dot print the control-flow graph of
+                           the vm code in Graphviz format
+    --emit-format=<fmt>    print the stage as `text` (the default) or, for
+                           ast to vm-alloc, as `json`
+    --error-format=<fmt>   print errors as `human` text (the default) or
+                           as one `json` object per line
+    --time-passes          print the wall time of each pass to stderr
+    --stats                print the sizes of the generated code to stderr
+    --dump-after=<pass>    print the program as <pass> leaves it to stderr;
+                           <pass> is one of lex, parse, lint, resolve,
+                           rename, check_loops, typecheck, normalize,
+                           closure, flat, trans_pg, regalloc or codegen,
+                           and the option can be repeated
+    --dump-to=<file>       write the dumps to <file> instead of stderr
+    --verify-ir            check the invariants of each IR after the pass
+                           that builds it
+    -h, --help             print this message
 
-    let tokenset = lex();
+Exit status is 0 on success, 1 if a file has errors or is not formatted
+and 2 on bad usage or a file that cannot be read or written.
+";
 
-    // println!("{:?}", tokenset.tokens);
+// a compile error in the input
+const EXIT_ERROR: i32 = 1;
+// bad arguments or a failed read or write
+const EXIT_USAGE: i32 = 2;
 
-    let ast = parse(tokenset);
+struct Options {
+    inputs: Vec<String>,
+    output: Option<String>,
+    unit: bool,
+    emit: Stage,
+    json: bool,
+    emit_json: bool,
+    emit_dot: bool,
+    time_passes: bool,
+    stats: bool,
+    dump_after: Vec<String>,
+    dump_to: Option<String>,
+    verify_ir: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("ruscaml: {}", message);
+    eprintln!("Try `ruscaml --help` for more information.");
+    process::exit(EXIT_USAGE);
+}
 
-    let norm_ast = normalize(ast);
-    // norm_ast.program_display();
+fn parse_args(args: impl Iterator<Item = String>) -> Options {
+    let mut options = Options {
+        inputs: vec![],
+        output: None,
+        unit: false,
+        emit: Stage::Asm,
+        json: false,
+        emit_json: false,
+        emit_dot: false,
+        time_passes: false,
+        stats: false,
+        dump_after: vec![],
+        dump_to: None,
+        verify_ir: false,
+    };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            "-c" => options.unit = true,
+            "--time-passes" => options.time_passes = true,
+            "--stats" => options.stats = true,
+            "--verify-ir" => options.verify_ir = true,
+            "-o" => match args.next() {
+                Some(file) => options.output = Some(file),
+                None => usage_error("`-o` needs a file name."),
+            },
+            "-" => options.inputs.push(arg),
+            _ if arg.starts_with("--emit=") => {
+                let name = &arg["--emit=".len()..];
+                // the control-flow graph of the vm code before or after
+                // register allocation
+                let (name, dot) = match name {
+                    "vm-dot" => ("vm", true),
+                    "vm-alloc-dot" => ("vm-alloc", true),
+                    _ => (name, false),
+                };
+                options.emit_dot = dot;
+                options.emit = match Stage::from_name(name) {
+                    Some(stage) => stage,
+                    None => usage_error(&format!("unknown stage `{}` for `--emit`.", name)),
+                };
+            }
+            _ if arg.starts_with("--dump-after=") => {
+                let pass = &arg["--dump-after=".len()..];
+                if !PASSES.contains(&pass) {
+                    usage_error(&format!("unknown pass `{}` for `--dump-after`.", pass));
+                }
+                options.dump_after.push(pass.to_string());
+            }
+            _ if arg.starts_with("--dump-to=") => {
+                options.dump_to = Some(arg["--dump-to=".len()..].to_string());
+            }
+            _ if arg.starts_with("--emit-format=") => {
+                options.emit_json = match &arg["--emit-format=".len()..] {
+                    "text" => false,
+                    "json" => true,
+                    format => usage_error(&format!("unknown emit format `{}`.", format)),
+                };
+            }
+            _ if arg.starts_with("--error-format=") => {
+                options.json = match &arg["--error-format=".len()..] {
+                    "human" => false,
+                    "json" => true,
+                    format => usage_error(&format!("unknown error format `{}`.", format)),
+                };
+            }
+            _ if arg.starts_with('-') => usage_error(&format!("unknown option `{}`.", arg)),
+            _ => options.inputs.push(arg),
+        }
+    }
+    if options.inputs.is_empty() {
+        usage_error("no input files.");
+    }
+    if options.output.is_some() && options.inputs.len() > 1 {
+        usage_error("`-o` cannot be used with several input files.");
+    }
+    if options.emit_json && [Stage::Tokens, Stage::Asm].contains(&options.emit) {
+        usage_error(&format!(
+            "`--emit={}` has no JSON form.",
+            options.emit.name()
+        ));
+    }
+    if options.emit_json && options.emit_dot {
+        usage_error(&format!(
+            "`--emit={}-dot` has no JSON form.",
+            options.emit.name()
+        ));
+    }
+    if options.unit && options.inputs.iter().any(|input| input == "-") {
+        usage_error("a unit is named after its file, so it cannot be read from stdin.");
+    }
+    options
+}
 
-    let closed_norm = closure(norm_ast);
-    // closed_norm.program_display();
+fn read_input(input: &str) -> io::Result<String> {
+    if input == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        Ok(source)
+    } else {
+        fs::read_to_string(input)
+    }
+}
 
-    let flatten_form = flat(closed_norm);
-    // flatten_form.program_display();
+// compiles `input` and writes the result; false if it has errors
+fn run(input: &str, options: &Options) -> bool {
+    let source = match read_input(input) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("ruscaml: cannot read `{}`: {}.", input, err);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    let mut session = Session::new(if input == "-" { "<stdin>" } else { input });
+    session.unit = options.unit;
+    session.dump_after = options.dump_after.clone();
+    session.verify_ir = options.verify_ir;
 
-    let mut virtual_code = trans_pg(flatten_form);
-    // virtual_code.program_display(false);
+    let result = session.compile_to(&source, options.emit);
+    if let Some(warnings) = CompileError::collect(session.warnings.clone()) {
+        session.emit(&warnings, options.json);
+    }
+    if (options.time_passes || options.stats) && options.inputs.len() > 1 {
+        eprintln!("{}:", input);
+    }
+    if options.time_passes {
+        eprint!("{}", passes_text(&session.passes));
+    }
+    if options.stats {
+        eprint!("{}", session.stats);
+    }
+    write_dumps(input, &session, options);
+    let out = match result {
+        Ok(artifact) => match (artifact.json(), artifact.dot()) {
+            (Some(json), _) if options.emit_json => format!("{}\n", json),
+            (_, Some(dot)) if options.emit_dot => dot,
+            _ => artifact.text(&session.source),
+        },
+        Err(err) => {
+            session.emit(&err, options.json);
+            return false;
+        }
+    };
+    match &options.output {
+        Some(file) => {
+            if let Err(err) = fs::write(file, out) {
+                eprintln!("ruscaml: cannot write `{}`: {}.", file, err);
+                process::exit(EXIT_USAGE);
+            }
+        }
+        None => {
+            let _ = io::stdout().write_all(out.as_bytes());
+        }
+    }
+    true
+}
 
-    regalloc(&mut virtual_code);
-    // virtual_code.program_display(true);
+// the dumps are written even if a later pass failed
+fn write_dumps(input: &str, session: &Session, options: &Options) {
+    if session.dumps.is_empty() {
+        return;
+    }
+    let mut text = String::new();
+    for (pass, dump) in &session.dumps {
+        text.push_str(&format!("=== {}: after {} ===\n{}", input, pass, dump));
+    }
+    match &options.dump_to {
+        Some(file) => {
+            let written = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .and_then(|mut out| out.write_all(text.as_bytes()));
+            if let Err(err) = written {
+                eprintln!("ruscaml: cannot write `{}`: {}.", file, err);
+                process::exit(EXIT_USAGE);
+            }
+        }
+        None => eprint!("{}", text),
+    }
+}
 
-    codegen(virtual_code);
+// `ruscaml fmt`; false if a file has errors or, with `--check`, is not
+// formatted
+fn fmt(args: impl Iterator<Item = String>) -> bool {
+    let mut check = false;
+    let mut inputs = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            "--check" => check = true,
+            "-" => inputs.push(arg),
+            _ if arg.starts_with('-') => usage_error(&format!("unknown option `{}`.", arg)),
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        usage_error("no input files.");
+    }
+    let mut ok = true;
+    for input in &inputs {
+        let source = match read_input(input) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("ruscaml: cannot read `{}`: {}.", input, err);
+                process::exit(EXIT_USAGE);
+            }
+        };
+        let formatted = match format(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                err.emit(input, &source, false);
+                ok = false;
+                continue;
+            }
+        };
+        if check {
+            if formatted != source {
+                eprintln!("ruscaml: `{}` is not formatted.", input);
+                ok = false;
+            }
+        } else if input == "-" {
+            let _ = io::stdout().write_all(formatted.as_bytes());
+        } else if formatted != source {
+            if let Err(err) = fs::write(input, formatted) {
+                eprintln!("ruscaml: cannot write `{}`: {}.", input, err);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    ok
+}
 
-    // let mut f = BufWriter::new(fs::File::create("a.s").unwrap());
+// `ruscaml eval`; false if a program has errors or cannot be evaluated
+fn eval_files(args: impl Iterator<Item = String>) -> bool {
+    let mut stage = Stage::Ast;
+    let mut inputs = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            "-" => inputs.push(arg),
+            _ if arg.starts_with("--stage=") => {
+                let name = &arg["--stage=".len()..];
+                stage = match Stage::from_name(name) {
+                    Some(stage @ (Stage::Ast | Stage::Closure | Stage::Flat)) => stage,
+                    _ => usage_error(&format!("unknown stage `{}` for `--stage`.", name)),
+                };
+            }
+            _ if arg.starts_with('-') => usage_error(&format!("unknown option `{}`.", arg)),
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        usage_error("no input files.");
+    }
+    let mut ok = true;
+    for input in &inputs {
+        let source = match read_input(input) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("ruscaml: cannot read `{}`: {}.", input, err);
+                process::exit(EXIT_USAGE);
+            }
+        };
+        let mut session = Session::new(if input == "-" { "<stdin>" } else { input });
+        let value = match stage {
+            Stage::Ast => session
+                .check(&source)
+                .and_then(|(ast, link)| eval(&ast, &link).map(|value| value.to_string())),
+            _ => session
+                .compile_to(&source, stage)
+                .and_then(|artifact| match artifact {
+                    Artifact::Closure(exp) => eval_closure(&exp, &session.link),
+                    Artifact::Flat(program) => eval_flat(&program, &session.link),
+                    _ => unreachable!(),
+                })
+                .map(|word| word.to_string()),
+        };
+        if let Some(warnings) = CompileError::collect(session.warnings.clone()) {
+            session.emit(&warnings, false);
+        }
+        match value {
+            Ok(value) => println!("{}", value),
+            Err(err) => {
+                session.emit(&err, false);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
 
-    // f.write(b".text\n").unwrap();
-    // f.write(b".global main\n").unwrap();
-    // f.write(b"main:\n").unwrap();
-    // f.write(b"\tpush %rbp\n").unwrap();
-    // f.write(b"\tmov %rsp, %rbp\n").unwrap();
-    // f.write(b"\tmov $1, %rax\n").unwrap();
-    // f.write(b"\tmov %rbp, %rsp\n").unwrap();
-    // f.write(b"\tpop %rbp\n").unwrap();
-    // f.write(b"\tret\n").unwrap();
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("fmt") => {
+            args.next();
+            if !fmt(args) {
+                process::exit(EXIT_ERROR);
+            }
+            return;
+        }
+        Some("eval") => {
+            args.next();
+            if !eval_files(args) {
+                process::exit(EXIT_ERROR);
+            }
+            return;
+        }
+        _ => {}
+    }
+    let options = parse_args(args);
+    let mut ok = true;
+    for input in &options.inputs {
+        ok &= run(input, &options);
+    }
+    if !ok {
+        process::exit(EXIT_ERROR);
+    }
 }
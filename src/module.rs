@@ -0,0 +1,363 @@
+use super::parser::*;
+use super::*;
+
+use std::collections::HashSet;
+
+#[derive(Clone, Default)]
+struct Structure {
+    path: Id,
+    vals: Vec<(Id, Id)>,
+    mods: Vec<(Id, Id)>,
+}
+
+// a structure being resolved: `visible` holds every name in scope,
+// including the outer ones and those brought in by `open`, while
+// `defined` holds only the members the structure exports
+#[derive(Default)]
+struct Scope {
+    visible: Structure,
+    defined: Structure,
+}
+
+impl Scope {
+    fn new(path: &str) -> Self {
+        let structure = Structure {
+            path: path.to_string(),
+            vals: vec![],
+            mods: vec![],
+        };
+        Self {
+            visible: structure.clone(),
+            defined: structure,
+        }
+    }
+    fn define_val(&mut self, id: Id, name: Id) {
+        self.visible.vals.push((id.clone(), name.clone()));
+        self.defined.vals.push((id, name));
+    }
+    fn define_mod(&mut self, id: Id, path: Id) {
+        self.visible.mods.push((id.clone(), path.clone()));
+        self.defined.mods.push((id, path));
+    }
+}
+
+struct Resolver<'a> {
+    link: &'a mut Linkage,
+    structures: Vec<Structure>,
+    used: HashSet<Id>,
+    // flattened members, bound around the program body in order
+    binds: Vec<Item>,
+    // unbound names, reported together once the program is resolved
+    errors: Vec<CompileError>,
+}
+
+fn find<'a>(scope: &'a [(Id, Id)], id: &str) -> Option<&'a Id> {
+    scope
+        .iter()
+        .rev()
+        .find(|(short, _)| short == id)
+        .map(|(_, long)| long)
+}
+
+// edit distance between `a` and `b`, counting characters
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(diag + (ca != *cb) as usize);
+            diag = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+// the closest of `names` to the misspelled `id`, innermost first on ties
+fn suggest<'a>(id: &str, names: impl Iterator<Item = &'a Id>) -> Option<&'a Id> {
+    // one edit per three characters, so one-letter names get no suggestion
+    let limit = (id.chars().count() + 1) / 3;
+    let mut best: Option<(usize, &Id)> = None;
+    for name in names {
+        let d = distance(id, name);
+        if d > 0 && d <= limit && best.is_none_or(|(bd, _)| d < bd) {
+            best = Some((d, name));
+        }
+    }
+    best.map(|(_, name)| name)
+}
+
+fn unbound(what: &str, id: &str, suggestion: Option<String>, span: Span) -> CompileError {
+    let message = match suggestion {
+        Some(name) => format!("unbound {} `{}`; did you mean `{}`?", what, id, name),
+        None => format!("unbound {} `{}`.", what, id),
+    };
+    CompileError::new(ErrorKind::Name, message, span)
+}
+
+impl<'a> Resolver<'a> {
+    fn new(link: &'a mut Linkage) -> Self {
+        Self {
+            link,
+            structures: vec![],
+            used: HashSet::new(),
+            binds: vec![],
+            errors: vec![],
+        }
+    }
+    fn structure(&self, path: &str) -> &Structure {
+        self.structures.iter().find(|st| st.path == path).unwrap()
+    }
+    // unique top-level name for member `id` of the structure at `path`
+    fn mangle(&mut self, path: &str, id: &str) -> Id {
+        let base = if path.is_empty() {
+            id.to_string()
+        } else {
+            format!("{}.{}", path, id)
+        };
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.used.contains(&name) {
+            name = format!("{}.{}", base, n);
+            n += 1;
+        }
+        self.used.insert(name.clone());
+        name
+    }
+    // a module of another compilation unit, known from its interface
+    // file, or `None` if there is no such file
+    fn import(&mut self, head: &str) -> CompileResult<Option<Id>> {
+        if !self.structures.iter().any(|st| st.path == head) {
+            let vals = match unit::load_signature(head, self.link)? {
+                Some(vals) => vals,
+                None => return Ok(None),
+            };
+            let mut structure = Structure {
+                path: head.to_string(),
+                vals: vec![],
+                mods: vec![],
+            };
+            for (id, ty) in vals {
+                let name = format!("{}.{}", head, id);
+                self.link.add_global(&name, Some(ty), false);
+                structure.vals.push((id, name));
+            }
+            self.structures.push(structure);
+        }
+        Ok(Some(head.to_string()))
+    }
+    // the structure `id` names, or `None` once an unbound module in it
+    // has been reported
+    fn module_path(
+        &mut self,
+        scope: &Structure,
+        id: &str,
+        span: Span,
+    ) -> CompileResult<Option<Id>> {
+        let mut names = id.split('.');
+        let head = names.next().unwrap();
+        let mut path = match find(&scope.mods, head) {
+            Some(path) => path.clone(),
+            None => match self.import(head)? {
+                Some(path) => path,
+                None => {
+                    let mods = scope.mods.iter().rev().map(|(short, _)| short);
+                    let suggestion = suggest(head, mods).cloned();
+                    self.errors.push(unbound("module", head, suggestion, span));
+                    return Ok(None);
+                }
+            },
+        };
+        for name in names {
+            let mods = &self.structure(&path).mods;
+            path = match find(mods, name) {
+                Some(path) => path.clone(),
+                None => {
+                    let suggestion = suggest(name, mods.iter().rev().map(|(short, _)| short))
+                        .map(|short| format!("{}.{}", path, short));
+                    let id = format!("{}.{}", path, name);
+                    self.errors.push(unbound("module", &id, suggestion, span));
+                    return Ok(None);
+                }
+            };
+        }
+        Ok(Some(path))
+    }
+    fn items(&mut self, items: Vec<Item>, scope: &mut Scope) -> CompileResult<()> {
+        let path = scope.defined.path.clone();
+        for item in items {
+            match item {
+                Item::Let((id, bspan), ast, span) => {
+                    let ast = self.expr(ast, &scope.visible, &mut vec![])?;
+                    let name = self.mangle(&path, &id);
+                    self.binds.push(Item::Let((name.clone(), bspan), ast, span));
+                    scope.define_val(id, name);
+                }
+                Item::Rec((id, bspan), arg, ast, span) => {
+                    let name = self.mangle(&path, &id);
+                    scope.define_val(id, name.clone());
+                    let locals = &mut vec![(arg.0.clone(), arg.0.clone())];
+                    let ast = self.expr(ast, &scope.visible, locals)?;
+                    self.binds.push(Item::Rec((name, bspan), arg, ast, span));
+                }
+                Item::Module(id, items, span) => {
+                    let subpath = if path.is_empty() {
+                        id.clone()
+                    } else {
+                        format!("{}.{}", path, id)
+                    };
+                    let duplicate = self.structures.iter().any(|st| st.path == subpath);
+                    if duplicate {
+                        self.errors.push(CompileError::new(
+                            ErrorKind::Module,
+                            format!("module `{}` is already defined.", subpath),
+                            span,
+                        ));
+                    }
+                    let mut sub = Scope::new(&subpath);
+                    sub.visible.vals = scope.visible.vals.clone();
+                    sub.visible.mods = scope.visible.mods.clone();
+                    self.items(items, &mut sub)?;
+                    // a duplicate is still resolved for its errors, but the
+                    // first definition is the one its name refers to
+                    if !duplicate {
+                        // only the members defined inside the structure are exported
+                        self.structures.push(sub.defined);
+                        scope.define_mod(id, subpath);
+                    }
+                }
+                Item::External(id, ty, symbol, span) => {
+                    // the prefix keeps the name apart from every source identifier
+                    let name = self.mangle(&path, &format!("external.{}", id));
+                    self.link.add_external(&name, ty, symbol, span)?;
+                    scope.define_val(id, name);
+                }
+                Item::Open(id, span) => {
+                    let opened = match self.module_path(&scope.visible, &id, span)? {
+                        Some(opened) => opened,
+                        None => continue,
+                    };
+                    let st = self.structure(&opened).clone();
+                    scope.visible.vals.extend(st.vals);
+                    scope.visible.mods.extend(st.mods);
+                }
+            }
+        }
+        Ok(())
+    }
+    // `locals` holds the binders of the expression, which shadow structure members
+    fn expr(
+        &mut self,
+        ast: Ast,
+        scope: &Structure,
+        locals: &mut Vec<(Id, Id)>,
+    ) -> CompileResult<Ast> {
+        macro_rules! sub {
+            ($ast: expr) => {
+                Box::new(self.expr(*$ast, scope, locals)?)
+            };
+        }
+        macro_rules! bound {
+            ($ids: expr, $ast: expr) => {{
+                let n = locals.len();
+                for id in $ids {
+                    locals.push((id.clone(), id.clone()));
+                }
+                let ast = self.expr(*$ast, scope, locals);
+                locals.truncate(n);
+                Box::new(ast?)
+            }};
+        }
+        Ok(match ast {
+            Ast::Var(id, span) => {
+                if let Some(local) = find(locals, &id) {
+                    Ast::Var(local.clone(), span)
+                } else if let Some(dot) = id.rfind('.') {
+                    let path = match self.module_path(scope, &id[..dot], span)? {
+                        Some(path) => path,
+                        None => return Ok(Ast::Var(id, span)),
+                    };
+                    let vals = &self.structure(&path).vals;
+                    match find(vals, &id[dot + 1..]) {
+                        Some(name) => Ast::Var(name.clone(), span),
+                        None => {
+                            let members = vals.iter().rev().map(|(short, _)| short);
+                            let suggestion = suggest(&id[dot + 1..], members)
+                                .map(|short| format!("{}{}", &id[..=dot], short));
+                            self.errors.push(unbound("value", &id, suggestion, span));
+                            Ast::Var(id, span)
+                        }
+                    }
+                } else if let Some(name) = find(&scope.vals, &id) {
+                    Ast::Var(name.clone(), span)
+                } else {
+                    let names = locals.iter().rev().chain(scope.vals.iter().rev());
+                    let suggestion = suggest(&id, names.map(|(short, _)| short)).cloned();
+                    self.errors.push(unbound("variable", &id, suggestion, span));
+                    Ast::Var(id, span)
+                }
+            }
+            Ast::Nonaexpr | Ast::Error(..) | Ast::ILit(..) | Ast::BLit(..) => ast,
+            Ast::Binop(ttype, ast1, ast2, span) => Ast::Binop(ttype, sub!(ast1), sub!(ast2), span),
+            Ast::If(ast1, ast2, ast3, span) => Ast::If(sub!(ast1), sub!(ast2), sub!(ast3), span),
+            Ast::Fun(id, ast1, span) => Ast::Fun(id.clone(), bound!([&id.0], ast1), span),
+            Ast::Let(id, ast1, ast2, span) => {
+                Ast::Let(id.clone(), sub!(ast1), bound!([&id.0], ast2), span)
+            }
+            Ast::Rec(id1, id2, ast1, ast2, span) => Ast::Rec(
+                id1.clone(),
+                id2.clone(),
+                bound!([&id1.0, &id2.0], ast1),
+                bound!([&id1.0], ast2),
+                span,
+            ),
+            Ast::Loop(id, ast1, ast2, span) => {
+                Ast::Loop(id.clone(), sub!(ast1), bound!([&id.0], ast2), span)
+            }
+            Ast::Recur(ast1, span) => Ast::Recur(sub!(ast1), span),
+            Ast::App(ast1, ast2, span) => Ast::App(sub!(ast1), sub!(ast2), span),
+            Ast::Tuple(ast1, ast2, span) => Ast::Tuple(sub!(ast1), sub!(ast2), span),
+            Ast::Proj(ast1, i, span) => Ast::Proj(sub!(ast1), i, span),
+        })
+    }
+}
+
+fn flatten_modules(program: Program, link: &mut Linkage) -> CompileResult<Ast> {
+    let mut resolver = Resolver::new(link);
+    let mut scope = Scope::default();
+    resolver.items(program.items, &mut scope)?;
+    let mut ast = resolver.expr(program.body, &scope.visible, &mut vec![])?;
+    if let Some(err) = CompileError::collect(resolver.errors) {
+        return Err(err);
+    }
+    let span = ast.span();
+    while let Some(bind) = resolver.binds.pop() {
+        ast = match bind {
+            Item::Let(id, ast1, _) => Ast::Let(id, Box::new(ast1), Box::new(ast), span),
+            Item::Rec(id, arg, ast1, _) => Ast::Rec(id, arg, Box::new(ast1), Box::new(ast), span),
+            Item::Module(..) | Item::Open(..) | Item::External(..) => unreachable!(),
+        };
+    }
+    external::check_calls(&ast, resolver.link)?;
+    Ok(ast)
+}
+
+// the members of compilation unit `name`, as `let` and `let rec` items
+// with mangled names
+pub fn flatten_unit(name: &str, items: Vec<Item>, link: &mut Linkage) -> CompileResult<Vec<Item>> {
+    let mut resolver = Resolver::new(link);
+    let mut scope = Scope::new(name);
+    resolver.items(items, &mut scope)?;
+    match CompileError::collect(resolver.errors) {
+        Some(err) => Err(err),
+        None => Ok(resolver.binds),
+    }
+}
+
+pub fn resolve_modules(program: Program, link: &mut Linkage) -> CompileResult<Ast> {
+    flatten_modules(program, link)
+}
@@ -1,25 +1,12 @@
 use super::parser::*;
 use super::*;
 
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
-// use std::fmt;
+use std::fmt;
 
-pub static FRESH_COUNT: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
-
-fn get_fresh_var() -> String {
-    let num = *(FRESH_COUNT).lock().unwrap();
-    *(FRESH_COUNT).lock().unwrap() = num + 1;
-    let mut s = String::from("@v");
-    let numstr = &num.to_string();
-    s.push_str(numstr);
-    s
-}
-
-pub fn duplicate_var(nv: String) -> String {
-    let mut s = String::from("@v");
-    s.push_str(&nv[2..]);
-    s
+// normalizes one program; `fresh` numbers the variables it introduces
+struct Normalizer<'a> {
+    link: &'a Linkage,
+    fresh: i32,
 }
 
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
@@ -28,12 +15,21 @@ pub enum Value {
     Intv(i32),
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Var(id) => write!(f, "{}", id),
+            Value::Intv(v) => write!(f, "{}", v),
+        }
+    }
+}
+
 impl Value {
     fn ast2value(ast: parser::Ast) -> (Option<Value>, Ast) {
         match ast {
-            Ast::ILit(v) => (Some(Value::Intv(v)), ast),
-            Ast::BLit(v) => (Some(Value::Intv(if v { 1 } else { 0 })), ast),
-            Ast::Var(v) => (Some(Value::Var(v.clone())), Ast::Var(v)),
+            Ast::ILit(v, _) => (Some(Value::Intv(v)), ast),
+            Ast::BLit(v, _) => (Some(Value::Intv(if v { 1 } else { 0 })), ast),
+            Ast::Var(v, span) => (Some(Value::Var(v.clone())), Ast::Var(v, span)),
             _ => (None, ast),
         }
     }
@@ -47,46 +43,25 @@ pub enum Cexp {
     If(Value, Box<Exp>, Box<Exp>),
     Tuple(Value, Value),
     Proj(Value, i32),
+    Extcall(String, Vec<Value>),
 }
 
-impl Cexp {
-    pub fn program_display(self) {
+impl fmt::Display for Cexp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Cexp::*;
         match self {
-            Val(Value::Var(id)) => {
-                print!("{}", id);
-            }
-            Val(Value::Intv(v)) => {
-                print!("{}", v);
-            }
-            Binop(tty, val1, val2) => {
-                Val(val1).program_display();
-                print!("{}", tty.bintype_signal());
-                Val(val2).program_display();
-            }
-            App(val1, val2) => {
-                Val(val1).program_display();
-                print!(" ");
-                Val(val2).program_display();
-            }
-            If(val, exp1, exp2) => {
-                print!("if ");
-                Val(val).program_display();
-                print!(" then ");
-                exp1.program_display();
-                print!(" else ");
-                exp2.program_display();
-            }
-            Tuple(val1, val2) => {
-                print!("(");
-                Val(val1).program_display();
-                print!(", ");
-                Val(val2).program_display();
-                print!(")");
-            }
-            Proj(val, i) => {
-                Val(val).program_display();
-                print!(".{}", i);
+            Val(val) => write!(f, "{}", val),
+            Binop(tty, val1, val2) => write!(f, "{}{}{}", val1, tty.bintype_signal(), val2),
+            App(val1, val2) => write!(f, "{} {}", val1, val2),
+            If(val, exp1, exp2) => write!(f, "if {} then {} else {}", val, exp1, exp2),
+            Tuple(val1, val2) => write!(f, "({}, {})", val1, val2),
+            Proj(val, i) => write!(f, "{}.{}", val, i),
+            Extcall(symbol, vals) => {
+                write!(f, "{}", symbol)?;
+                for val in vals {
+                    write!(f, " {}", val)?;
+                }
+                Ok(())
             }
         }
     }
@@ -94,49 +69,28 @@ impl Cexp {
 
 #[derive(Clone, Debug)]
 pub enum Exp {
-    Compexp(Box<Cexp>),
-    Let(Id, Box<Cexp>, Box<Exp>),
-    Loop(Id, Box<Cexp>, Box<Exp>),
-    Letrec(Id, Id, Box<Exp>, Box<Exp>),
-    Recur(Value),
+    Compexp(Box<Cexp>, Span),
+    Let(Id, Box<Cexp>, Box<Exp>, Span),
+    Loop(Id, Box<Cexp>, Box<Exp>, Span),
+    Letrec(Id, Id, Box<Exp>, Box<Exp>, Span),
+    Recur(Value, Span),
 }
 
-impl Exp {
-    pub fn program_display(self) {
+impl fmt::Display for Exp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Exp::*;
         match self {
-            Compexp(cexp) => {
-                cexp.program_display();
-            }
-            Let(id, cexp, exp) => {
-                print!("let {} = ", id);
-                cexp.program_display();
-                print!(" in\n");
-                exp.program_display();
-            }
-            Loop(id, cexp, exp) => {
-                print!("loop {} = ", id);
-                cexp.program_display();
-                print!(" in\n");
-                exp.program_display();
-            }
-            Letrec(id1, id2, exp1, exp2) => {
-                print!("let rec {} = fun ", id1);
-                print!("{} -> ", id2);
-                match *exp1 {
-                    Let(..) | Letrec(..) | Loop(..) => {
-                        print!("\n");
-                    }
-                    _ => {}
+            Compexp(cexp, _) => write!(f, "{}", cexp),
+            Let(id, cexp, exp, _) => write!(f, "let {} = {} in\n{}", id, cexp, exp),
+            Loop(id, cexp, exp, _) => write!(f, "loop {} = {} in\n{}", id, cexp, exp),
+            Letrec(id1, id2, exp1, exp2, _) => {
+                write!(f, "let rec {} = fun {} -> ", id1, id2)?;
+                if let Let(..) | Letrec(..) | Loop(..) = **exp1 {
+                    writeln!(f)?;
                 }
-                exp1.program_display();
-                print!(" in\n");
-                exp2.program_display();
-            }
-            Recur(val) => {
-                print!("recur ");
-                Cexp::Val(val).program_display();
+                write!(f, "{} in\n{}", exp1, exp2)
             }
+            Recur(val, _) => write!(f, "recur {}", val),
         }
     }
 }
@@ -150,7 +104,15 @@ pub enum Bintype {
 }
 
 impl Bintype {
-    pub fn bintype_signal(self) -> char {
+    pub const ALL: [Bintype; 4] = [Bintype::Plus, Bintype::Mult, Bintype::Lt, Bintype::Eq];
+    // the operator `bintype_signal` writes as `signal`
+    pub fn from_signal(signal: char) -> Option<Bintype> {
+        Bintype::ALL
+            .iter()
+            .find(|btype| btype.bintype_signal() == signal)
+            .cloned()
+    }
+    pub fn bintype_signal(&self) -> char {
         use Bintype::*;
         match self {
             Plus => '+',
@@ -161,251 +123,539 @@ impl Bintype {
     }
 }
 
-fn ttype2btype(ttype: TokenType) -> Bintype {
+fn ttype2btype(ttype: TokenType) -> CompileResult<Bintype> {
     match ttype {
-        TokenType::Plus => Bintype::Plus,
-        TokenType::Mult => Bintype::Mult,
-        TokenType::Lt => Bintype::Lt,
-        TokenType::Eq => Bintype::Eq,
-        _ => {
-            panic!("ttyep2btype error.")
-        }
+        TokenType::Plus => Ok(Bintype::Plus),
+        TokenType::Mult => Ok(Bintype::Mult),
+        TokenType::Lt => Ok(Bintype::Lt),
+        TokenType::Eq => Ok(Bintype::Eq),
+        _ => Err(CompileError::internal(&format!(
+            "`{}` is not a binary operator.",
+            ttype.text()
+        ))),
     }
 }
 
-fn norm_exp(ast: Ast, fid: usize, fs: &mut Vec<AsgFun>) -> Exp {
-    match ast {
-        Ast::ILit(v) => fs[fid].apply()(Cexp::Val(Value::Intv(v))),
-        Ast::BLit(v) => fs[fid].apply()(Cexp::Val(Value::Intv(if v { 1 } else { 0 }))),
-        Ast::Binop(ttype, ast1, ast2) => {
-            let (val1, _ast1) = Value::ast2value(*ast1);
-            let (val2, _ast2) = Value::ast2value(*ast2);
-            match (val1, val2) {
-                (Some(v1), Some(v2)) => fs[fid].apply()(Cexp::Binop(ttype2btype(ttype), v1, v2)),
-                (None, Some(_)) => {
-                    let nv1 = get_fresh_var();
-                    norm_exp(
-                        Ast::Let(
-                            nv1.clone(),
-                            Box::new(_ast1),
-                            Box::new(Ast::Binop(ttype, Box::new(Ast::Var(nv1)), Box::new(_ast2))),
-                        ),
-                        fid,
-                        fs,
-                    )
-                }
-                (Some(_), None) => {
-                    let nv2 = get_fresh_var();
-                    norm_exp(
-                        Ast::Let(
-                            nv2.clone(),
-                            Box::new(_ast2),
-                            Box::new(Ast::Binop(ttype, Box::new(_ast1), Box::new(Ast::Var(nv2)))),
-                        ),
-                        fid,
-                        fs,
-                    )
-                }
-                (None, None) => {
-                    let nv1 = get_fresh_var();
-                    let nv2 = get_fresh_var();
-                    norm_exp(
-                        Ast::Let(
-                            nv1.clone(),
-                            Box::new(_ast1),
-                            Box::new(Ast::Let(
-                                nv2.clone(),
+impl Normalizer<'_> {
+    fn get_fresh_var(&mut self) -> String {
+        let num = self.fresh;
+        self.fresh = num + 1;
+        let mut s = String::from("@v");
+        let numstr = &num.to_string();
+        s.push_str(numstr);
+        s
+    }
+    fn norm_exp(&mut self, ast: Ast, fid: usize, fs: &mut Vec<AsgFun>) -> CompileResult<Exp> {
+        Ok(match ast {
+            Ast::ILit(v, span) => fs[fid].apply()(Cexp::Val(Value::Intv(v)), span),
+            Ast::BLit(v, span) => {
+                fs[fid].apply()(Cexp::Val(Value::Intv(if v { 1 } else { 0 })), span)
+            }
+            Ast::Binop(ttype, ast1, ast2, span) => {
+                let (val1, _ast1) = Value::ast2value(*ast1);
+                let (val2, _ast2) = Value::ast2value(*ast2);
+                match (val1, val2) {
+                    (Some(v1), Some(v2)) => {
+                        fs[fid].apply()(Cexp::Binop(ttype2btype(ttype)?, v1, v2), span)
+                    }
+                    (None, Some(_)) => {
+                        let nv1 = self.get_fresh_var();
+                        self.norm_exp(
+                            Ast::Let(
+                                (nv1.clone(), span),
+                                Box::new(_ast1),
+                                Box::new(Ast::Binop(
+                                    ttype,
+                                    Box::new(Ast::Var(nv1, span)),
+                                    Box::new(_ast2),
+                                    span,
+                                )),
+                                span,
+                            ),
+                            fid,
+                            fs,
+                        )?
+                    }
+                    (Some(_), None) => {
+                        let nv2 = self.get_fresh_var();
+                        self.norm_exp(
+                            Ast::Let(
+                                (nv2.clone(), span),
                                 Box::new(_ast2),
                                 Box::new(Ast::Binop(
                                     ttype,
-                                    Box::new(Ast::Var(nv1)),
-                                    Box::new(Ast::Var(nv2)),
+                                    Box::new(_ast1),
+                                    Box::new(Ast::Var(nv2, span)),
+                                    span,
+                                )),
+                                span,
+                            ),
+                            fid,
+                            fs,
+                        )?
+                    }
+                    (None, None) => {
+                        let nv1 = self.get_fresh_var();
+                        let nv2 = self.get_fresh_var();
+                        self.norm_exp(
+                            Ast::Let(
+                                (nv1.clone(), span),
+                                Box::new(_ast1),
+                                Box::new(Ast::Let(
+                                    (nv2.clone(), span),
+                                    Box::new(_ast2),
+                                    Box::new(Ast::Binop(
+                                        ttype,
+                                        Box::new(Ast::Var(nv1, span)),
+                                        Box::new(Ast::Var(nv2, span)),
+                                        span,
+                                    )),
+                                    span,
                                 )),
-                            )),
-                        ),
-                        fid,
-                        fs,
-                    )
+                                span,
+                            ),
+                            fid,
+                            fs,
+                        )?
+                    }
                 }
             }
-        }
-        Ast::If(ast1, ast2, ast3) => {
-            let nv = get_fresh_var();
-            let ass_ins = fs[fid].apply()(Cexp::If(
-                Value::Var(nv.clone()),
-                Box::new(norm_exp(*ast2, 0, fs)),
-                Box::new(norm_exp(*ast3, 0, fs)),
-            ));
-            fs.push(AsgFun::new(Box::new(|ce| {
-                Exp::Let(duplicate_var(nv), Box::new(ce), Box::new(ass_ins))
-            })));
-            norm_exp(*ast1, fs.len() - 1, fs)
-        }
-        Ast::Fun(id, ast1) => {
-            let nv = get_fresh_var();
-            norm_exp(
-                Ast::Rec(nv.clone(), id, Box::new(*ast1), Box::new(Ast::Var(nv))),
-                fid,
-                fs,
-            )
-        }
-        Ast::Var(id) => fs[fid].apply()(Cexp::Val(Value::Var(id))),
-        Ast::Let(id, ast1, ast2) => match Value::ast2value(*ast1) {
-            (Some(val1), _) => Exp::Let(
-                id,
-                Box::new(Cexp::Val(val1)),
-                Box::new(norm_exp(*ast2, fid, fs)),
-            ),
-            (None, _ast1) => {
-                let nast2 = norm_exp(*ast2, fid, fs);
-                fs.push(AsgFun::new(Box::new(|ce| {
-                    Exp::Let(id, Box::new(ce), Box::new(nast2))
-                })));
-                norm_exp(_ast1, fs.len() - 1, fs)
-            }
-        },
-        Ast::Rec(id1, id2, ast1, ast2) => Exp::Letrec(
-            id1,
-            id2,
-            Box::new(norm_exp(*ast1, 0, fs)),
-            Box::new(norm_exp(*ast2, fid, fs)),
-        ),
-        Ast::Loop(id, ast1, ast2) => match Value::ast2value(*ast1) {
-            (Some(val1), _) => Exp::Loop(
-                id,
-                Box::new(Cexp::Val(val1)),
-                Box::new(norm_exp(*ast2, fid, fs)),
-            ),
-            (None, _ast1) => {
-                let nast2 = norm_exp(*ast2, fid, fs);
-                fs.push(AsgFun::new(Box::new(|ce| {
-                    Exp::Loop(id, Box::new(ce), Box::new(nast2))
+            Ast::If(ast1, ast2, ast3, span) => {
+                let nv = self.get_fresh_var();
+                let ass_ins = fs[fid].apply()(
+                    Cexp::If(
+                        Value::Var(nv.clone()),
+                        Box::new(self.norm_exp(*ast2, 0, fs)?),
+                        Box::new(self.norm_exp(*ast3, 0, fs)?),
+                    ),
+                    span,
+                );
+                fs.push(AsgFun::new(Box::new(|ce, span| {
+                    Exp::Let(nv, Box::new(ce), Box::new(ass_ins), span)
                 })));
-                norm_exp(_ast1, fs.len() - 1, fs)
+                self.norm_exp(*ast1, fs.len() - 1, fs)?
             }
-        },
-        Ast::Recur(ast1) => match Value::ast2value(*ast1) {
-            (Some(val1), _) => Exp::Recur(val1),
-            (None, _ast1) => {
-                let nv = get_fresh_var();
-                fs.push(AsgFun::new(Box::new(|ce| {
-                    Exp::Let(
-                        nv.clone(),
-                        Box::new(ce),
-                        Box::new(Exp::Recur(Value::Var(nv))),
-                    )
-                })));
-                norm_exp(_ast1, fs.len() - 1, fs)
+            Ast::Fun(id, ast1, span) => {
+                let nv = self.get_fresh_var();
+                self.norm_exp(
+                    Ast::Rec(
+                        (nv.clone(), span),
+                        id,
+                        Box::new(*ast1),
+                        Box::new(Ast::Var(nv, span)),
+                        span,
+                    ),
+                    fid,
+                    fs,
+                )?
             }
-        },
-        Ast::App(ast1, ast2) => {
-            let (val1, _ast1) = Value::ast2value(*ast1);
-            let (val2, _ast2) = Value::ast2value(*ast2);
-            match (val1, val2) {
-                (Some(v1), Some(v2)) => fs[fid].apply()(Cexp::App(v1, v2)),
-                (Some(v1), _) => {
-                    let nv2 = get_fresh_var();
-                    let ass_ins = fs[fid].apply()(Cexp::App(v1, Value::Var(nv2.clone())));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(nv2, Box::new(ce), Box::new(ass_ins))
+            Ast::Var(id, span) => fs[fid].apply()(Cexp::Val(Value::Var(id)), span),
+            Ast::Let((id, _), ast1, ast2, span) => match Value::ast2value(*ast1) {
+                (Some(val1), _) => Exp::Let(
+                    id,
+                    Box::new(Cexp::Val(val1)),
+                    Box::new(self.norm_exp(*ast2, fid, fs)?),
+                    span,
+                ),
+                (None, _ast1) => {
+                    let nast2 = self.norm_exp(*ast2, fid, fs)?;
+                    fs.push(AsgFun::new(Box::new(|ce, span| {
+                        Exp::Let(id, Box::new(ce), Box::new(nast2), span)
                     })));
-                    norm_exp(_ast2, fs.len() - 1, fs)
+                    self.norm_exp(_ast1, fs.len() - 1, fs)?
                 }
-                (_, Some(v2)) => {
-                    let nv1 = get_fresh_var();
-                    let ass_ins = fs[fid].apply()(Cexp::App(Value::Var(nv1.clone()), v2));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(nv1, Box::new(ce), Box::new(ass_ins))
+            },
+            Ast::Rec((id1, _), (id2, _), ast1, ast2, span) => Exp::Letrec(
+                id1,
+                id2,
+                Box::new(self.norm_exp(*ast1, 0, fs)?),
+                Box::new(self.norm_exp(*ast2, fid, fs)?),
+                span,
+            ),
+            Ast::Loop((id, _), ast1, ast2, span) => match Value::ast2value(*ast1) {
+                (Some(val1), _) => Exp::Loop(
+                    id,
+                    Box::new(Cexp::Val(val1)),
+                    Box::new(self.norm_exp(*ast2, fid, fs)?),
+                    span,
+                ),
+                (None, _ast1) => {
+                    let nast2 = self.norm_exp(*ast2, fid, fs)?;
+                    fs.push(AsgFun::new(Box::new(|ce, span| {
+                        Exp::Loop(id, Box::new(ce), Box::new(nast2), span)
                     })));
-                    norm_exp(_ast1, fs.len() - 1, fs)
+                    self.norm_exp(_ast1, fs.len() - 1, fs)?
                 }
-                (None, None) => {
-                    let nv1 = get_fresh_var();
-                    let nv2 = get_fresh_var();
-                    let ass_ins = fs[fid].apply()(Cexp::App(
-                        Value::Var(nv1.clone()),
-                        Value::Var(nv2.clone()),
-                    ));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(duplicate_var(nv2), Box::new(ce), Box::new(ass_ins))
-                    })));
-                    let nast2 = Box::new(norm_exp(_ast2, fs.len() - 1, fs));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(duplicate_var(nv1), Box::new(ce), nast2)
+            },
+            Ast::Recur(ast1, span) => match Value::ast2value(*ast1) {
+                (Some(val1), _) => Exp::Recur(val1, span),
+                (None, _ast1) => {
+                    let nv = self.get_fresh_var();
+                    fs.push(AsgFun::new(Box::new(|ce, span| {
+                        Exp::Let(
+                            nv.clone(),
+                            Box::new(ce),
+                            Box::new(Exp::Recur(Value::Var(nv), span)),
+                            span,
+                        )
                     })));
-                    norm_exp(_ast1, fs.len() - 1, fs)
+                    self.norm_exp(_ast1, fs.len() - 1, fs)?
                 }
-            }
-        }
-        Ast::Tuple(ast1, ast2) => {
-            let (val1, _ast1) = Value::ast2value(*ast1);
-            let (val2, _ast2) = Value::ast2value(*ast2);
-            match (val1, val2) {
-                (Some(v1), Some(v2)) => fs[fid].apply()(Cexp::Tuple(v1, v2)),
-                (Some(v1), _) => {
-                    let nv2 = get_fresh_var();
-                    let ass_ins = fs[fid].apply()(Cexp::Tuple(v1, Value::Var(nv2.clone())));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(nv2, Box::new(ce), Box::new(ass_ins))
-                    })));
-                    norm_exp(_ast2, fs.len() - 1, fs)
+            },
+            Ast::App(..) if external::external_call(&ast, self.link).is_some() => {
+                let span = ast.span();
+                let (head, args) = external_args(ast)?;
+                let symbol = match self.link.find_external(&head) {
+                    Some(external) => external.symbol.clone(),
+                    None => return Err(CompileError::internal("external_args error.")),
+                };
+                let mut vals = vec![];
+                let mut binds = vec![];
+                for arg in args {
+                    match Value::ast2value(arg) {
+                        (Some(val), _) => vals.push(val),
+                        (None, _arg) => {
+                            let nv = self.get_fresh_var();
+                            vals.push(Value::Var(nv.clone()));
+                            binds.push((nv, _arg));
+                        }
+                    }
                 }
-                (_, Some(v2)) => {
-                    let nv1 = get_fresh_var();
-                    let ass_ins = fs[fid].apply()(Cexp::Tuple(Value::Var(nv1.clone()), v2));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(nv1, Box::new(ce), Box::new(ass_ins))
-                    })));
-                    norm_exp(_ast1, fs.len() - 1, fs)
+                if binds.is_empty() {
+                    return Ok(fs[fid].apply()(Cexp::Extcall(symbol, vals), span));
                 }
-                (None, None) => {
-                    let nv1 = get_fresh_var();
-                    let nv2 = get_fresh_var();
-                    let ass_ins = fs[fid].apply()(Cexp::Tuple(
-                        Value::Var(nv1.clone()),
-                        Value::Var(nv2.clone()),
-                    ));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(duplicate_var(nv2), Box::new(ce), Box::new(ass_ins))
-                    })));
-                    let nast2 = Box::new(norm_exp(_ast2, fs.len() - 1, fs));
-                    fs.push(AsgFun::new(Box::new(|ce| {
-                        Exp::Let(duplicate_var(nv1), Box::new(ce), nast2)
-                    })));
-                    norm_exp(_ast1, fs.len() - 1, fs)
+                // bind the compound arguments first, then call with variables only
+                let mut call = Ast::Var(head, span);
+                for val in vals {
+                    let arg = match val {
+                        Value::Var(id) => Ast::Var(id, span),
+                        Value::Intv(v) => Ast::ILit(v, span),
+                    };
+                    call = Ast::App(Box::new(call), Box::new(arg), span);
+                }
+                for (nv, arg) in binds.into_iter().rev() {
+                    call = Ast::Let((nv, span), Box::new(arg), Box::new(call), span);
                 }
+                self.norm_exp(call, fid, fs)?
             }
+            Ast::App(ast1, ast2, span) => {
+                let (val1, _ast1) = Value::ast2value(*ast1);
+                let (val2, _ast2) = Value::ast2value(*ast2);
+                match (val1, val2) {
+                    (Some(v1), Some(v2)) => fs[fid].apply()(Cexp::App(v1, v2), span),
+                    (Some(v1), _) => {
+                        let nv2 = self.get_fresh_var();
+                        let ass_ins = fs[fid].apply()(Cexp::App(v1, Value::Var(nv2.clone())), span);
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv2, Box::new(ce), Box::new(ass_ins), span)
+                        })));
+                        self.norm_exp(_ast2, fs.len() - 1, fs)?
+                    }
+                    (_, Some(v2)) => {
+                        let nv1 = self.get_fresh_var();
+                        let ass_ins = fs[fid].apply()(Cexp::App(Value::Var(nv1.clone()), v2), span);
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv1, Box::new(ce), Box::new(ass_ins), span)
+                        })));
+                        self.norm_exp(_ast1, fs.len() - 1, fs)?
+                    }
+                    (None, None) => {
+                        let nv1 = self.get_fresh_var();
+                        let nv2 = self.get_fresh_var();
+                        let ass_ins = fs[fid].apply()(
+                            Cexp::App(Value::Var(nv1.clone()), Value::Var(nv2.clone())),
+                            span,
+                        );
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv2, Box::new(ce), Box::new(ass_ins), span)
+                        })));
+                        let nast2 = Box::new(self.norm_exp(_ast2, fs.len() - 1, fs)?);
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv1, Box::new(ce), nast2, span)
+                        })));
+                        self.norm_exp(_ast1, fs.len() - 1, fs)?
+                    }
+                }
+            }
+            Ast::Tuple(ast1, ast2, span) => {
+                let (val1, _ast1) = Value::ast2value(*ast1);
+                let (val2, _ast2) = Value::ast2value(*ast2);
+                match (val1, val2) {
+                    (Some(v1), Some(v2)) => fs[fid].apply()(Cexp::Tuple(v1, v2), span),
+                    (Some(v1), _) => {
+                        let nv2 = self.get_fresh_var();
+                        let ass_ins =
+                            fs[fid].apply()(Cexp::Tuple(v1, Value::Var(nv2.clone())), span);
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv2, Box::new(ce), Box::new(ass_ins), span)
+                        })));
+                        self.norm_exp(_ast2, fs.len() - 1, fs)?
+                    }
+                    (_, Some(v2)) => {
+                        let nv1 = self.get_fresh_var();
+                        let ass_ins =
+                            fs[fid].apply()(Cexp::Tuple(Value::Var(nv1.clone()), v2), span);
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv1, Box::new(ce), Box::new(ass_ins), span)
+                        })));
+                        self.norm_exp(_ast1, fs.len() - 1, fs)?
+                    }
+                    (None, None) => {
+                        let nv1 = self.get_fresh_var();
+                        let nv2 = self.get_fresh_var();
+                        let ass_ins = fs[fid].apply()(
+                            Cexp::Tuple(Value::Var(nv1.clone()), Value::Var(nv2.clone())),
+                            span,
+                        );
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv2, Box::new(ce), Box::new(ass_ins), span)
+                        })));
+                        let nast2 = Box::new(self.norm_exp(_ast2, fs.len() - 1, fs)?);
+                        fs.push(AsgFun::new(Box::new(|ce, span| {
+                            Exp::Let(nv1, Box::new(ce), nast2, span)
+                        })));
+                        self.norm_exp(_ast1, fs.len() - 1, fs)?
+                    }
+                }
+            }
+            Ast::Proj(ast1, v, span) => {
+                let nv = self.get_fresh_var();
+                let ass_ins = fs[fid].apply()(Cexp::Proj(Value::Var(nv.clone()), v), span);
+                fs.push(AsgFun::new(Box::new(|ce, span| {
+                    Exp::Let(nv, Box::new(ce), Box::new(ass_ins), span)
+                })));
+                self.norm_exp(*ast1, fs.len() - 1, fs)?
+            }
+            Ast::Nonaexpr | Ast::Error(..) => {
+                return Err(CompileError::internal(
+                    "There shouldn't be Nonaexpr in Ast.",
+                ));
+            }
+        })
+    }
+}
+
+// the external at the head of an application and its arguments
+fn external_args(ast: Ast) -> CompileResult<(Id, Vec<Ast>)> {
+    match ast {
+        Ast::App(ast1, ast2, _) => {
+            let (head, mut args) = external_args(*ast1)?;
+            args.push(*ast2);
+            Ok((head, args))
         }
-        Ast::Proj(ast1, v) => {
-            let nv = get_fresh_var();
-            let ass_ins = fs[fid].apply()(Cexp::Proj(Value::Var(nv.clone()), v));
-            fs.push(AsgFun::new(Box::new(|ce| {
-                Exp::Let(duplicate_var(nv), Box::new(ce), Box::new(ass_ins))
-            })));
-            norm_exp(*ast1, fs.len() - 1, fs)
-        }
-        Ast::Nonaexpr => {
-            panic!("There shouldn't be Nonaexpr in Ast.");
-        }
+        Ast::Var(id, _) => Ok((id, vec![])),
+        _ => Err(CompileError::internal("external_args error.")),
     }
 }
 
-fn ef(cexp: Cexp) -> Exp {
-    Exp::Compexp(Box::new(cexp))
+fn ef(cexp: Cexp, span: Span) -> Exp {
+    Exp::Compexp(Box::new(cexp), span)
 }
 
-struct AsgFun(Box<dyn FnOnce(Cexp) -> Exp>);
+// the span passed along with a `Cexp` is that of the source expression
+// it computes
+struct AsgFun(Box<dyn FnOnce(Cexp, Span) -> Exp>);
 
 impl AsgFun {
-    fn new(f: Box<dyn FnOnce(Cexp) -> Exp>) -> Self {
+    fn new(f: Box<dyn FnOnce(Cexp, Span) -> Exp>) -> Self {
         Self(f)
     }
-    fn apply(&mut self) -> Box<dyn FnOnce(Cexp) -> Exp> {
+    fn apply(&mut self) -> Box<dyn FnOnce(Cexp, Span) -> Exp> {
         std::mem::replace(&mut self.0, Box::new(ef))
     }
 }
 
-pub fn normalize(ast: Ast) -> Exp {
+pub fn normalize(ast: Ast, link: &Linkage) -> CompileResult<Exp> {
     let mut fs = vec![(AsgFun::new(Box::new(ef)))];
-    norm_exp(ast, 0, &mut fs)
+    Normalizer { link, fresh: 0 }.norm_exp(ast, 0, &mut fs)
+}
+
+// the JSON form, documented in the README; it is shared with the closure
+// and flat IRs where their nodes agree
+
+impl Value {
+    // a variable is a string and an integer a number
+    pub fn to_json(&self) -> Json {
+        match self {
+            Value::Var(id) => Json::str(id),
+            Value::Intv(v) => Json::int(*v),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        match json {
+            Json::Num(_) => Ok(Value::Intv(json.as_int()?)),
+            _ => Ok(Value::Var(json.as_id()?)),
+        }
+    }
+}
+
+pub fn values_json(vals: &[Value]) -> Json {
+    Json::Array(vals.iter().map(Value::to_json).collect())
+}
+
+pub fn values_from_json(json: &Json) -> CompileResult<Vec<Value>> {
+    json.as_array()?.iter().map(Value::from_json).collect()
+}
+
+impl Bintype {
+    pub fn to_json(&self) -> Json {
+        Json::Str(self.bintype_signal().to_string())
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        let op = json.as_str()?;
+        let mut chars = op.chars();
+        match (chars.next().and_then(Bintype::from_signal), chars.next()) {
+            (Some(btype), None) => Ok(btype),
+            _ => Err(json::unknown_kind("operator", op)),
+        }
+    }
+}
+
+// the two values of a pair, which the closure IR generalizes to a list
+fn pair(json: &Json, what: &str) -> CompileResult<(Value, Value)> {
+    match values_from_json(json)?.as_slice() {
+        [val1, val2] => Ok((val1.clone(), val2.clone())),
+        _ => Err(json::json_error(format!(
+            "`{}` should have two values.",
+            what
+        ))),
+    }
+}
+
+impl Cexp {
+    pub fn to_json(&self) -> Json {
+        use Cexp::*;
+        match self {
+            Val(val) => Json::node("Val", vec![("value", val.to_json())]),
+            Binop(btype, val1, val2) => Json::node(
+                "Binop",
+                vec![
+                    ("op", btype.to_json()),
+                    ("lhs", val1.to_json()),
+                    ("rhs", val2.to_json()),
+                ],
+            ),
+            App(val1, val2) => Json::node(
+                "App",
+                vec![
+                    ("fun", val1.to_json()),
+                    ("args", values_json(std::slice::from_ref(val2))),
+                ],
+            ),
+            If(val, exp1, exp2) => Json::node(
+                "If",
+                vec![
+                    ("cond", val.to_json()),
+                    ("then", exp1.to_json()),
+                    ("else", exp2.to_json()),
+                ],
+            ),
+            Tuple(val1, val2) => Json::node(
+                "Tuple",
+                vec![("values", values_json(&[val1.clone(), val2.clone()]))],
+            ),
+            Proj(val, i) => Json::node(
+                "Proj",
+                vec![("tuple", val.to_json()), ("index", Json::int(*i))],
+            ),
+            Extcall(symbol, vals) => Json::node(
+                "Extcall",
+                vec![("symbol", Json::str(symbol)), ("args", values_json(vals))],
+            ),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Cexp::*;
+        let value = |key| Value::from_json(json.field(key)?);
+        Ok(match json.kind()? {
+            "Val" => Val(value("value")?),
+            "Binop" => Binop(
+                Bintype::from_json(json.field("op")?)?,
+                value("lhs")?,
+                value("rhs")?,
+            ),
+            "App" => match values_from_json(json.field("args")?)?.as_slice() {
+                [arg] => App(value("fun")?, arg.clone()),
+                _ => {
+                    return Err(json::json_error(String::from(
+                        "`App` should have one argument.",
+                    )))
+                }
+            },
+            "If" => If(
+                value("cond")?,
+                Box::new(Exp::from_json(json.field("then")?)?),
+                Box::new(Exp::from_json(json.field("else")?)?),
+            ),
+            "Tuple" => {
+                let (val1, val2) = pair(json.field("values")?, "Tuple")?;
+                Tuple(val1, val2)
+            }
+            "Proj" => Proj(value("tuple")?, json.field("index")?.as_int()?),
+            "Extcall" => Extcall(
+                json.field("symbol")?.as_id()?,
+                values_from_json(json.field("args")?)?,
+            ),
+            kind => return Err(json::unknown_kind("normal Cexp", kind)),
+        })
+    }
+}
+
+impl Exp {
+    pub fn to_json(&self) -> Json {
+        use Exp::*;
+        match self {
+            Compexp(cexp, span) => Json::node(
+                "Compexp",
+                vec![("exp", cexp.to_json()), ("span", Json::span(*span))],
+            ),
+            Let(id, cexp, exp, span) | Loop(id, cexp, exp, span) => Json::node(
+                if let Let(..) = self { "Let" } else { "Loop" },
+                vec![
+                    ("name", Json::str(id)),
+                    ("value", cexp.to_json()),
+                    ("body", exp.to_json()),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Letrec(id, arg, exp1, exp2, span) => Json::node(
+                "Letrec",
+                vec![
+                    ("name", Json::str(id)),
+                    ("params", Json::strs(std::slice::from_ref(arg))),
+                    ("body", exp1.to_json()),
+                    ("cont", exp2.to_json()),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Recur(val, span) => Json::node(
+                "Recur",
+                vec![("value", val.to_json()), ("span", Json::span(*span))],
+            ),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Exp::*;
+        let span = json.span_field()?;
+        let exp =
+            |key| -> CompileResult<Box<Exp>> { Ok(Box::new(Exp::from_json(json.field(key)?)?)) };
+        let cexp =
+            |key| -> CompileResult<Box<Cexp>> { Ok(Box::new(Cexp::from_json(json.field(key)?)?)) };
+        let name = || json.field("name")?.as_id();
+        Ok(match json.kind()? {
+            "Compexp" => Compexp(cexp("exp")?, span),
+            "Let" => Let(name()?, cexp("value")?, exp("body")?, span),
+            "Loop" => Loop(name()?, cexp("value")?, exp("body")?, span),
+            "Letrec" => match json.field("params")?.as_ids()?.as_slice() {
+                [arg] => Letrec(name()?, arg.clone(), exp("body")?, exp("cont")?, span),
+                _ => {
+                    return Err(json::json_error(String::from(
+                        "a normal `Letrec` should have one parameter.",
+                    )))
+                }
+            },
+            "Recur" => Recur(Value::from_json(json.field("value")?)?, span),
+            kind => return Err(json::unknown_kind("normal Exp", kind)),
+        })
+    }
 }
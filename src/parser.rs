@@ -1,240 +1,642 @@
 use super::lexer::*;
+use super::typing::Type;
 use super::*;
 
 #[derive(Clone, Debug)]
 pub enum Ast {
     Nonaexpr,
-    ILit(i32),
-    BLit(bool),
-    Binop(TokenType, Box<Ast>, Box<Ast>),
-    If(Box<Ast>, Box<Ast>, Box<Ast>),
-    Fun(Id, Box<Ast>),
-    Var(Id),
-    Let(Id, Box<Ast>, Box<Ast>),
-    Rec(Id, Id, Box<Ast>, Box<Ast>),
-    Loop(Id, Box<Ast>, Box<Ast>),
-    Recur(Box<Ast>),
-    App(Box<Ast>, Box<Ast>),
-    Tuple(Box<Ast>, Box<Ast>),
-    Proj(Box<Ast>, i32),
-}
-
-fn identify(tokenset: &mut TokenSet) -> Id {
-    if let Some(id) = tokenset.curid() {
+    // where a syntax error was recovered from
+    Error(Span),
+    ILit(i32, Span),
+    BLit(bool, Span),
+    Binop(TokenType, Box<Ast>, Box<Ast>, Span),
+    If(Box<Ast>, Box<Ast>, Box<Ast>, Span),
+    // binders are paired with the span of their name
+    Fun((Id, Span), Box<Ast>, Span),
+    Var(Id, Span),
+    Let((Id, Span), Box<Ast>, Box<Ast>, Span),
+    Rec((Id, Span), (Id, Span), Box<Ast>, Box<Ast>, Span),
+    Loop((Id, Span), Box<Ast>, Box<Ast>, Span),
+    Recur(Box<Ast>, Span),
+    App(Box<Ast>, Box<Ast>, Span),
+    Tuple(Box<Ast>, Box<Ast>, Span),
+    Proj(Box<Ast>, i32, Span),
+}
+
+impl Ast {
+    pub fn span(&self) -> Span {
+        use Ast::*;
+        match self {
+            Nonaexpr => Span::default(),
+            Error(span)
+            | ILit(.., span)
+            | BLit(.., span)
+            | Binop(.., span)
+            | If(.., span)
+            | Fun(.., span)
+            | Var(.., span)
+            | Let(.., span)
+            | Rec(.., span)
+            | Loop(.., span)
+            | Recur(.., span)
+            | App(.., span)
+            | Tuple(.., span)
+            | Proj(.., span) => *span,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Item {
+    Let((Id, Span), Ast, Span),
+    Rec((Id, Span), (Id, Span), Ast, Span),
+    Module(Id, Vec<Item>, Span),
+    Open(Id, Span),
+    External(Id, Type, String, Span),
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub items: Vec<Item>,
+    pub body: Ast,
+}
+
+fn identify(tokenset: &mut TokenSet) -> CompileResult<Id> {
+    let id = tokenset.curid()?;
+    tokenset.pos += 1;
+    Ok(id)
+}
+
+// a name being bound, with its span
+fn binder(tokenset: &mut TokenSet) -> CompileResult<(Id, Span)> {
+    let id = identify(tokenset)?;
+    Ok((id, tokenset.prevspan()))
+}
+
+// `M.N.x` is read as the single dotted name of a module member
+fn path(tokenset: &mut TokenSet) -> CompileResult<Id> {
+    let mut id = identify(tokenset)?;
+    while id.starts_with(char::is_uppercase)
+        && tokenset.curtype() == TokenType::Dot
+        && tokenset.nexttype() == TokenType::Id
+    {
         tokenset.pos += 1;
-        Id::from(id)
-    } else {
-        panic!("We will never get to this process.")
+        id.push('.');
+        id.push_str(&identify(tokenset)?);
     }
+    Ok(id)
 }
 
-fn proj(tokenset: &mut TokenSet, mut ast: Ast) -> Ast {
+fn proj(tokenset: &mut TokenSet, mut ast: Ast) -> CompileResult<Ast> {
     while tokenset.consume_ttype(TokenType::Dot) {
-        let num = aexpr(tokenset);
-        if let Ast::ILit(v) = num {
-            ast = Ast::Proj(Box::new(ast), v);
-        } else {
-            compile_error(tokenset, "proj type error.");
-            std::process::exit(1);
+        if tokenset.curtype() != TokenType::ILit {
+            return Err(tokenset.error("tuple index expected."));
         }
+        let v = tokenset.curnum();
+        tokenset.pos += 1;
+        let span = ast.span().to(tokenset.prevspan());
+        ast = Ast::Proj(Box::new(ast), v, span);
     }
-    ast
+    Ok(ast)
 }
 
-fn aexpr(tokenset: &mut TokenSet) -> Ast {
+fn aexpr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    let lo = tokenset.curspan();
     match tokenset.curtype() {
         TokenType::ILit => {
             let num = tokenset.curnum();
             tokenset.pos += 1;
-            Ast::ILit(num)
+            Ok(Ast::ILit(num, lo))
         }
         TokenType::Id => {
-            let var = tokenset.curid().unwrap();
-            tokenset.pos += 1;
-            proj(tokenset, Ast::Var(var))
+            let var = path(tokenset)?;
+            let span = lo.to(tokenset.prevspan());
+            proj(tokenset, Ast::Var(var, span))
         }
         TokenType::True => {
             tokenset.pos += 1;
-            Ast::BLit(true)
+            Ok(Ast::BLit(true, lo))
         }
         TokenType::False => {
             tokenset.pos += 1;
-            Ast::BLit(false)
+            Ok(Ast::BLit(false, lo))
         }
         TokenType::Lbrac => {
             tokenset.pos += 1;
-            let mut ast = expr(tokenset);
+            let mut ast = expr(tokenset)?;
             if tokenset.consume_ttype(TokenType::Comma) {
-                ast = Ast::Tuple(Box::new(ast), Box::new(expr(tokenset)));
+                let ast2 = expr(tokenset)?;
+                let span = lo.to(tokenset.curspan());
+                ast = Ast::Tuple(Box::new(ast), Box::new(ast2), span);
             }
-            tokenset.assert_ttype(TokenType::Rbrac);
+            tokenset.assert_ttype(TokenType::Rbrac)?;
             proj(tokenset, ast)
         }
-        _ => Ast::Nonaexpr,
+        _ => Ok(Ast::Nonaexpr),
     }
 }
 
-fn appexpr(tokenset: &mut TokenSet) -> Ast {
+// an atomic expression that must be present
+fn operand(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    match aexpr(tokenset)? {
+        Ast::Nonaexpr => Err(tokenset.error(&format!(
+            "expected expression, found `{}`.",
+            tokenset.curtype().text()
+        ))),
+        ast => Ok(ast),
+    }
+}
+
+fn appexpr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    let lo = tokenset.curspan();
     if tokenset.consume_ttype(TokenType::Recur) {
-        return Ast::Recur(Box::new(aexpr(tokenset)));
+        let ast = operand(tokenset)?;
+        let span = lo.to(tokenset.prevspan());
+        return Ok(Ast::Recur(Box::new(ast), span));
     }
-    let mut ast = aexpr(tokenset);
+    let mut ast = operand(tokenset)?;
     loop {
-        let ast1 = aexpr(tokenset);
+        let ast1 = aexpr(tokenset)?;
         if let Ast::Nonaexpr = ast1 {
             break;
         }
-        ast = Ast::App(Box::new(ast), Box::new(ast1));
+        let span = lo.to(tokenset.prevspan());
+        ast = Ast::App(Box::new(ast), Box::new(ast1), span);
     }
-    ast
+    Ok(ast)
 }
 
-fn mexpr(tokenset: &mut TokenSet) -> Ast {
-    let mut ast = appexpr(tokenset);
+fn mexpr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    let lo = tokenset.curspan();
+    let mut ast = appexpr(tokenset)?;
     while tokenset.consume_ttype(TokenType::Mult) {
-        ast = Ast::Binop(TokenType::Mult, Box::new(ast), Box::new(aexpr(tokenset)));
+        let rhs = operand(tokenset)?;
+        let span = lo.to(tokenset.prevspan());
+        ast = Ast::Binop(TokenType::Mult, Box::new(ast), Box::new(rhs), span);
     }
-    ast
+    Ok(ast)
 }
 
-fn pexpr(tokenset: &mut TokenSet) -> Ast {
-    let mut ast = mexpr(tokenset);
+fn pexpr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    let lo = tokenset.curspan();
+    let mut ast = mexpr(tokenset)?;
     while tokenset.consume_ttype(TokenType::Plus) {
-        ast = Ast::Binop(TokenType::Plus, Box::new(ast), Box::new(pexpr(tokenset)));
+        let rhs = pexpr(tokenset)?;
+        let span = lo.to(tokenset.prevspan());
+        ast = Ast::Binop(TokenType::Plus, Box::new(ast), Box::new(rhs), span);
     }
-    ast
+    Ok(ast)
 }
 
-fn ltexpr(tokenset: &mut TokenSet) -> Ast {
-    let last = pexpr(tokenset);
+fn ltexpr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    let lo = tokenset.curspan();
+    let last = pexpr(tokenset)?;
     if tokenset.consume_ttype(TokenType::Lt) {
-        let rast = pexpr(tokenset);
-        return Ast::Binop(TokenType::Lt, Box::new(last), Box::new(rast));
+        let rast = pexpr(tokenset)?;
+        let span = lo.to(tokenset.prevspan());
+        return Ok(Ast::Binop(
+            TokenType::Lt,
+            Box::new(last),
+            Box::new(rast),
+            span,
+        ));
     }
-    last
+    Ok(last)
 }
 
-fn eqexpr(tokenset: &mut TokenSet) -> Ast {
-    let lhs = ltexpr(tokenset);
+fn eqexpr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    let lo = tokenset.curspan();
+    let lhs = ltexpr(tokenset)?;
     if tokenset.consume_ttype(TokenType::Eq) {
-        let rhs = ltexpr(tokenset);
-        return Ast::Binop(TokenType::Eq, Box::new(lhs), Box::new(rhs));
+        let rhs = ltexpr(tokenset)?;
+        let span = lo.to(tokenset.prevspan());
+        return Ok(Ast::Binop(
+            TokenType::Eq,
+            Box::new(lhs),
+            Box::new(rhs),
+            span,
+        ));
+    }
+    Ok(lhs)
+}
+
+// `let rec f = fun x -> body`, split into `x` and `body`
+fn rec_fun(tokenset: &mut TokenSet) -> CompileResult<((Id, Span), Ast)> {
+    let lo = tokenset.curspan();
+    match expr(tokenset)? {
+        Ast::Fun(funid, body, _) => Ok((funid, *body)),
+        Ast::Error(span) => Ok(((String::from("_"), span), Ast::Error(span))),
+        _ => Err(CompileError::new(
+            ErrorKind::Syntax,
+            String::from("`let rec` should bind a `fun`."),
+            lo.to(tokenset.prevspan()),
+        )),
     }
-    lhs
 }
 
-fn expr(tokenset: &mut TokenSet) -> Ast {
+// tokens an expression can be resumed before
+fn sync_point(ttype: TokenType) -> bool {
+    use TokenType::*;
+    matches!(ttype, In | Semisemi | Then | Else | Rbrac)
+}
+
+// records `err` unless it is at or before the last error recorded,
+// which the enclosing rules run into again as they unwind
+fn report(tokenset: &mut TokenSet, err: CompileError) {
+    let lo = |err: &CompileError| err.span.map_or(0, |span| span.lo);
+    if tokenset
+        .errors
+        .last()
+        .is_none_or(|last| lo(&err) > lo(last))
+    {
+        tokenset.errors.push(err);
+    }
+}
+
+// records `err` and skips to the next synchronization point that is
+// not inside parentheses, bindings or conditionals opened meanwhile
+fn recover(tokenset: &mut TokenSet, err: CompileError) {
+    use TokenType::*;
+    report(tokenset, err);
+    let mut depth = 0;
+    while !tokenset.eof() {
+        match tokenset.curtype() {
+            Lbrac | Let | Loop | If => depth += 1,
+            Rbrac | In | Else if depth > 0 => depth -= 1,
+            Then if depth > 0 => {}
+            ttype if sync_point(ttype) => break,
+            _ => {}
+        }
+        tokenset.pos += 1;
+    }
+}
+
+// a syntax error inside an expression becomes an error node, so that
+// parsing goes on and reports the errors after it too
+fn expr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
+    let start = tokenset.pos;
+    match sub_expr(tokenset) {
+        Err(err) if err.kind == ErrorKind::Syntax => {
+            recover(tokenset, err);
+            Ok(Ast::Error(tokenset.span_from(start)))
+        }
+        result => result,
+    }
+}
+
+fn sub_expr(tokenset: &mut TokenSet) -> CompileResult<Ast> {
     let ast;
+    let lo = tokenset.curspan();
     match tokenset.curtype() {
         TokenType::If => {
             tokenset.pos += 1;
-            let cond = expr(tokenset);
-            tokenset.assert_ttype(TokenType::Then);
-            let then = expr(tokenset);
-            tokenset.assert_ttype(TokenType::Else);
-            let els = expr(tokenset);
-            ast = Ast::If(Box::new(cond), Box::new(then), Box::new(els));
+            let cond = expr(tokenset)?;
+            tokenset.assert_ttype(TokenType::Then)?;
+            let then = expr(tokenset)?;
+            tokenset.assert_ttype(TokenType::Else)?;
+            let els = expr(tokenset)?;
+            let span = lo.to(tokenset.prevspan());
+            ast = Ast::If(Box::new(cond), Box::new(then), Box::new(els), span);
         }
         TokenType::Fun => {
             tokenset.pos += 1;
-            let id = identify(tokenset);
-            tokenset.assert_ttype(TokenType::Arrow);
-            let body = expr(tokenset);
-            ast = Ast::Fun(id, Box::new(body));
+            let id = binder(tokenset)?;
+            tokenset.assert_ttype(TokenType::Arrow)?;
+            let body = expr(tokenset)?;
+            let span = lo.to(tokenset.prevspan());
+            ast = Ast::Fun(id, Box::new(body), span);
         }
         TokenType::Let => {
             tokenset.pos += 1;
             match tokenset.curtype() {
                 TokenType::Rec => {
                     tokenset.pos += 1;
-                    let id = identify(tokenset);
-                    tokenset.assert_ttype(TokenType::Assign);
-                    let funast = expr(tokenset);
-                    if let Ast::Fun(funid, body) = funast {
-                        tokenset.assert_ttype(TokenType::In);
-                        ast = Ast::Rec(id, funid, Box::new(*body), Box::new(expr(tokenset)));
-                    } else {
-                        panic!("should type fun. {:?}", funast);
-                    }
+                    let id = binder(tokenset)?;
+                    tokenset.assert_ttype(TokenType::Assign)?;
+                    let (funid, body) = rec_fun(tokenset)?;
+                    tokenset.assert_ttype(TokenType::In)?;
+                    let ast2 = expr(tokenset)?;
+                    let span = lo.to(tokenset.prevspan());
+                    ast = Ast::Rec(id, funid, Box::new(body), Box::new(ast2), span);
                 }
                 _ => {
-                    let id = identify(tokenset);
-                    tokenset.assert_ttype(TokenType::Assign);
-                    let ast1 = expr(tokenset);
-                    tokenset.assert_ttype(TokenType::In);
-                    let ast2 = expr(tokenset);
-                    ast = Ast::Let(id, Box::new(ast1), Box::new(ast2))
+                    let id = binder(tokenset)?;
+                    tokenset.assert_ttype(TokenType::Assign)?;
+                    let ast1 = expr(tokenset)?;
+                    tokenset.assert_ttype(TokenType::In)?;
+                    let ast2 = expr(tokenset)?;
+                    let span = lo.to(tokenset.prevspan());
+                    ast = Ast::Let(id, Box::new(ast1), Box::new(ast2), span)
                 }
             }
         }
         TokenType::Loop => {
             tokenset.pos += 1;
-            let id = identify(tokenset);
-            tokenset.assert_ttype(TokenType::Assign);
-            let ast1 = expr(tokenset);
-            tokenset.assert_ttype(TokenType::In);
-            let ast2 = expr(tokenset);
-            ast = Ast::Loop(id, Box::new(ast1), Box::new(ast2))
+            let id = binder(tokenset)?;
+            tokenset.assert_ttype(TokenType::Assign)?;
+            let ast1 = expr(tokenset)?;
+            tokenset.assert_ttype(TokenType::In)?;
+            let ast2 = expr(tokenset)?;
+            let span = lo.to(tokenset.prevspan());
+            ast = Ast::Loop(id, Box::new(ast1), Box::new(ast2), span)
         }
         _ => {
-            ast = eqexpr(tokenset);
-        }
-    }
-    ast
-}
-
-fn recur_check(ast: Ast, endpos: bool) -> Ast {
-    match ast {
-        Ast::Nonaexpr | Ast::ILit(_) | Ast::BLit(_) | Ast::Var(_) => ast,
-        Ast::Binop(ttype, ast1, ast2) => Ast::Binop(
-            ttype,
-            Box::new(recur_check(*ast1, endpos)),
-            Box::new(recur_check(*ast2, endpos)),
-        ),
-        Ast::If(ast1, ast2, ast3) => Ast::If(
-            Box::new(recur_check(*ast1, endpos)),
-            Box::new(recur_check(*ast2, endpos)),
-            Box::new(recur_check(*ast3, endpos)),
-        ),
-        Ast::Fun(id, ast1) => Ast::Fun(id, Box::new(recur_check(*ast1, endpos))),
-        Ast::Let(id, ast1, ast2) => Ast::Let(
-            id,
-            Box::new(recur_check(*ast1, endpos)),
-            Box::new(recur_check(*ast2, endpos)),
-        ),
-        Ast::Rec(id, funid, ast1, ast2) => Ast::Rec(
-            id,
-            funid,
-            Box::new(recur_check(*ast1, endpos)),
-            Box::new(recur_check(*ast2, endpos)),
-        ),
-        Ast::Loop(id, ast1, ast2) => Ast::Loop(
-            id,
-            Box::new(recur_check(*ast1, endpos)),
-            Box::new(recur_check(*ast2, true)),
-        ),
-        Ast::App(ast1, ast2) => Ast::App(
-            Box::new(recur_check(*ast1, endpos)),
-            Box::new(recur_check(*ast2, endpos)),
-        ),
-        Ast::Tuple(ast1, ast2) => Ast::Tuple(
-            Box::new(recur_check(*ast1, endpos)),
-            Box::new(recur_check(*ast2, endpos)),
-        ),
-        Ast::Proj(ast1, v) => Ast::Proj(Box::new(recur_check(*ast1, endpos)), v),
-        Ast::Recur(ast1) => {
-            if !endpos {
-                message_error("<recur <exp>> should be at end position.");
-                std::process::exit(1);
+            ast = eqexpr(tokenset)?;
+        }
+    }
+    Ok(ast)
+}
+
+// type expressions of signatures and externals
+fn type_atom(tokenset: &mut TokenSet) -> CompileResult<Type> {
+    let ty = match tokenset.curtype() {
+        TokenType::Lbrac => {
+            tokenset.pos += 1;
+            let ty = typexpr(tokenset)?;
+            tokenset.assert_ttype(TokenType::Rbrac)?;
+            ty
+        }
+        TokenType::Id if tokenset.curid()? == "int" => {
+            tokenset.pos += 1;
+            Type::Int
+        }
+        TokenType::Id if tokenset.curid()? == "bool" => {
+            tokenset.pos += 1;
+            Type::Bool
+        }
+        _ => return Err(tokenset.error("type expected.")),
+    };
+    if tokenset.consume_ttype(TokenType::Mult) {
+        return Ok(Type::Tuple(Box::new(ty), Box::new(type_atom(tokenset)?)));
+    }
+    Ok(ty)
+}
+
+pub fn typexpr(tokenset: &mut TokenSet) -> CompileResult<Type> {
+    let ty = type_atom(tokenset)?;
+    if tokenset.consume_ttype(TokenType::Arrow) {
+        return Ok(Type::Fun(Box::new(ty), Box::new(typexpr(tokenset)?)));
+    }
+    Ok(ty)
+}
+
+// tokens an item can be resumed before
+fn item_start(ttype: TokenType) -> bool {
+    use TokenType::*;
+    matches!(ttype, Semisemi | Module | Open | External | End | Let)
+}
+
+// records `err` of the item from `start` and skips to the next item
+fn recover_item(tokenset: &mut TokenSet, err: CompileError, start: usize) {
+    report(tokenset, err);
+    if tokenset.pos == start && !tokenset.eof() {
+        tokenset.pos += 1;
+    }
+    let mut depth = 0;
+    while !tokenset.eof() {
+        match tokenset.curtype() {
+            TokenType::Lbrac => depth += 1,
+            TokenType::Rbrac if depth > 0 => depth -= 1,
+            ttype if depth == 0 && item_start(ttype) => break,
+            _ => {}
+        }
+        tokenset.pos += 1;
+    }
+}
+
+// an item, or None after recovering from a syntax error in it
+fn item_or_recover(tokenset: &mut TokenSet) -> CompileResult<Option<Item>> {
+    let start = tokenset.pos;
+    match item(tokenset) {
+        Ok(item) => Ok(Some(item)),
+        Err(err) if err.kind == ErrorKind::Syntax => {
+            recover_item(tokenset, err, start);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn item(tokenset: &mut TokenSet) -> CompileResult<Item> {
+    let lo = tokenset.curspan();
+    match tokenset.curtype() {
+        TokenType::Module => {
+            tokenset.pos += 1;
+            let id = identify(tokenset)?;
+            tokenset.assert_ttype(TokenType::Assign)?;
+            tokenset.assert_ttype(TokenType::Struct)?;
+            let mut items = vec![];
+            while !matches!(
+                tokenset.curtype(),
+                TokenType::End | TokenType::Semisemi | TokenType::Eof
+            ) {
+                items.extend(item_or_recover(tokenset)?);
+            }
+            tokenset.assert_ttype(TokenType::End)?;
+            Ok(Item::Module(id, items, lo.to(tokenset.prevspan())))
+        }
+        TokenType::Open => {
+            tokenset.pos += 1;
+            let id = path(tokenset)?;
+            Ok(Item::Open(id, lo.to(tokenset.prevspan())))
+        }
+        TokenType::External => {
+            tokenset.pos += 1;
+            let id = identify(tokenset)?;
+            tokenset.assert_ttype(TokenType::Colon)?;
+            let ty = typexpr(tokenset)?;
+            tokenset.assert_ttype(TokenType::Assign)?;
+            let symbol = tokenset.tokens[tokenset.pos].id.clone();
+            tokenset.assert_ttype(TokenType::Str)?;
+            Ok(Item::External(
+                id,
+                ty,
+                symbol.unwrap_or_default(),
+                lo.to(tokenset.prevspan()),
+            ))
+        }
+        _ => {
+            tokenset.assert_ttype(TokenType::Let)?;
+            if tokenset.consume_ttype(TokenType::Rec) {
+                let id = binder(tokenset)?;
+                tokenset.assert_ttype(TokenType::Assign)?;
+                let (funid, body) = rec_fun(tokenset)?;
+                Ok(Item::Rec(id, funid, body, lo.to(tokenset.prevspan())))
+            } else {
+                let id = binder(tokenset)?;
+                tokenset.assert_ttype(TokenType::Assign)?;
+                let ast = expr(tokenset)?;
+                Ok(Item::Let(id, ast, lo.to(tokenset.prevspan())))
             }
-            Ast::Recur(Box::new(recur_check(*ast1, endpos)))
         }
     }
 }
 
-pub fn parse(mut tokenset: TokenSet) -> Ast {
-    let ast = expr(&mut tokenset);
-    tokenset.assert_ttype(TokenType::Semisemi);
-    let ast = recur_check(ast, false);
-    ast
+// the syntax errors recovered from, or `value` if there were none
+fn finish<T>(tokenset: TokenSet, value: T) -> CompileResult<T> {
+    match CompileError::collect(tokenset.errors) {
+        Some(err) => Err(err),
+        None => Ok(value),
+    }
+}
+
+pub fn parse_unit(mut tokenset: TokenSet) -> CompileResult<Vec<Item>> {
+    let mut items = vec![];
+    while !tokenset.eof() {
+        if !tokenset.consume_ttype(TokenType::Semisemi) {
+            items.extend(item_or_recover(&mut tokenset)?);
+        }
+    }
+    finish(tokenset, items)
+}
+
+// the program with error nodes where syntax errors were recovered from;
+// the errors are left in `tokenset.errors`
+pub fn parse_partial(tokenset: &mut TokenSet) -> CompileResult<Program> {
+    let mut items = vec![];
+    while let TokenType::Module | TokenType::Open | TokenType::External = tokenset.curtype() {
+        items.extend(item_or_recover(tokenset)?);
+    }
+    // an item that swallowed the body has been reported already
+    let swallowed = matches!(tokenset.curtype(), TokenType::Semisemi | TokenType::Eof);
+    let reported = tokenset.errors.len();
+    let body = if swallowed && reported > 0 {
+        Ast::Error(tokenset.curspan())
+    } else {
+        expr(tokenset)?
+    };
+    if let Err(err) = tokenset.assert_ttype(TokenType::Semisemi) {
+        // a body that recovered from an error stopped wherever it could
+        // resynchronise, so what follows it is not reported again
+        if tokenset.errors.len() == reported {
+            report(tokenset, err);
+        }
+    }
+    Ok(Program { items, body })
+}
+
+pub fn parse(mut tokenset: TokenSet) -> CompileResult<Program> {
+    let program = parse_partial(&mut tokenset)?;
+    finish(tokenset, program)
+}
+
+// the JSON form of the syntax tree, for tools; it is not read back, as
+// the source text is the way into the compiler
+impl Ast {
+    pub fn to_json(&self) -> Json {
+        use Ast::*;
+        let ast = |ast: &Ast| ast.to_json();
+        let (kind, mut fields) = match self {
+            Nonaexpr => return Json::node("Nonaexpr", vec![]),
+            Error(_) => ("Error", vec![]),
+            ILit(v, _) => ("ILit", vec![("value", Json::int(*v))]),
+            BLit(v, _) => ("BLit", vec![("value", Json::Bool(*v))]),
+            Binop(op, lhs, rhs, _) => (
+                "Binop",
+                vec![
+                    ("op", Json::str(op.text())),
+                    ("lhs", ast(lhs)),
+                    ("rhs", ast(rhs)),
+                ],
+            ),
+            If(cond, then, els, _) => (
+                "If",
+                vec![("cond", ast(cond)), ("then", ast(then)), ("else", ast(els))],
+            ),
+            Fun((param, _), body, _) => (
+                "Fun",
+                vec![("param", Json::str(param)), ("body", ast(body))],
+            ),
+            Var(id, _) => ("Var", vec![("name", Json::str(id))]),
+            Let((id, _), value, body, _) | Loop((id, _), value, body, _) => (
+                if let Let(..) = self { "Let" } else { "Loop" },
+                vec![
+                    ("name", Json::str(id)),
+                    ("value", ast(value)),
+                    ("body", ast(body)),
+                ],
+            ),
+            Rec((id, _), (param, _), value, body, _) => (
+                "Rec",
+                vec![
+                    ("name", Json::str(id)),
+                    ("param", Json::str(param)),
+                    ("value", ast(value)),
+                    ("body", ast(body)),
+                ],
+            ),
+            Recur(value, _) => ("Recur", vec![("value", ast(value))]),
+            App(fun, arg, _) => ("App", vec![("fun", ast(fun)), ("arg", ast(arg))]),
+            Tuple(fst, snd, _) => (
+                "Tuple",
+                vec![("values", Json::Array(vec![ast(fst), ast(snd)]))],
+            ),
+            Proj(tuple, i, _) => (
+                "Proj",
+                vec![("tuple", ast(tuple)), ("index", Json::int(*i))],
+            ),
+        };
+        fields.push(("span", Json::span(self.span())));
+        Json::node(kind, fields)
+    }
+}
+
+impl Item {
+    pub fn to_json(&self) -> Json {
+        match self {
+            Item::Let((id, _), value, span) => Json::node(
+                "Let",
+                vec![
+                    ("name", Json::str(id)),
+                    ("value", value.to_json()),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Item::Rec((id, _), (param, _), value, span) => Json::node(
+                "Rec",
+                vec![
+                    ("name", Json::str(id)),
+                    ("param", Json::str(param)),
+                    ("value", value.to_json()),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Item::Module(id, items, span) => Json::node(
+                "Module",
+                vec![
+                    ("name", Json::str(id)),
+                    (
+                        "items",
+                        Json::Array(items.iter().map(Item::to_json).collect()),
+                    ),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+            Item::Open(id, span) => Json::node(
+                "Open",
+                vec![("name", Json::str(id)), ("span", Json::span(*span))],
+            ),
+            Item::External(id, ty, symbol, span) => Json::node(
+                "External",
+                vec![
+                    ("name", Json::str(id)),
+                    ("type", Json::Str(ty.to_string())),
+                    ("symbol", Json::str(symbol)),
+                    ("span", Json::span(*span)),
+                ],
+            ),
+        }
+    }
+}
+
+impl Program {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            (
+                "items",
+                Json::Array(self.items.iter().map(Item::to_json).collect()),
+            ),
+            ("body", self.body.to_json()),
+        ])
+    }
 }
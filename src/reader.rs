@@ -0,0 +1,216 @@
+use super::normal::Bintype;
+use super::*;
+
+// a cursor over the text the IR printers write, for reading it back;
+// positions are byte offsets into `text`
+pub struct Reader<'a> {
+    pub text: &'a str,
+    pub pos: usize,
+}
+
+// characters of IR names: `@` and `$` mark names the compiler made up
+fn name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@' || c == '$'
+}
+
+fn name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '$' || c == '\''
+}
+
+const KEYWORDS: [&str; 9] = [
+    "let", "rec", "in", "loop", "recur", "if", "then", "else", "extern",
+];
+
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+    // skips spaces and line breaks
+    pub fn skip(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+    // skips spaces but not line breaks, for line-based formats
+    pub fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t']).len();
+    }
+    pub fn eof(&mut self) -> bool {
+        self.skip();
+        self.pos == self.text.len()
+    }
+    // a syntax error at the current position
+    pub fn error(&self, message: &str) -> CompileError {
+        let width = self.rest().chars().next().map_or(0, char::len_utf8);
+        CompileError::new(
+            ErrorKind::Syntax,
+            message.to_string(),
+            Span::new(self.pos, self.pos + width),
+        )
+    }
+    fn found(&self) -> String {
+        match self.rest().chars().next() {
+            Some(c) if name_char(c) => {
+                let len = self
+                    .rest()
+                    .find(|c| !name_char(c))
+                    .unwrap_or(self.rest().len());
+                format!("`{}`", &self.rest()[..len])
+            }
+            Some(c) => format!("`{}`", c.escape_debug()),
+            None => String::from("end of input"),
+        }
+    }
+    // consumes the operator of a `Binop` if the line continues with one
+    pub fn bintype(&mut self) -> Option<Bintype> {
+        self.skip_spaces();
+        let btype = self.rest().chars().next().and_then(Bintype::from_signal)?;
+        self.pos += 1;
+        Some(btype)
+    }
+    // consumes `signal` if the text continues with it
+    pub fn eat(&mut self, signal: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(signal) {
+            self.pos += signal.len();
+            true
+        } else {
+            false
+        }
+    }
+    pub fn expect(&mut self, signal: &str) -> CompileResult<()> {
+        self.skip();
+        if self.eat(signal) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`, found {}.", signal, self.found())))
+        }
+    }
+    // consumes `keyword` if it is the next word
+    pub fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip();
+        let rest = self.rest();
+        if rest.starts_with(keyword) && !rest[keyword.len()..].starts_with(name_char) {
+            self.pos += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+    pub fn expect_keyword(&mut self, keyword: &str) -> CompileResult<()> {
+        if self.keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`, found {}.", keyword, self.found())))
+        }
+    }
+    // a name; members of modules are joined with `.`, which is told
+    // apart from a projection by the digit that follows the latter
+    pub fn name(&mut self) -> CompileResult<Id> {
+        self.skip();
+        let start = self.pos;
+        while self.rest().starts_with(name_start) {
+            self.pos += self
+                .rest()
+                .find(|c| !name_char(c))
+                .unwrap_or(self.rest().len());
+            if self.rest().starts_with('.') && self.rest()[1..].starts_with(name_start) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let name = &self.text[start..self.pos];
+        if name.is_empty() || KEYWORDS.contains(&name) {
+            self.pos = start;
+            return Err(self.error(&format!("expected name, found {}.", self.found())));
+        }
+        Ok(name.to_string())
+    }
+    // a decimal integer, possibly negative
+    pub fn int(&mut self) -> CompileResult<i32> {
+        self.skip();
+        let start = self.pos;
+        let sign = if self.rest().starts_with('-') { 1 } else { 0 };
+        let digits = self.rest()[sign..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest().len() - sign);
+        if digits == 0 {
+            return Err(self.error(&format!("expected integer, found {}.", self.found())));
+        }
+        self.pos += sign + digits;
+        self.text[start..self.pos].parse().map_err(|_| {
+            CompileError::new(
+                ErrorKind::Syntax,
+                String::from("integer literal is too large."),
+                Span::new(start, self.pos),
+            )
+        })
+    }
+    pub fn at_int(&mut self) -> bool {
+        self.skip();
+        let rest = self.rest();
+        rest.starts_with(|c: char| c.is_ascii_digit())
+            || (rest.starts_with('-') && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+    }
+    // a label of the vm code, which may start with `.`
+    pub fn label(&mut self) -> CompileResult<String> {
+        self.skip_spaces();
+        let len = self
+            .rest()
+            .find(|c| !(name_char(c) || c == '.'))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(self.error(&format!("expected label, found {}.", self.found())));
+        }
+        self.pos += len;
+        Ok(self.text[self.pos - len..self.pos].to_string())
+    }
+    // the rest of the line is blank, for line-based formats
+    pub fn at_line_end(&mut self) -> bool {
+        self.skip_spaces();
+        let rest = self.rest();
+        rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n")
+    }
+    pub fn end_line(&mut self) -> CompileResult<()> {
+        if self.at_line_end() {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected end of line, found {}.", self.found())))
+        }
+    }
+    // `items` in parentheses, separated by `,`
+    pub fn list<T>(
+        &mut self,
+        item: impl FnMut(&mut Self) -> CompileResult<T>,
+    ) -> CompileResult<Vec<T>> {
+        self.list_in("(", ")", item)
+    }
+    pub fn list_in<T>(
+        &mut self,
+        open: &str,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> CompileResult<T>,
+    ) -> CompileResult<Vec<T>> {
+        self.expect(open)?;
+        let mut items = vec![];
+        self.skip();
+        if self.eat(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            self.skip();
+            if self.eat(close) {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+    pub fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.pos)
+    }
+}
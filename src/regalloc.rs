@@ -4,10 +4,10 @@ use vm::*;
 pub const REG_SIZE: usize = 10;
 pub const A1: i32 = 0;
 
-pub fn regalloc(pg: &mut vm::Program) {
+pub fn regalloc(pg: &mut vm::Program) -> CompileResult<()> {
     let mut regs: [i32; REG_SIZE] = [-1, -1, -1, -1, -1, -1, -1, -1, -1, -1];
     for decl in &mut pg.decls {
-        for instr in &mut decl.instrs {
+        for (instr, span) in &mut decl.instrs {
             use vm::Instr::*;
             match instr {
                 Mover(r1, r2) | Binop(_, r1, r2) => {
@@ -23,7 +23,7 @@ pub fn regalloc(pg: &mut vm::Program) {
                         reg.set_real(&mut regs);
                     }
                 }
-                Call(r, args) => {
+                Call(r, args) | Callext(r, _, args) => {
                     r.set_real(&mut regs);
                     for reg in args {
                         reg.set_real(&mut regs);
@@ -39,15 +39,16 @@ pub fn regalloc(pg: &mut vm::Program) {
                     } else {
                         1000
                     };
-                    for i in 0..pri as usize {
-                        regs[i] = 1;
+                    for reg in regs.iter_mut().take(pri as usize) {
+                        *reg = 1;
                     }
                 }
                 Kill(r) => {
-                    r.kill(&mut regs);
+                    r.kill(&mut regs, *span)?;
                 }
                 _ => {}
             }
         }
     }
+    Ok(())
 }
@@ -0,0 +1,150 @@
+use super::parser::*;
+use super::*;
+
+use std::collections::HashSet;
+
+// the names the renaming pass gave to binders, with the names they have
+// in the source
+#[derive(Clone, Debug, Default)]
+pub struct Renames {
+    pub original: HashMap<Id, Id>,
+}
+
+fn name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_'.$@".contains(c)
+}
+
+impl Renames {
+    // the source name of `id`
+    pub fn original<'a>(&'a self, id: &'a str) -> &'a str {
+        self.original.get(id).map_or(id, String::as_str)
+    }
+    // `text` with every renamed name in it given back its source name
+    pub fn restore_text(&self, text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find(name_char) {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest.find(|c| !name_char(c)).unwrap_or(rest.len());
+            out.push_str(self.original(&rest[..end]));
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        out
+    }
+    // `err` as it reads with the names of the source
+    pub fn restore(&self, mut err: CompileError) -> CompileError {
+        err.message = self.restore_text(&err.message);
+        if let Some((_, note)) = &mut err.note {
+            *note = self.restore_text(note);
+        }
+        err.more = err.more.into_iter().map(|err| self.restore(err)).collect();
+        err
+    }
+}
+
+// gives every binder of the program a name of its own, so that the
+// passes after it never see one binding shadow another; the first
+// binder of a name keeps it and the others become `name$n`, which no
+// source identifier can be. Free names are globals of other units and
+// externals, which have a `.` in them and so never meet a binder
+struct Renamer {
+    // every name given to a binder so far
+    taken: HashSet<Id>,
+    renames: Renames,
+}
+
+impl Renamer {
+    fn binder(&mut self, id: &str) -> Id {
+        if self.taken.insert(id.to_string()) {
+            return id.to_string();
+        }
+        let mut n = 1;
+        let mut name = format!("{}${}", id, n);
+        while !self.taken.insert(name.clone()) {
+            n += 1;
+            name = format!("{}${}", id, n);
+        }
+        self.renames.original.insert(name.clone(), id.to_string());
+        name
+    }
+    // `scope` maps the names of the source in scope to their new names,
+    // innermost last
+    fn expr(&mut self, ast: Ast, scope: &mut Vec<(Id, Id)>) -> Ast {
+        macro_rules! sub {
+            ($ast: expr) => {
+                Box::new(self.expr(*$ast, scope))
+            };
+        }
+        macro_rules! bound {
+            ($binds: expr, $ast: expr) => {{
+                let n = scope.len();
+                scope.extend($binds);
+                let ast = self.expr(*$ast, scope);
+                scope.truncate(n);
+                Box::new(ast)
+            }};
+        }
+        match ast {
+            Ast::Var(id, span) => {
+                let name = scope.iter().rev().find(|(old, _)| *old == id);
+                Ast::Var(name.map_or(id, |(_, new)| new.clone()), span)
+            }
+            Ast::Nonaexpr | Ast::Error(..) | Ast::ILit(..) | Ast::BLit(..) => ast,
+            Ast::Binop(ttype, ast1, ast2, span) => Ast::Binop(ttype, sub!(ast1), sub!(ast2), span),
+            Ast::If(ast1, ast2, ast3, span) => Ast::If(sub!(ast1), sub!(ast2), sub!(ast3), span),
+            Ast::Fun((id, bspan), ast1, span) => {
+                let name = self.binder(&id);
+                Ast::Fun((name.clone(), bspan), bound!(vec![(id, name)], ast1), span)
+            }
+            Ast::Let((id, bspan), ast1, ast2, span) => {
+                let ast1 = sub!(ast1);
+                let name = self.binder(&id);
+                Ast::Let(
+                    (name.clone(), bspan),
+                    ast1,
+                    bound!(vec![(id, name)], ast2),
+                    span,
+                )
+            }
+            Ast::Rec((id1, bspan1), (id2, bspan2), ast1, ast2, span) => {
+                let name1 = self.binder(&id1);
+                let name2 = self.binder(&id2);
+                let fun = (id1, name1.clone());
+                Ast::Rec(
+                    (name1, bspan1),
+                    (name2.clone(), bspan2),
+                    bound!(vec![fun.clone(), (id2, name2)], ast1),
+                    bound!(vec![fun], ast2),
+                    span,
+                )
+            }
+            Ast::Loop((id, bspan), ast1, ast2, span) => {
+                let ast1 = sub!(ast1);
+                let name = self.binder(&id);
+                Ast::Loop(
+                    (name.clone(), bspan),
+                    ast1,
+                    bound!(vec![(id, name)], ast2),
+                    span,
+                )
+            }
+            Ast::Recur(ast1, span) => Ast::Recur(sub!(ast1), span),
+            Ast::App(ast1, ast2, span) => Ast::App(sub!(ast1), sub!(ast2), span),
+            Ast::Tuple(ast1, ast2, span) => Ast::Tuple(sub!(ast1), sub!(ast2), span),
+            Ast::Proj(ast1, i, span) => Ast::Proj(sub!(ast1), i, span),
+        }
+    }
+}
+
+// `ast` with unique binders, after module members have been resolved to
+// their unique top-level names
+pub fn rename(ast: Ast) -> (Ast, Renames) {
+    let mut renamer = Renamer {
+        taken: HashSet::new(),
+        renames: Renames::default(),
+    };
+    let ast = renamer.expr(ast, &mut vec![]);
+    (ast, renamer.renames)
+}
@@ -0,0 +1,378 @@
+use super::check::*;
+use super::closure::*;
+use super::codegen::*;
+use super::flat::*;
+use super::lexer::*;
+use super::lint::*;
+use super::module::*;
+use super::normal::*;
+use super::parser::*;
+use super::regalloc::*;
+use super::rename::*;
+use super::typing::*;
+use super::unit::*;
+use super::verify::*;
+use super::vm::*;
+use super::*;
+
+use std::time::{Duration, Instant};
+
+// the stages a compilation can stop after, in the order they run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Tokens,
+    Ast,
+    Normal,
+    Closure,
+    Flat,
+    Vm,
+    // vm code with real registers
+    VmAlloc,
+    Asm,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 8] = [
+        Stage::Tokens,
+        Stage::Ast,
+        Stage::Normal,
+        Stage::Closure,
+        Stage::Flat,
+        Stage::Vm,
+        Stage::VmAlloc,
+        Stage::Asm,
+    ];
+    pub fn name(self) -> &'static str {
+        use Stage::*;
+        match self {
+            Tokens => "tokens",
+            Ast => "ast",
+            Normal => "normal",
+            Closure => "closure",
+            Flat => "flat",
+            Vm => "vm",
+            VmAlloc => "vm-alloc",
+            Asm => "asm",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Stage::ALL
+            .iter()
+            .copied()
+            .find(|stage| stage.name() == name)
+    }
+}
+
+// the program as a stage leaves it
+#[derive(Clone, Debug)]
+pub enum Artifact {
+    Tokens(Vec<Token>),
+    // the body of a unit is `Nonaexpr`
+    Ast(parser::Program),
+    Normal(normal::Exp),
+    Closure(closure::Exp),
+    Flat(flat::Program),
+    Vm(vm::Program),
+    // with real registers
+    VmAlloc(vm::Program),
+    Asm(String),
+}
+
+impl Artifact {
+    // the text `--emit` prints; tokens are located in `source`
+    pub fn text(&self, source: &str) -> String {
+        match self {
+            Artifact::Tokens(tokens) => tokens_text(tokens, source),
+            Artifact::Ast(program) => program.to_string(),
+            Artifact::Normal(exp) => format!("{}\n", exp),
+            Artifact::Closure(exp) => format!("{}\n", exp),
+            Artifact::Flat(program) => format!("{}\n", program),
+            Artifact::Vm(program) => program.to_string(),
+            Artifact::VmAlloc(program) => format!("{:#}", program),
+            Artifact::Asm(asm) => asm.clone(),
+        }
+    }
+}
+
+impl Artifact {
+    // the JSON form of the syntax tree and the IRs; tokens and assembly
+    // have none
+    pub fn json(&self) -> Option<Json> {
+        match self {
+            Artifact::Tokens(_) | Artifact::Asm(_) => None,
+            Artifact::Ast(program) => Some(program.to_json()),
+            Artifact::Normal(exp) => Some(exp.to_json()),
+            Artifact::Closure(exp) => Some(exp.to_json()),
+            Artifact::Flat(program) => Some(program.to_json()),
+            Artifact::Vm(program) | Artifact::VmAlloc(program) => Some(program.to_json()),
+        }
+    }
+    // the control-flow graph of the vm code in Graphviz format
+    pub fn dot(&self) -> Option<String> {
+        match self {
+            Artifact::Vm(program) => Some(cfg::dot(program, false)),
+            Artifact::VmAlloc(program) => Some(cfg::dot(program, true)),
+            _ => None,
+        }
+    }
+}
+
+// how far `Session::front` got
+enum Front {
+    // at the stage asked for
+    Stopped(Artifact),
+    // through type checking, with what the program links to
+    Checked(parser::Ast, Linkage),
+}
+
+// the passes in the order they run, as `--time-passes` and
+// `--dump-after` name them
+pub const PASSES: [&str; 13] = [
+    "lex",
+    "parse",
+    "lint",
+    "resolve",
+    "rename",
+    "check_loops",
+    "typecheck",
+    "normalize",
+    "closure",
+    "flat",
+    "trans_pg",
+    "regalloc",
+    "codegen",
+];
+
+// compiles sources to assembly; everything a compilation keeps lives
+// here or in the stages it runs, so sessions can be used side by side
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    // where the source comes from; it names a unit and the directory
+    // the units it uses are looked up in
+    pub file: PathBuf,
+    // compile a unit without `_toplevel`, exporting its functions
+    pub unit: bool,
+    // the source last compiled, with `EXPECT` expanded; spans of errors
+    // and warnings point into it
+    pub source: String,
+    // warnings of the last compilation, even if it failed
+    pub warnings: Vec<CompileError>,
+    // wall time of each pass the last compilation got through
+    pub passes: Vec<(&'static str, Duration)>,
+    // sizes of the code the last compilation produced
+    pub stats: Stats,
+    // check the invariants of each IR after the pass that builds it
+    pub verify_ir: bool,
+    // passes whose result is kept in `dumps`
+    pub dump_after: Vec<String>,
+    // the program as text after each pass in `dump_after` that ran
+    pub dumps: Vec<(&'static str, String)>,
+    // the source names of the binders the last compilation renamed;
+    // errors are reported with the source names
+    pub renames: Renames,
+    // the units and externals the last compilation that got through
+    // type checking refers to
+    pub link: Linkage,
+}
+
+impl Session {
+    pub fn new(file: impl Into<PathBuf>) -> Self {
+        Self {
+            file: file.into(),
+            ..Self::default()
+        }
+    }
+    // the arm64 assembly of `source`
+    pub fn compile(&mut self, source: &str) -> CompileResult<String> {
+        match self.compile_to(source, Stage::Asm)? {
+            Artifact::Asm(asm) => Ok(asm),
+            _ => unreachable!(),
+        }
+    }
+    fn time(&mut self, pass: &'static str, start: Instant) {
+        self.passes.push((pass, start.elapsed()));
+    }
+    fn dump(&mut self, pass: &'static str, text: impl FnOnce() -> String) {
+        if self.dump_after.iter().any(|name| name == pass) {
+            self.dumps.push((pass, text()));
+        }
+    }
+    // what `source` is compiled to by the stages up to `stage`
+    pub fn compile_to(&mut self, source: &str, stage: Stage) -> CompileResult<Artifact> {
+        let (ast, link) = match self.front(source, stage)? {
+            Front::Stopped(artifact) => return Ok(artifact),
+            Front::Checked(ast, link) => (ast, link),
+        };
+        let result = self.lower(ast, link, stage);
+        result.map_err(|err| self.renames.restore(err))
+    }
+    // `source` renamed and type-checked, with the units and externals it
+    // refers to; this is the program the back end would compile
+    pub fn check(&mut self, source: &str) -> CompileResult<(parser::Ast, Linkage)> {
+        match self.front(source, Stage::Normal)? {
+            Front::Checked(ast, link) => Ok((ast, link)),
+            Front::Stopped(_) => unreachable!(),
+        }
+    }
+    // the passes up to type checking
+    fn front(&mut self, source: &str, stage: Stage) -> CompileResult<Front> {
+        self.source = source.to_string();
+        self.warnings = vec![];
+        self.passes = vec![];
+        self.stats = Stats::default();
+        self.dumps = vec![];
+        self.renames = Renames::default();
+        self.link = Linkage::default();
+        let mut link = Linkage {
+            dir: match self.file.parent() {
+                Some(dir) => dir.to_path_buf(),
+                None => PathBuf::from("."),
+            },
+            ..Linkage::default()
+        };
+
+        let start = Instant::now();
+        let tokenset = lex(&mut self.source)?;
+        self.time("lex", start);
+        let source = self.source.clone();
+        self.dump("lex", || tokens_text(&tokenset.tokens, &source));
+        if stage == Stage::Tokens {
+            return Ok(Front::Stopped(Artifact::Tokens(tokenset.tokens)));
+        }
+
+        let ast = if self.unit {
+            let start = Instant::now();
+            let items = parse_unit(tokenset)?;
+            self.time("parse", start);
+            let body = parser::Ast::Nonaexpr;
+            let program = parser::Program { items, body };
+            self.dump("parse", || program.to_string());
+            let start = Instant::now();
+            self.warnings = lint_unit(&program.items);
+            self.time("lint", start);
+            self.dump("lint", || program.to_string());
+            if stage == Stage::Ast {
+                return Ok(Front::Stopped(Artifact::Ast(program)));
+            }
+            let items = program.items;
+            let start = Instant::now();
+            let ast = resolve_unit(&unit_name(&self.file), items, &mut link)?;
+            self.time("resolve", start);
+            ast
+        } else {
+            let start = Instant::now();
+            let program = parse(tokenset)?;
+            self.time("parse", start);
+            self.dump("parse", || program.to_string());
+            let start = Instant::now();
+            self.warnings = lint(&program);
+            self.time("lint", start);
+            self.dump("lint", || program.to_string());
+            if stage == Stage::Ast {
+                return Ok(Front::Stopped(Artifact::Ast(program)));
+            }
+            let start = Instant::now();
+            let ast = resolve_modules(program, &mut link)?;
+            self.time("resolve", start);
+            ast
+        };
+        self.dump("resolve", || ast.to_string());
+
+        let start = Instant::now();
+        let (ast, renames) = rename(ast);
+        self.time("rename", start);
+        self.dump("rename", || ast.to_string());
+        self.renames = renames;
+        let result = self.analyze(&ast, &link);
+        result.map_err(|err| self.renames.restore(err))?;
+        self.link = link.clone();
+        Ok(Front::Checked(ast, link))
+    }
+    // the checks after renaming, whose errors may name renamed binders
+    fn analyze(&mut self, ast: &parser::Ast, link: &Linkage) -> CompileResult<()> {
+        let start = Instant::now();
+        check_loops(ast)?;
+        self.time("check_loops", start);
+        self.dump("check_loops", || ast.to_string());
+        let start = Instant::now();
+        typecheck(ast, link)?;
+        self.time("typecheck", start);
+        self.dump("typecheck", || ast.to_string());
+        Ok(())
+    }
+    // the passes after type checking, whose errors may name renamed
+    // binders too
+    fn lower(&mut self, ast: parser::Ast, link: Linkage, stage: Stage) -> CompileResult<Artifact> {
+        let start = Instant::now();
+        let norm_ast = normalize(ast, &link)?;
+        self.time("normalize", start);
+        self.dump("normalize", || format!("{}\n", norm_ast));
+        if self.verify_ir {
+            verify_normal(&norm_ast, &link)?;
+        }
+        if stage == Stage::Normal {
+            return Ok(Artifact::Normal(norm_ast));
+        }
+
+        let start = Instant::now();
+        let (closed_norm, closures) = closure(norm_ast, &link)?;
+        self.time("closure", start);
+        self.dump("closure", || format!("{}\n", closed_norm));
+        if self.verify_ir {
+            verify_closure(&closed_norm, &link)?;
+        }
+        self.stats.closures = closures;
+        if stage == Stage::Closure {
+            return Ok(Artifact::Closure(closed_norm));
+        }
+
+        let start = Instant::now();
+        let mut flatten_form = flat(closed_norm, &link)?;
+        if self.unit {
+            flatten_form.recs.retain(|rec| rec.0 != "_toplevel");
+        }
+        self.time("flat", start);
+        self.dump("flat", || format!("{}\n", flatten_form));
+        if self.verify_ir {
+            verify_flat(&flatten_form, &link)?;
+        }
+        self.stats.recdecls = flatten_form.recs.len();
+        if stage == Stage::Flat {
+            return Ok(Artifact::Flat(flatten_form));
+        }
+
+        let start = Instant::now();
+        let mut virtual_code = trans_pg(flatten_form)?;
+        self.time("trans_pg", start);
+        self.dump("trans_pg", || virtual_code.to_string());
+        if self.verify_ir {
+            verify_vm(&virtual_code)?;
+        }
+        self.stats.count_vm("trans_pg", &virtual_code);
+        if stage == Stage::Vm {
+            return Ok(Artifact::Vm(virtual_code));
+        }
+
+        let start = Instant::now();
+        regalloc(&mut virtual_code)?;
+        self.time("regalloc", start);
+        self.dump("regalloc", || format!("{:#}", virtual_code));
+        if self.verify_ir {
+            verify_vm(&virtual_code)?;
+        }
+        self.stats.count_vm("regalloc", &virtual_code);
+        if stage == Stage::VmAlloc {
+            return Ok(Artifact::VmAlloc(virtual_code));
+        }
+
+        let start = Instant::now();
+        let asm = codegen(virtual_code, &link)?;
+        self.time("codegen", start);
+        self.dump("codegen", || asm.clone());
+        Ok(Artifact::Asm(asm))
+    }
+    // prints `err` and the errors it carries against the last source
+    pub fn emit(&self, err: &CompileError, json: bool) {
+        err.emit(&self.file.to_string_lossy(), &self.source, json);
+    }
+}
@@ -0,0 +1,134 @@
+use super::*;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+// one function of the vm code
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeclStats {
+    pub name: String,
+    pub instrs: usize,
+    // frame size in 4-byte stack slots (`Decl.vc`)
+    pub frame: i32,
+    // distinct virtual registers and the real ones they were given
+    pub vregs: usize,
+    pub regs: usize,
+}
+
+// sizes of the code of one compilation; stages that did not run leave
+// their counts at zero
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub closures: usize,
+    pub recdecls: usize,
+    // the vm code after each pass that produces it, in pass order
+    pub vm: Vec<(&'static str, Vec<DeclStats>)>,
+}
+
+impl Stats {
+    pub fn count_vm(&mut self, pass: &'static str, program: &vm::Program) {
+        let decls = program
+            .decls
+            .iter()
+            .map(|decl| {
+                let regs: Vec<&vm::Reg> = decl
+                    .instrs
+                    .iter()
+                    .flat_map(|(instr, _)| instr.regs())
+                    .collect();
+                DeclStats {
+                    name: decl.funlb.clone(),
+                    instrs: decl.instrs.len(),
+                    frame: decl.vc,
+                    vregs: regs.iter().map(|r| r.vm).collect::<HashSet<_>>().len(),
+                    regs: regs
+                        .iter()
+                        .filter(|r| r.rm >= 0)
+                        .map(|r| r.rm)
+                        .collect::<HashSet<_>>()
+                        .len(),
+                }
+            })
+            .collect();
+        self.vm.push((pass, decls));
+    }
+    // the counts of the vm code `pass` left, if it ran
+    pub fn after(&self, pass: &str) -> Option<&[DeclStats]> {
+        self.vm
+            .iter()
+            .find(|(name, _)| *name == pass)
+            .map(|(_, decls)| &decls[..])
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "closures allocated: {}", self.closures)?;
+        writeln!(f, "functions (Recdecl): {}", self.recdecls)?;
+        for (pass, decls) in &self.vm {
+            let instrs: usize = decls.iter().map(|decl| decl.instrs).sum();
+            writeln!(f, "vm instructions after {}: {}", pass, instrs)?;
+        }
+        // one row per function, with a pair of columns per pass so the
+        // shrinkage of each function can be read off its row
+        let names = match self.vm.first() {
+            Some((_, decls)) if !decls.is_empty() => decls,
+            _ => return Ok(()),
+        };
+        let width = names
+            .iter()
+            .map(|decl| decl.name.len())
+            .max()
+            .unwrap()
+            .max("function".len());
+        write!(f, "{:w$}", "function", w = width)?;
+        for (pass, _) in &self.vm {
+            write!(f, "  {:>w$}", pass, w = "instrs".len().max(pass.len()))?;
+        }
+        writeln!(f, "  {:>5}  {:>5}  {:>4}", "frame", "vregs", "regs")?;
+        for (i, decl) in names.iter().enumerate() {
+            write!(f, "{:w$}", decl.name, w = width)?;
+            for (pass, decls) in &self.vm {
+                let instrs = decls.get(i).map_or(0, |decl| decl.instrs);
+                write!(f, "  {:>w$}", instrs, w = "instrs".len().max(pass.len()))?;
+            }
+            // the frame and registers are those of the last pass
+            let last = &self.vm.last().unwrap().1[i];
+            writeln!(
+                f,
+                "  {:>5}  {:>5}  {:>4}",
+                last.frame, last.vregs, last.regs
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// wall time of each pass that ran, in milliseconds, and their total
+pub fn passes_text(passes: &[(&str, Duration)]) -> String {
+    let width = passes
+        .iter()
+        .map(|(pass, _)| pass.len())
+        .max()
+        .unwrap_or(0)
+        .max("total".len());
+    let mut out = String::new();
+    let mut total = Duration::default();
+    for (pass, time) in passes {
+        total += *time;
+        out.push_str(&format!(
+            "{:w$}  {:>9.3}ms\n",
+            pass,
+            time.as_secs_f64() * 1000.0,
+            w = width
+        ));
+    }
+    out.push_str(&format!(
+        "{:w$}  {:>9.3}ms\n",
+        "total",
+        total.as_secs_f64() * 1000.0,
+        w = width
+    ));
+    out
+}
@@ -0,0 +1,331 @@
+use super::lexer::TokenType;
+use super::parser::*;
+use super::*;
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Int,
+    Bool,
+    Fun(Box<Type>, Box<Type>),
+    Tuple(Box<Type>, Box<Type>),
+    Var(usize),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Type::*;
+        match self {
+            Int => write!(f, "int"),
+            Bool => write!(f, "bool"),
+            Fun(ty1, ty2) => match **ty1 {
+                Fun(..) => write!(f, "({}) -> {}", ty1, ty2),
+                _ => write!(f, "{} -> {}", ty1, ty2),
+            },
+            Tuple(ty1, ty2) => write!(f, "({} * {})", ty1, ty2),
+            Var(n) => write!(f, "'t{}", n),
+        }
+    }
+}
+
+fn type_error(message: String, span: Span, note: Option<(Span, String)>) -> CompileError {
+    CompileError {
+        kind: ErrorKind::Type,
+        severity: Severity::Error,
+        message,
+        span: Some(span),
+        note,
+        more: vec![],
+    }
+}
+
+// substitution of type variables, with the span of the expression
+// that fixed each variable to a type
+struct Infer<'a> {
+    link: &'a Linkage,
+    subst: Vec<Option<Type>>,
+    origins: Vec<Option<Span>>,
+    vars: Vec<(Id, Type)>,
+    loops: Vec<(Type, Span)>,
+    recs: Vec<(Id, Type)>,
+}
+
+impl<'a> Infer<'a> {
+    fn new(link: &'a Linkage) -> Self {
+        Self {
+            link,
+            subst: vec![],
+            origins: vec![],
+            vars: vec![],
+            loops: vec![],
+            recs: vec![],
+        }
+    }
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        self.origins.push(None);
+        Type::Var(self.subst.len() - 1)
+    }
+    fn resolve(&self, ty: &Type) -> Type {
+        use Type::*;
+        match ty {
+            Var(n) => match &self.subst[*n] {
+                Some(ty) => self.resolve(ty),
+                None => Var(*n),
+            },
+            Fun(ty1, ty2) => Fun(Box::new(self.resolve(ty1)), Box::new(self.resolve(ty2))),
+            Tuple(ty1, ty2) => Tuple(Box::new(self.resolve(ty1)), Box::new(self.resolve(ty2))),
+            _ => ty.clone(),
+        }
+    }
+    fn origin(&self, ty: &Type) -> Option<Span> {
+        let mut ty = ty;
+        while let Type::Var(n) = ty {
+            if self.origins[*n].is_some() {
+                return self.origins[*n];
+            }
+            match &self.subst[*n] {
+                Some(next) => ty = next,
+                None => break,
+            }
+        }
+        None
+    }
+    fn occurs(&self, n: usize, ty: &Type) -> bool {
+        use Type::*;
+        match self.resolve(ty) {
+            Var(m) => n == m,
+            Fun(ty1, ty2) | Tuple(ty1, ty2) => self.occurs(n, &ty1) || self.occurs(n, &ty2),
+            _ => false,
+        }
+    }
+    fn prune(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(n) => match &self.subst[*n] {
+                Some(ty) => self.prune(ty),
+                None => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+    fn unify(&mut self, ty1: &Type, ty2: &Type, span: Span) -> bool {
+        use Type::*;
+        let origin1 = self.origin(ty1);
+        let origin2 = self.origin(ty2);
+        match (self.prune(ty1), self.prune(ty2)) {
+            (Int, Int) | (Bool, Bool) => true,
+            (Var(n), Var(m)) if n == m => true,
+            (Var(n), ty) => self.bind(n, ty, origin2.unwrap_or(span)),
+            (ty, Var(n)) => self.bind(n, ty, origin1.unwrap_or(span)),
+            (Fun(a1, r1), Fun(a2, r2)) => self.unify(&a1, &a2, span) && self.unify(&r1, &r2, span),
+            (Tuple(a1, b1), Tuple(a2, b2)) => {
+                self.unify(&a1, &a2, span) && self.unify(&b1, &b2, span)
+            }
+            _ => false,
+        }
+    }
+    fn bind(&mut self, n: usize, ty: Type, origin: Span) -> bool {
+        if self.occurs(n, &ty) {
+            return false;
+        }
+        self.subst[n] = Some(ty);
+        self.origins[n] = Some(origin);
+        true
+    }
+    // `found` is the type of the expression at `span`, `hint` the place
+    // that introduced `expected` when no type variable remembers it
+    fn expect(
+        &mut self,
+        expected: &Type,
+        found: &Type,
+        span: Span,
+        hint: Option<Span>,
+    ) -> CompileResult<()> {
+        let origin = self.origin(expected).or(hint);
+        let forigin = self.origin(found);
+        if self.unify(expected, found, span) {
+            return Ok(());
+        }
+        let expected = self.resolve(expected);
+        let found = self.resolve(found);
+        let note = match (origin, forigin) {
+            (Some(ospan), _) => Some((
+                ospan,
+                format!("expected type `{}` was introduced here.", expected),
+            )),
+            (None, Some(ospan)) => Some((
+                ospan,
+                format!("found type `{}` was introduced here.", found),
+            )),
+            (None, None) => None,
+        };
+        Err(type_error(
+            format!("expected {}, found {}.", expected, found),
+            span,
+            note,
+        ))
+    }
+    // a variable standing for `ty` that remembers `span` as its origin
+    fn introduce(&mut self, ty: Type, span: Span) -> Type {
+        let origin = self.origin(&ty).unwrap_or(span);
+        let var = self.fresh();
+        if let Type::Var(n) = var {
+            self.bind(n, ty, origin);
+        }
+        var
+    }
+    fn lookup(&self, id: &Id, span: Span) -> CompileResult<Type> {
+        for (vid, ty) in self.vars.iter().rev() {
+            if vid == id {
+                return Ok(ty.clone());
+            }
+        }
+        if let Some(unit::Global { ty: Some(ty), .. }) = self.link.find_global(id) {
+            return Ok(ty.clone());
+        }
+        if let Some(external) = self.link.find_external(id) {
+            return Ok(external.ty.clone());
+        }
+        Err(type_error(
+            format!("unbound variable `{}`.", id),
+            span,
+            None,
+        ))
+    }
+    fn infer(&mut self, ast: &Ast) -> CompileResult<Type> {
+        match ast {
+            Ast::Nonaexpr | Ast::Error(..) => Ok(self.fresh()),
+            Ast::ILit(..) => Ok(Type::Int),
+            Ast::BLit(..) => Ok(Type::Bool),
+            Ast::Binop(ttype, ast1, ast2, _) => {
+                let ty1 = self.infer(ast1)?;
+                let ty2 = self.infer(ast2)?;
+                match ttype {
+                    TokenType::Eq => {
+                        self.expect(&ty1, &ty2, ast2.span(), Some(ast1.span()))?;
+                        Ok(Type::Bool)
+                    }
+                    _ => {
+                        self.expect(&Type::Int, &ty1, ast1.span(), None)?;
+                        self.expect(&Type::Int, &ty2, ast2.span(), None)?;
+                        if let TokenType::Lt = ttype {
+                            Ok(Type::Bool)
+                        } else {
+                            Ok(Type::Int)
+                        }
+                    }
+                }
+            }
+            Ast::If(ast1, ast2, ast3, _) => {
+                let ty1 = self.infer(ast1)?;
+                self.expect(&Type::Bool, &ty1, ast1.span(), None)?;
+                let ty2 = self.infer(ast2)?;
+                let ty3 = self.infer(ast3)?;
+                self.expect(&ty2, &ty3, ast3.span(), Some(ast2.span()))?;
+                Ok(ty2)
+            }
+            Ast::Fun((id, _), ast1, _) => {
+                let arg = self.fresh();
+                self.vars.push((id.clone(), arg.clone()));
+                let body = self.infer(ast1);
+                self.vars.pop();
+                Ok(Type::Fun(Box::new(arg), Box::new(body?)))
+            }
+            Ast::Var(id, span) => self.lookup(id, *span),
+            Ast::Let((id, _), ast1, ast2, _) => {
+                let ty1 = self.infer(ast1)?;
+                let ty1 = self.introduce(ty1, ast1.span());
+                self.vars.push((id.clone(), ty1));
+                let ty2 = self.infer(ast2);
+                self.vars.pop();
+                ty2
+            }
+            Ast::Rec((id1, _), (id2, _), ast1, ast2, _) => {
+                let arg = self.fresh();
+                let ret = self.fresh();
+                let funty = Type::Fun(Box::new(arg.clone()), Box::new(ret.clone()));
+                self.recs.push((id1.clone(), funty.clone()));
+                self.vars.push((id1.clone(), funty));
+                self.vars.push((id2.clone(), arg));
+                let body = self.infer(ast1);
+                self.vars.pop();
+                let ty2 = body
+                    .and_then(|body| self.expect(&ret, &body, ast1.span(), None))
+                    .and_then(|_| self.infer(ast2));
+                self.vars.pop();
+                ty2
+            }
+            Ast::Loop((id, _), ast1, ast2, _) => {
+                let ty1 = self.infer(ast1)?;
+                self.vars.push((id.clone(), ty1.clone()));
+                self.loops.push((ty1, ast1.span()));
+                let ty2 = self.infer(ast2);
+                self.loops.pop();
+                self.vars.pop();
+                ty2
+            }
+            Ast::Recur(ast1, _) => {
+                let ty1 = self.infer(ast1)?;
+                if let Some((loopty, lspan)) = self.loops.last().cloned() {
+                    self.expect(&loopty, &ty1, ast1.span(), Some(lspan))?;
+                }
+                Ok(self.fresh())
+            }
+            Ast::App(ast1, ast2, _) => {
+                let ty1 = self.infer(ast1)?;
+                let ty2 = self.infer(ast2)?;
+                let arg = self.fresh();
+                let ret = self.fresh();
+                let funty = Type::Fun(Box::new(arg.clone()), Box::new(ret.clone()));
+                if !self.unify(&funty, &ty1, ast1.span()) {
+                    return Err(type_error(
+                        format!("expected function, found {}.", self.resolve(&ty1)),
+                        ast1.span(),
+                        None,
+                    ));
+                }
+                self.expect(&arg, &ty2, ast2.span(), None)?;
+                Ok(ret)
+            }
+            Ast::Tuple(ast1, ast2, _) => {
+                let ty1 = self.infer(ast1)?;
+                let ty2 = self.infer(ast2)?;
+                Ok(Type::Tuple(Box::new(ty1), Box::new(ty2)))
+            }
+            Ast::Proj(ast1, i, span) => {
+                let ty1 = self.infer(ast1)?;
+                let fst = self.fresh();
+                let snd = self.fresh();
+                let tuplety = Type::Tuple(Box::new(fst.clone()), Box::new(snd.clone()));
+                self.expect(&tuplety, &ty1, ast1.span(), None)?;
+                match i {
+                    1 => Ok(fst),
+                    2 => Ok(snd),
+                    _ => Err(type_error(
+                        format!("tuple index {} is out of range.", i),
+                        *span,
+                        None,
+                    )),
+                }
+            }
+        }
+    }
+}
+
+pub fn typecheck(ast: &Ast, link: &Linkage) -> CompileResult<Type> {
+    let mut infer = Infer::new(link);
+    let ty = infer.infer(ast)?;
+    Ok(infer.resolve(&ty))
+}
+
+// types of the functions bound by `let rec` in `ast`
+pub fn binder_types(ast: &Ast, link: &Linkage) -> CompileResult<Vec<(Id, Type)>> {
+    let mut infer = Infer::new(link);
+    infer.infer(ast)?;
+    Ok(infer
+        .recs
+        .iter()
+        .map(|(id, ty)| (id.clone(), infer.resolve(ty)))
+        .collect())
+}
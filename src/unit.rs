@@ -0,0 +1,259 @@
+use super::lexer::*;
+use super::parser::*;
+use super::typing::Type;
+use super::*;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// a top-level function that is reached through a symbol instead of a
+// closure built at run time
+#[derive(Clone, Debug)]
+pub struct Global {
+    pub name: Id,
+    pub ty: Option<Type>,
+    pub defined: bool,
+    pub exported: bool,
+}
+
+impl Linkage {
+    pub fn add_global(&mut self, name: &str, ty: Option<Type>, defined: bool) {
+        self.globals.push(Global {
+            name: name.to_string(),
+            ty,
+            defined,
+            exported: defined,
+        });
+    }
+    pub fn find_global(&self, name: &str) -> Option<&Global> {
+        self.globals.iter().find(|g| g.name == name)
+    }
+    pub fn is_global(&self, name: &str) -> bool {
+        self.find_global(name).is_some()
+    }
+    // the file of unit `module` with extension `ext`
+    fn source_file(&self, module: &str, ext: &str) -> PathBuf {
+        let mut file = module[..1].to_lowercase();
+        file.push_str(&module[1..]);
+        file.push('.');
+        file.push_str(ext);
+        self.dir.join(file)
+    }
+}
+
+fn mangle(name: &str) -> String {
+    format!("ruscaml_{}", name.replace('.', "_"))
+}
+
+// label of the code of global function `name`
+pub fn code_symbol(name: &str) -> String {
+    mangle(name)
+}
+
+// label of the static closure of global function `name`
+pub fn closure_symbol(name: &str) -> String {
+    format!("{}_clo", mangle(name))
+}
+
+// module name of the unit compiled from `file`
+pub fn unit_name(file: &Path) -> Id {
+    let stem = file.file_stem().unwrap().to_string_lossy().to_string();
+    let mut name = stem[..1].to_uppercase();
+    name.push_str(&stem[1..]);
+    name
+}
+
+// `val name : type` declarations of an interface file
+fn parse_signature(program: &str) -> CompileResult<Vec<(Id, Type)>> {
+    let mut tokenset = tokenize(program)?;
+    let mut vals = vec![];
+    while !tokenset.eof() {
+        tokenset.assert_ttype(TokenType::Val)?;
+        let id = tokenset.curid()?;
+        tokenset.pos += 1;
+        tokenset.assert_ttype(TokenType::Colon)?;
+        vals.push((id, typexpr(&mut tokenset)?));
+    }
+    Ok(vals)
+}
+
+// the signature of `module`, if it has an interface file; errors are
+// reported by line because the file is not the program being compiled
+pub fn load_signature(module: &str, link: &Linkage) -> CompileResult<Option<Vec<(Id, Type)>>> {
+    let file = link.source_file(module, "mli");
+    let program = match fs::read_to_string(&file) {
+        Ok(program) => program,
+        Err(_) => return Ok(None),
+    };
+    parse_signature(&program).map(Some).map_err(|err| {
+        let (line, column) = line_col(&program, err.span.map_or(0, |span| span.lo));
+        CompileError::unlocated(
+            ErrorKind::Unit,
+            format!(
+                "{}: {} Line: {}, Column: {}.",
+                file.display(),
+                err.message,
+                line,
+                column
+            ),
+        )
+    })
+}
+
+// whether `found` is an instance of the inferred type `inferred`
+fn instance(inferred: &Type, found: &Type, vars: &mut HashMap<usize, Type>) -> bool {
+    use Type::*;
+    match (inferred, found) {
+        (Int, Int) | (Bool, Bool) => true,
+        (Var(n), ty) => match vars.get(n) {
+            Some(bound) => bound == ty,
+            None => {
+                vars.insert(*n, ty.clone());
+                true
+            }
+        },
+        (Fun(a1, r1), Fun(a2, r2)) | (Tuple(a1, r1), Tuple(a2, r2)) => {
+            instance(a1, a2, vars) && instance(r1, r2, vars)
+        }
+        _ => false,
+    }
+}
+
+fn free_vars(ast: &Ast, link: &Linkage, bound: &mut Vec<Id>, fvs: &mut Vec<(Id, Span)>) {
+    macro_rules! bound {
+        ($ids: expr, $ast: expr) => {{
+            let n = bound.len();
+            for id in $ids {
+                bound.push(id.clone());
+            }
+            free_vars($ast, link, bound, fvs);
+            bound.truncate(n);
+        }};
+    }
+    match ast {
+        Ast::Var(id, span) => {
+            if !bound.contains(id) && !link.is_global(id) && link.find_external(id).is_none() {
+                fvs.push((id.clone(), *span));
+            }
+        }
+        Ast::Nonaexpr | Ast::Error(..) | Ast::ILit(..) | Ast::BLit(..) => {}
+        Ast::Binop(_, ast1, ast2, _) | Ast::App(ast1, ast2, _) | Ast::Tuple(ast1, ast2, _) => {
+            free_vars(ast1, link, bound, fvs);
+            free_vars(ast2, link, bound, fvs);
+        }
+        Ast::If(ast1, ast2, ast3, _) => {
+            free_vars(ast1, link, bound, fvs);
+            free_vars(ast2, link, bound, fvs);
+            free_vars(ast3, link, bound, fvs);
+        }
+        Ast::Fun((id, _), ast1, _) => bound!([id], ast1),
+        Ast::Let((id, _), ast1, ast2, _) | Ast::Loop((id, _), ast1, ast2, _) => {
+            free_vars(ast1, link, bound, fvs);
+            bound!([id], ast2);
+        }
+        Ast::Rec((id1, _), (id2, _), ast1, ast2, _) => {
+            bound!([id1, id2], ast1);
+            bound!([id1], ast2);
+        }
+        Ast::Recur(ast1, _) | Ast::Proj(ast1, _, _) => free_vars(ast1, link, bound, fvs),
+    }
+}
+
+fn check_unit(name: &str, items: Vec<Item>, link: &mut Linkage) -> CompileResult<Ast> {
+    let binds = module::flatten_unit(name, items, link)?;
+    let mut funs = vec![];
+    for bind in &binds {
+        match bind {
+            Item::Rec((id, _), _, _, span) => {
+                link.add_global(id, None, true);
+                funs.push((id.clone(), *span));
+            }
+            Item::Let((id, _), _, span) => {
+                return Err(CompileError::new(
+                    ErrorKind::Unit,
+                    format!(
+                        "`{}` is not a function; only functions can be defined at the top level of a unit.",
+                        &id[name.len() + 1..]
+                    ),
+                    *span,
+                ));
+            }
+            Item::Module(..) | Item::Open(..) | Item::External(..) => unreachable!(),
+        }
+    }
+    // exported functions are not closures, so they may not capture anything
+    for bind in &binds {
+        if let Item::Rec((id, _), (arg, _), ast, span) = bind {
+            let mut fvs = vec![];
+            free_vars(ast, link, &mut vec![id.clone(), arg.clone()], &mut fvs);
+            if let Some((fv, fvspan)) = fvs.into_iter().next() {
+                return Err(CompileError::new(
+                    ErrorKind::Unit,
+                    format!(
+                        "unit function `{}` cannot capture `{}`.",
+                        &id[name.len() + 1..],
+                        fv
+                    ),
+                    fvspan,
+                )
+                .with_note(*span, String::from("the function is defined here.")));
+            }
+        }
+    }
+    let mut ast = Ast::ILit(0, Span::default());
+    for bind in binds.into_iter().rev() {
+        if let Item::Rec(id, arg, ast1, span) = bind {
+            ast = Ast::Rec(id, arg, Box::new(ast1), Box::new(ast), span);
+        }
+    }
+    external::check_calls(&ast, link)?;
+    let types = typing::binder_types(&ast, link)?;
+    // the interface file lists the exported functions and their types
+    if let Some(vals) = load_signature(name, link)? {
+        for g in link.globals.iter_mut() {
+            if g.defined {
+                g.exported = false;
+            }
+        }
+        for (id, ty) in vals {
+            let gname = format!("{}.{}", name, id);
+            let (inferred, span) = match funs.iter().find(|(f, _)| *f == gname) {
+                Some((_, span)) => (
+                    types.iter().find(|(f, _)| *f == gname).unwrap().1.clone(),
+                    *span,
+                ),
+                None => {
+                    return Err(CompileError::unlocated(
+                        ErrorKind::Unit,
+                        format!(
+                            "{}: `{}` is declared but not defined.",
+                            link.source_file(name, "mli").display(),
+                            id
+                        ),
+                    ));
+                }
+            };
+            if !instance(&inferred, &ty, &mut HashMap::new()) {
+                return Err(CompileError::new(
+                    ErrorKind::Unit,
+                    format!(
+                        "`{}` has type {} but the interface declares {}.",
+                        id, inferred, ty
+                    ),
+                    span,
+                ));
+            }
+            for g in link.globals.iter_mut() {
+                if g.name == gname {
+                    g.exported = true;
+                    g.ty = Some(ty.clone());
+                }
+            }
+        }
+    }
+    Ok(ast)
+}
+
+pub fn resolve_unit(name: &str, items: Vec<Item>, link: &mut Linkage) -> CompileResult<Ast> {
+    check_unit(name, items, link)
+}
@@ -0,0 +1,420 @@
+use super::*;
+
+use std::collections::HashSet;
+
+// checks of what each IR promises the passes after it, for `--verify-ir`;
+// a failure is a bug in the pass that built the IR
+
+fn ir_error(ir: &str, message: String, span: Span) -> CompileError {
+    CompileError::new(ErrorKind::Internal, format!("{} IR: {}", ir, message), span)
+}
+
+fn unbound(ir: &str, id: &str, span: Span) -> CompileError {
+    ir_error(ir, format!("`{}` is not bound.", id), span)
+}
+
+// the arguments of a `normal::Cexp` are `Value`s, so they are atoms by
+// construction; what is left to check is that every variable is bound
+fn normal_value(val: &NV, scope: &[Id], link: &Linkage, span: Span) -> CompileResult<()> {
+    match val {
+        NV::Var(id) if !scope.contains(id) && !link.is_global(id) => {
+            Err(unbound("normal", id, span))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn normal_exp(exp: &normal::Exp, scope: &mut Vec<Id>, link: &Linkage) -> CompileResult<()> {
+    use normal::Exp::*;
+    let depth = scope.len();
+    match exp {
+        Compexp(cexp, span) => normal_cexp(cexp, scope, link, *span)?,
+        Let(id, cexp, exp, span) | Loop(id, cexp, exp, span) => {
+            normal_cexp(cexp, scope, link, *span)?;
+            scope.push(id.clone());
+            normal_exp(exp, scope, link)?;
+        }
+        Letrec(id, arg, body, exp, _) => {
+            scope.push(id.clone());
+            scope.push(arg.clone());
+            normal_exp(body, scope, link)?;
+            scope.pop();
+            normal_exp(exp, scope, link)?;
+        }
+        Recur(val, span) => normal_value(val, scope, link, *span)?,
+    }
+    scope.truncate(depth);
+    Ok(())
+}
+
+fn normal_cexp(
+    cexp: &normal::Cexp,
+    scope: &mut Vec<Id>,
+    link: &Linkage,
+    span: Span,
+) -> CompileResult<()> {
+    use normal::Cexp::*;
+    match cexp {
+        Val(val) | Proj(val, _) => normal_value(val, scope, link, span),
+        Binop(_, val1, val2) | App(val1, val2) | Tuple(val1, val2) => {
+            normal_value(val1, scope, link, span)?;
+            normal_value(val2, scope, link, span)
+        }
+        If(val, exp1, exp2) => {
+            normal_value(val, scope, link, span)?;
+            normal_exp(exp1, scope, link)?;
+            normal_exp(exp2, scope, link)
+        }
+        Extcall(_, vals) => vals
+            .iter()
+            .try_for_each(|val| normal_value(val, scope, link, span)),
+    }
+}
+
+// renaming gives every binder a name of its own, and normalization
+// only adds fresh ones
+fn normal_binders(exp: &normal::Exp, binders: &mut HashSet<Id>) -> CompileResult<()> {
+    use normal::Exp::*;
+    let mut bind = |id: &Id, span: Span| {
+        if binders.insert(id.clone()) {
+            Ok(())
+        } else {
+            Err(ir_error(
+                "normal",
+                format!("`{}` is bound twice.", id),
+                span,
+            ))
+        }
+    };
+    match exp {
+        Compexp(..) | Recur(..) => {}
+        Let(id, _, _, span) | Loop(id, _, _, span) => bind(id, *span)?,
+        Letrec(id, arg, _, _, span) => {
+            bind(id, *span)?;
+            bind(arg, *span)?;
+        }
+    }
+    match exp {
+        Compexp(cexp, _) | Let(_, cexp, _, _) | Loop(_, cexp, _, _) => {
+            if let normal::Cexp::If(_, exp1, exp2) = &**cexp {
+                normal_binders(exp1, binders)?;
+                normal_binders(exp2, binders)?;
+            }
+        }
+        Letrec(_, _, body, _, _) => normal_binders(body, binders)?,
+        Recur(..) => {}
+    }
+    match exp {
+        Let(_, _, exp, _) | Loop(_, _, exp, _) | Letrec(_, _, _, exp, _) => {
+            normal_binders(exp, binders)
+        }
+        Compexp(..) | Recur(..) => Ok(()),
+    }
+}
+
+pub fn verify_normal(exp: &normal::Exp, link: &Linkage) -> CompileResult<()> {
+    normal_exp(exp, &mut vec![], link)?;
+    normal_binders(exp, &mut HashSet::new())
+}
+
+// closure conversion passes everything a function uses as parameters,
+// so its body may refer only to them, to functions and to globals
+struct Closed<'a> {
+    link: &'a Linkage,
+    functions: HashSet<Id>,
+    // the function whose body is being checked
+    function: Option<Id>,
+}
+
+impl Closed<'_> {
+    fn value(&self, val: &NV, scope: &[Id], span: Span) -> CompileResult<()> {
+        match val {
+            NV::Var(id)
+                if !scope.contains(id)
+                    && !self.functions.contains(id)
+                    && !self.link.is_global(id) =>
+            {
+                Err(match &self.function {
+                    Some(function) => ir_error(
+                        "closure",
+                        format!("function `{}` has free variable `{}`.", function, id),
+                        span,
+                    ),
+                    None => unbound("closure", id, span),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+    fn exp(&mut self, exp: &closure::Exp, scope: &mut Vec<Id>) -> CompileResult<()> {
+        use closure::Exp::*;
+        let depth = scope.len();
+        match exp {
+            Compexp(cexp, span) => self.cexp(cexp, scope, *span)?,
+            Let(id, cexp, exp, span) | Loop(id, cexp, exp, span) => {
+                self.cexp(cexp, scope, *span)?;
+                scope.push(id.clone());
+                self.exp(exp, scope)?;
+            }
+            Letrec(id, args, body, exp, _) => {
+                let outer = self.function.replace(id.clone());
+                self.exp(body, &mut args.clone())?;
+                self.function = outer;
+                self.exp(exp, scope)?;
+            }
+            Recur(val, span) => self.value(val, scope, *span)?,
+        }
+        scope.truncate(depth);
+        Ok(())
+    }
+    fn cexp(&mut self, cexp: &closure::Cexp, scope: &mut Vec<Id>, span: Span) -> CompileResult<()> {
+        use closure::Cexp::*;
+        match cexp {
+            Val(val) | Proj(val, _) => self.value(val, scope, span),
+            Binop(_, val1, val2) => {
+                self.value(val1, scope, span)?;
+                self.value(val2, scope, span)
+            }
+            App(val, vals) => {
+                self.value(val, scope, span)?;
+                vals.iter().try_for_each(|val| self.value(val, scope, span))
+            }
+            Tuple(vals) | Extcall(_, vals) => {
+                vals.iter().try_for_each(|val| self.value(val, scope, span))
+            }
+            If(val, exp1, exp2) => {
+                self.value(val, scope, span)?;
+                self.exp(exp1, scope)?;
+                self.exp(exp2, scope)
+            }
+        }
+    }
+}
+
+fn closure_functions(exp: &closure::Exp, functions: &mut HashSet<Id>) {
+    use closure::Exp::*;
+    match exp {
+        Compexp(cexp, _) | Let(_, cexp, _, _) | Loop(_, cexp, _, _) => {
+            if let closure::Cexp::If(_, exp1, exp2) = &**cexp {
+                closure_functions(exp1, functions);
+                closure_functions(exp2, functions);
+            }
+        }
+        Letrec(id, _, body, _, _) => {
+            functions.insert(id.clone());
+            closure_functions(body, functions);
+        }
+        Recur(..) => {}
+    }
+    match exp {
+        Let(_, _, exp, _) | Loop(_, _, exp, _) | Letrec(_, _, _, exp, _) => {
+            closure_functions(exp, functions)
+        }
+        _ => {}
+    }
+}
+
+pub fn verify_closure(exp: &closure::Exp, link: &Linkage) -> CompileResult<()> {
+    let mut functions = HashSet::new();
+    closure_functions(exp, &mut functions);
+    let mut closed = Closed {
+        link,
+        functions,
+        function: None,
+    };
+    closed.exp(exp, &mut vec![])
+}
+
+// a `Recdecl` may use only its parameters and its own bindings, and
+// each function label must be one of the program or a static closure of
+// a global
+struct Flat<'a> {
+    labels: HashSet<Id>,
+    recdecl: &'a str,
+}
+
+impl Flat<'_> {
+    fn value(&self, val: &FV, scope: &[Id], span: Span) -> CompileResult<()> {
+        match val {
+            FV::Var(id) if !scope.contains(id) => Err(ir_error(
+                "flat",
+                format!("`{}` is not bound in `{}`.", id, self.recdecl),
+                span,
+            )),
+            FV::Fun(label) if !self.labels.contains(label) => Err(ir_error(
+                "flat",
+                format!("`{}` uses unknown function `{}`.", self.recdecl, label),
+                span,
+            )),
+            _ => Ok(()),
+        }
+    }
+    fn exp(&self, exp: &flat::Exp, scope: &mut Vec<Id>) -> CompileResult<()> {
+        use flat::Exp::*;
+        let depth = scope.len();
+        match exp {
+            Compexp(cexp, span) => self.cexp(cexp, scope, *span)?,
+            Let(id, cexp, exp, span) | Loop(id, cexp, exp, span) => {
+                self.cexp(cexp, scope, *span)?;
+                scope.push(id.clone());
+                self.exp(exp, scope)?;
+            }
+            Recur(val, span) => self.value(val, scope, *span)?,
+        }
+        scope.truncate(depth);
+        Ok(())
+    }
+    fn cexp(&self, cexp: &flat::Cexp, scope: &mut Vec<Id>, span: Span) -> CompileResult<()> {
+        use flat::Cexp::*;
+        match cexp {
+            Val(val) | Proj(val, _) => self.value(val, scope, span),
+            Binop(_, val1, val2) => {
+                self.value(val1, scope, span)?;
+                self.value(val2, scope, span)
+            }
+            App(val, vals) => {
+                self.value(val, scope, span)?;
+                vals.iter().try_for_each(|val| self.value(val, scope, span))
+            }
+            Tuple(vals) | Extcall(_, vals) => {
+                vals.iter().try_for_each(|val| self.value(val, scope, span))
+            }
+            If(val, exp1, exp2) => {
+                self.value(val, scope, span)?;
+                self.exp(exp1, scope)?;
+                self.exp(exp2, scope)
+            }
+        }
+    }
+}
+
+pub fn verify_flat(program: &flat::Program, link: &Linkage) -> CompileResult<()> {
+    let mut labels: HashSet<Id> = link
+        .globals
+        .iter()
+        .map(|global| unit::closure_symbol(&global.name))
+        .collect();
+    for flat::Recdecl(id, _, _, span) in &program.recs {
+        if !labels.insert(id.clone()) {
+            return Err(ir_error(
+                "flat",
+                format!("`{}` is defined twice.", id),
+                *span,
+            ));
+        }
+    }
+    for flat::Recdecl(id, args, body, _) in &program.recs {
+        let flat = Flat {
+            labels: labels.clone(),
+            recdecl: id,
+        };
+        flat.exp(body, &mut args.clone())?;
+    }
+    Ok(())
+}
+
+// the registers an instruction reads, and the one it writes
+fn uses_defs(instr: &vm::Instr) -> (Vec<&vm::Reg>, Option<&vm::Reg>) {
+    use vm::Instr::*;
+    match instr {
+        Move(r, _) | Load(r, _) | Loadf(r, _) => (vec![], Some(r)),
+        Mover(r1, r2) | Ret(r1, r2) => (vec![r2], Some(r1)),
+        Binop(_, r1, r2) => (vec![r1, r2], Some(r1)),
+        Store(_, r) | Br(r, _) | Kill(r) => (vec![r], None),
+        Read(r, _) => (vec![r], Some(r)),
+        Call(r, args) => {
+            let mut uses = vec![r];
+            uses.extend(args);
+            (uses, Some(r))
+        }
+        Callext(r, _, args) | Malloc(r, args) => (args.iter().collect(), Some(r)),
+        Argst(..) | Label(_) | Gt(_) | Begin(_) | End(_) | Dummy => (vec![], None),
+    }
+}
+
+// the registers defined on entry to each block of `decl`: those every
+// path from the start defines and does not kill; a block no path
+// reaches has `None`
+fn defined_on_entry(decl: &vm::Decl, blocks: &[cfg::Block]) -> Vec<Option<HashSet<i32>>> {
+    let mut entry = vec![None; blocks.len()];
+    if blocks.is_empty() {
+        return entry;
+    }
+    entry[0] = Some(HashSet::new());
+    let mut work = vec![0];
+    while let Some(block) = work.pop() {
+        let mut defined = entry[block].clone().unwrap();
+        for (instr, _) in &decl.instrs[blocks[block].start..blocks[block].end] {
+            if let vm::Instr::Kill(r) = instr {
+                defined.remove(&r.vm);
+            }
+            if let Some(r) = uses_defs(instr).1 {
+                defined.insert(r.vm);
+            }
+        }
+        for &(succ, _) in &blocks[block].succs {
+            let merged: HashSet<i32> = match &entry[succ] {
+                Some(old) => old.intersection(&defined).copied().collect(),
+                None => defined.clone(),
+            };
+            if entry[succ].as_ref() != Some(&merged) {
+                entry[succ] = Some(merged);
+                work.push(succ);
+            }
+        }
+    }
+    entry
+}
+
+// a register must be defined on every path of the control-flow graph
+// that reaches a read of it; `Kill` ends a register, so reading it
+// afterwards is an error too
+pub fn verify_vm(program: &vm::Program) -> CompileResult<()> {
+    for decl in &program.decls {
+        let error = |message: String, span: Span| {
+            ir_error("vm", format!("{} in `{}`.", message, decl.funlb), span)
+        };
+        let labels: HashSet<&String> = decl
+            .instrs
+            .iter()
+            .filter_map(|(instr, _)| match instr {
+                vm::Instr::Label(label) => Some(label),
+                _ => None,
+            })
+            .collect();
+        for (instr, span) in &decl.instrs {
+            if let vm::Instr::Gt(label) | vm::Instr::Br(_, label) = instr {
+                if !labels.contains(label) {
+                    return Err(error(format!("jump to missing label `{}`", label), *span));
+                }
+            }
+        }
+        let blocks = cfg::blocks(decl);
+        let entry = defined_on_entry(decl, &blocks);
+        for (block, defined) in blocks.iter().zip(entry) {
+            // code no path reaches never reads anything
+            let mut defined = match defined {
+                Some(defined) => defined,
+                None => continue,
+            };
+            for (instr, span) in &decl.instrs[block.start..block.end] {
+                let (uses, def) = uses_defs(instr);
+                for r in uses {
+                    if !defined.contains(&r.vm) {
+                        return Err(error(
+                            format!("`{}` is used before it is defined", r),
+                            *span,
+                        ));
+                    }
+                }
+                if let vm::Instr::Kill(r) = instr {
+                    defined.remove(&r.vm);
+                }
+                if let Some(r) = def {
+                    defined.insert(r.vm);
+                }
+            }
+        }
+    }
+    Ok(())
+}
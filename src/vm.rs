@@ -1,77 +1,72 @@
 use super::normal::Bintype;
+use super::reader::Reader;
 use super::*;
 use regalloc::REG_SIZE;
 
-use once_cell::sync::Lazy;
-use std::sync::Mutex;
+use std::fmt;
 
 pub type Ofs = i32;
 pub type Byte = i32;
 type Label = String;
 
-macro_rules! print_reg {
-    ($r: ident, $real: expr) => {
-        if $real {
-            $r.rm
-        } else {
-            $r.vm
-        }
-    };
-}
-
 macro_rules! reg_byte {
-    ($r: ident) => {
-        if $r.byte == 4 {
-            next_stack32()
-        } else {
-            assert_eq!($r.byte, 8);
-            next_stack64()
+    ($tr: ident, $r: ident) => {
+        match $r.byte {
+            4 => Ok($tr.next_stack32()),
+            8 => Ok($tr.next_stack64()),
+            _ => Err(CompileError::internal(&format!(
+                "register of {} bytes.",
+                $r.byte
+            ))),
         }
     };
 }
 
-pub static STACK_POS: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
-pub static FRESH_NUM: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
-pub static LOOP_INFO: Lazy<Mutex<Vec<(Label, i32)>>> = Lazy::new(|| Mutex::new(vec![]));
-pub static REG_NUM: Lazy<Mutex<i32>> = Lazy::new(|| Mutex::new(0));
-pub static HAVE_APP: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
-
-fn next_stack32() -> i32 {
-    let pos = *STACK_POS.lock().unwrap();
-    *STACK_POS.lock().unwrap() = pos + 1;
-    pos + 1
-}
-
-fn next_stack64() -> i32 {
-    let pos = *STACK_POS.lock().unwrap();
-    *STACK_POS.lock().unwrap() = pos + 2;
-    pos + 2
-}
-
-fn next_label() -> Label {
-    let fresh_num = *FRESH_NUM.lock().unwrap();
-    *FRESH_NUM.lock().unwrap() = fresh_num + 1;
-    let mut s = Label::from(".L");
-    s.push_str(&fresh_num.to_string());
-    s
+// counters of one translation; labels and virtual registers are
+// numbered across the whole program, stack slots per function
+#[derive(Default)]
+struct Translator {
+    stack_pos: i32,
+    fresh_num: i32,
+    loop_info: Vec<(Label, i32)>,
+    reg_num: i32,
+    have_app: bool,
 }
 
-fn add_loopinfo(label: Label, ofs: i32) {
-    (*LOOP_INFO.lock().unwrap()).push((label, ofs));
-}
-
-fn get_loopinfo() -> (Label, Ofs) {
-    if let Some(info) = (*LOOP_INFO.lock().unwrap()).pop() {
-        info
-    } else {
-        panic!("get_looplabel error.");
+impl Translator {
+    fn next_stack32(&mut self) -> i32 {
+        self.stack_pos += 1;
+        self.stack_pos
+    }
+    fn next_stack64(&mut self) -> i32 {
+        self.stack_pos += 2;
+        self.stack_pos
+    }
+    fn next_label(&mut self) -> Label {
+        let fresh_num = self.fresh_num;
+        self.fresh_num = fresh_num + 1;
+        let mut s = Label::from(".L");
+        s.push_str(&fresh_num.to_string());
+        s
+    }
+    fn add_loopinfo(&mut self, label: Label, ofs: i32) {
+        self.loop_info.push((label, ofs));
+    }
+    fn get_loopinfo(&mut self) -> CompileResult<(Label, Ofs)> {
+        match self.loop_info.pop() {
+            Some(info) => Ok(info),
+            None => Err(CompileError::internal("get_looplabel error.")),
+        }
+    }
+    fn new_reg(&mut self, byte: i32) -> Reg {
+        let nreg = self.reg_num;
+        self.reg_num = nreg + 1;
+        Reg {
+            vm: nreg,
+            rm: -1,
+            byte,
+        }
     }
-}
-
-fn next_regnum() -> i32 {
-    let nreg = *REG_NUM.lock().unwrap();
-    *REG_NUM.lock().unwrap() = nreg + 1;
-    nreg
 }
 
 #[derive(Clone, Debug)]
@@ -86,10 +81,19 @@ impl Program {
     fn add(&mut self, decl: Decl) {
         self.decls.push(decl);
     }
-    pub fn program_display(self, real: bool) {
-        for decl in self.decls {
-            decl.program_display(real);
+}
+
+// `{:#}` shows the real registers that allocation assigned
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for decl in &self.decls {
+            if f.alternate() {
+                write!(f, "{:#}", decl)?;
+            } else {
+                write!(f, "{}", decl)?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -97,26 +101,42 @@ impl Program {
 pub struct Decl {
     pub funlb: Label,
     pub vc: i32,
-    pub instrs: Vec<Instr>,
+    // each instruction with the source span it was translated from
+    pub instrs: Vec<(Instr, Span)>,
     pub haveapp: bool,
+    span: Span,
 }
 
 impl Decl {
-    fn new(funlb: Label, vc: i32, instrs: Vec<Instr>, haveapp: bool) -> Self {
+    fn new(funlb: Label, vc: i32, instrs: Vec<(Instr, Span)>, haveapp: bool) -> Self {
         Self {
             funlb,
             vc,
             instrs,
             haveapp,
+            span: Span::default(),
         }
     }
     fn addinstr(&mut self, instr: Instr) {
-        self.instrs.push(instr);
+        self.instrs.push((instr, self.span));
     }
-    fn program_display(self, real: bool) {
-        for instr in self.instrs {
-            instr.program_display(real);
+}
+
+impl fmt::Display for Decl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "function {} (frame {}", self.funlb, self.vc)?;
+        if self.haveapp {
+            write!(f, ", calls")?;
+        }
+        writeln!(f, "):")?;
+        for (instr, _) in &self.instrs {
+            if f.alternate() {
+                write!(f, "{:#}", instr)?;
+            } else {
+                write!(f, "{}", instr)?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -136,58 +156,59 @@ pub struct Reg {
 }
 
 impl Reg {
-    fn new(byte: i32) -> Self {
-        Self {
-            vm: next_regnum(),
-            rm: -1,
-            byte,
-        }
-    }
     pub fn set_real(&mut self, regs: &mut [i32; REG_SIZE]) {
         // find real register already allocated
-        for i in 0..REG_SIZE {
-            if regs[i] == self.vm {
+        for (i, reg) in regs.iter().enumerate() {
+            if *reg == self.vm {
                 self.rm = i as i32;
                 return;
             }
         }
         // allocate real register
-        for i in 0..REG_SIZE {
-            if regs[i] == -1 {
-                regs[i] = self.vm;
+        for (i, reg) in regs.iter_mut().enumerate() {
+            if *reg == -1 {
+                *reg = self.vm;
                 self.rm = i as i32;
                 return;
             }
         }
-        // message_error("There are enough registers.");
     }
-    pub fn kill(&mut self, regs: &mut [i32; REG_SIZE]) {
-        for i in 0..REG_SIZE {
-            if regs[i] == self.vm {
+    pub fn kill(&mut self, regs: &mut [i32; REG_SIZE], span: Span) -> CompileResult<()> {
+        for (i, reg) in regs.iter_mut().enumerate() {
+            if *reg == self.vm {
                 self.rm = i as i32;
-                regs[i] = -1;
-                return;
+                *reg = -1;
+                return Ok(());
             }
         }
-        message_error("register cannot be killed.")
+        Err(CompileError::new(
+            ErrorKind::Internal,
+            format!("`{}` cannot be killed, as it holds no register.", self),
+            span,
+        ))
     }
 }
-impl Operand {
-    fn program_display(self) {
+// a register of 4 bytes, the default, is written without its width;
+// `{:#}` shows the real register
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "r{}", if f.alternate() { self.rm } else { self.vm })?;
+        if self.byte != 4 {
+            write!(f, ":{}", self.byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Operand::*;
         match self {
-            Param(c) => {
-                print!(" param({})", c);
-            }
-            Local(c, _) => {
-                print!(" local({})", c);
-            }
-            Proc(lb) => {
-                print!(" labimm {}", lb);
-            }
-            Intv(c) => {
-                print!(" imm({})", c);
-            }
+            Param(c) => write!(f, "param({})", c),
+            Local(c, 4) => write!(f, "local({})", c),
+            Local(c, byte) => write!(f, "local({}):{}", c, byte),
+            Proc(lb) => write!(f, "labimm {}", lb),
+            Intv(c) => write!(f, "imm({})", c),
         }
     }
 }
@@ -206,6 +227,7 @@ pub enum Instr {
     Br(Reg, Label),
     Gt(Label),
     Call(Reg, Vec<Reg>),
+    Callext(Reg, Label, Vec<Reg>),
     Ret(Reg, Reg),
     Malloc(Reg, Vec<Reg>),
     Read(Reg, (Ofs, Byte)),
@@ -217,336 +239,794 @@ pub enum Instr {
 
 // should use macro
 impl Instr {
-    fn program_display(self, real: bool) {
+    // the registers the instruction reads or writes
+    pub fn regs(&self) -> Vec<&Reg> {
         use Instr::*;
         match self {
-            Move(r, op1) => {
-                print!(" r{} <-", print_reg!(r, real));
-                op1.program_display();
-                print!("\n");
-            }
-            Mover(r1, r2) => {
-                print!(" r{} <- r{}\n", print_reg!(r1, real), print_reg!(r2, real));
+            Move(r, _)
+            | Store(_, r)
+            | Load(r, _)
+            | Loadf(r, _)
+            | Br(r, _)
+            | Read(r, _)
+            | Kill(r) => {
+                vec![r]
             }
-            Store(ofs, r) => {
-                print!(" local({}) <- r{}\n", ofs, print_reg!(r, real));
+            Mover(r1, r2) | Binop(_, r1, r2) | Ret(r1, r2) => vec![r1, r2],
+            Call(r, args) | Callext(r, _, args) | Malloc(r, args) => {
+                let mut regs = vec![r];
+                regs.extend(args);
+                regs
             }
-            Load(r, ofs) => {
-                print!(" r{} <- local({})\n", print_reg!(r, real), ofs);
-            }
-            Loadf(r, id) => {
-                print!(" r{} <- :{}\n", print_reg!(r, real), id);
-            }
-            Argst(ofs, op) => {
-                print!(" local({}) <- Param(", ofs);
-                op.program_display();
-                print!(" )\n");
-            }
-            Binop(btype, r1, r2) => {
-                print!(
-                    " r{} <- {}(r{}, r{})\n",
-                    print_reg!(r1, real),
-                    btype.bintype_signal(),
-                    print_reg!(r1, real),
-                    print_reg!(r2, real)
-                );
-            }
-            Label(lb) => {
-                print!("{}:\n", lb)
-            }
-            Br(r, lb) => {
-                print!(" if r{} then goto {}\n", print_reg!(r, real), lb);
-            }
-            Gt(lb) => {
-                print!(" goto {}\n", lb);
-            }
-            Call(r, mut args) => {
-                print!(" r{} ", print_reg!(r, real));
-                args.reverse();
-                print!("(");
-                loop {
-                    if let Some(rx) = args.pop() {
-                        print!(" r{}", print_reg!(rx, real));
-                    }
-                    if args.is_empty() {
-                        print!(" )\n");
-                        break;
-                    }
-                    print!(",");
-                }
+            Argst(..) | Label(_) | Gt(_) | Begin(_) | End(_) | Dummy => vec![],
+        }
+    }
+}
+
+// one instruction per line, except `Ret`, which moves its value into the
+// result register and then returns
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instr::*;
+        let real = f.alternate();
+        let reg = |r: &Reg| {
+            if real {
+                format!("{:#}", r)
+            } else {
+                r.to_string()
             }
+        };
+        let regs = |regs: &[Reg]| {
+            let regs: Vec<String> = regs.iter().map(|r| format!(" {}", reg(r))).collect();
+            format!("{} ", regs.join(","))
+        };
+        match self {
+            Move(r, op) => writeln!(f, " {} <- {}", reg(r), op),
+            Mover(r1, r2) => writeln!(f, " {} <- {}", reg(r1), reg(r2)),
+            Store(ofs, r) => writeln!(f, " local({}) <- {}", ofs, reg(r)),
+            Load(r, ofs) => writeln!(f, " {} <- local({})", reg(r), ofs),
+            Loadf(r, id) => writeln!(f, " {} <- :{}", reg(r), id),
+            Argst(ofs, op) => writeln!(f, " local({}) <- {}", ofs, op),
+            Binop(btype, r1, r2) => writeln!(
+                f,
+                " {} <- {}({}, {})",
+                reg(r1),
+                btype.bintype_signal(),
+                reg(r1),
+                reg(r2)
+            ),
+            Label(lb) => writeln!(f, "{}:", lb),
+            Br(r, lb) => writeln!(f, " if {} then goto {}", reg(r), lb),
+            Gt(lb) => writeln!(f, " goto {}", lb),
+            Call(r, args) => writeln!(f, " {} ({})", reg(r), regs(args)),
+            Callext(r, lb, args) => writeln!(f, " {} <- {}({})", reg(r), lb, regs(args)),
             Ret(r1, r2) => {
-                print!(" r{} <- r{}\n", print_reg!(r1, real), print_reg!(r2, real));
-                print!(" return(r{})\n", print_reg!(r1, real));
-            }
-            Malloc(r, mut datas) => {
-                print!("r{} <- new [", print_reg!(r, real));
-                loop {
-                    if let Some(rx) = datas.pop() {
-                        print!(" r{}", rx.rm);
-                    }
-                    if datas.is_empty() {
-                        print!(" ]\n");
-                        break;
-                    }
-                    print!(",");
-                }
+                writeln!(f, " {} <- {}", reg(r1), reg(r2))?;
+                writeln!(f, " return({})", reg(r1))
             }
+            Malloc(r, datas) => writeln!(f, "{} <- new [{}]", reg(r), regs(datas)),
             Read(r, (ofs, byte)) => {
-                print!(
-                    "read r{} <- #{}~{}( r{} )\n",
-                    print_reg!(r, real),
-                    ofs,
-                    byte,
-                    print_reg!(r, real)
-                );
-            }
-            Kill(r) => {
-                print!("kill r{}\n", print_reg!(r, real));
-            }
-            Begin(..) | End(..) => {
-                print!(" Begin or End is unimplemented. ");
+                writeln!(f, "read {} <- #{}~{}( {} )", reg(r), ofs, byte, reg(r))
             }
-            Dummy => {}
+            Kill(r) => writeln!(f, "kill {}", reg(r)),
+            Begin(lb) => writeln!(f, " begin {}", lb),
+            End(lb) => writeln!(f, " end {}", lb),
+            Dummy => writeln!(f, " nop"),
         }
     }
 }
 
-fn trans_value(fval: flat::Value, varenv: &Env<String, (Ofs, Byte)>) -> Operand {
+fn trans_value(fval: flat::Value, varenv: &Env<String, (Ofs, Byte)>) -> CompileResult<Operand> {
     use flat::Value::*;
-    match fval {
+    Ok(match fval {
         Var(id) => {
-            let (ofs, b4) = varenv.find(&id).unwrap();
+            let (ofs, b4) = varenv.get(&id)?;
             Operand::Local(*ofs, *b4)
         }
         Fun(id) => Operand::Proc(id),
         Intv(v) => Operand::Intv(v),
-    }
+    })
 }
 
-fn value2reg(decl: &mut Decl, val: flat::Value, varenv: &Env<String, (Ofs, Byte)>) -> Reg {
-    let op = trans_value(val, varenv);
-    use Operand::*;
-    match op {
-        Local(ofs, byte) => {
-            let r = Reg::new(byte);
-            decl.addinstr(Instr::Load(r, ofs));
-            r
-        }
-        Intv(v) => {
-            let r = Reg::new(4);
-            decl.addinstr(Instr::Move(r, Intv(v)));
-            r
-        }
-        Proc(id) => {
-            let r = Reg::new(8);
-            decl.addinstr(Instr::Loadf(r, id));
-            r
-        }
-        Param(..) => {
-            panic!("value2reg error.");
-        }
+impl Translator {
+    fn value2reg(
+        &mut self,
+        decl: &mut Decl,
+        val: flat::Value,
+        varenv: &Env<String, (Ofs, Byte)>,
+    ) -> CompileResult<Reg> {
+        let op = trans_value(val, varenv)?;
+        use Operand::*;
+        Ok(match op {
+            Local(ofs, byte) => {
+                let r = self.new_reg(byte);
+                decl.addinstr(Instr::Load(r, ofs));
+                r
+            }
+            Intv(v) => {
+                let r = self.new_reg(4);
+                decl.addinstr(Instr::Move(r, Intv(v)));
+                r
+            }
+            Proc(id) => {
+                let r = self.new_reg(8);
+                decl.addinstr(Instr::Loadf(r, id));
+                r
+            }
+            Param(..) => {
+                return Err(CompileError::internal("value2reg error."));
+            }
+        })
     }
-}
 
-fn trans_cexp(
-    fcexp: flat::Cexp,
-    decl: &mut Decl,
-    varenv: &mut Env<String, (Ofs, Byte)>,
-    mallocenv: &mut Env<String, Vec<Byte>>,
-) -> Reg {
-    use flat::Cexp::*;
-    match fcexp {
-        Val(val) => value2reg(decl, val, varenv),
-        Binop(btype, val1, val2) => {
-            let r1 = value2reg(decl, val1, varenv);
-            let r2 = value2reg(decl, val2, varenv);
-            decl.addinstr(Instr::Binop(btype, r1, r2));
-            decl.addinstr(Instr::Kill(r2));
-            r1
-        }
-        App(val, vals) => {
-            let mut args = vec![];
-            for val in vals {
-                let r = value2reg(decl, val, varenv);
-                args.push(r);
+    fn trans_cexp(
+        &mut self,
+        fcexp: flat::Cexp,
+        decl: &mut Decl,
+        varenv: &mut Env<String, (Ofs, Byte)>,
+        mallocenv: &mut Env<String, Vec<Byte>>,
+    ) -> CompileResult<Reg> {
+        use flat::Cexp::*;
+        Ok(match fcexp {
+            Val(val) => self.value2reg(decl, val, varenv)?,
+            Binop(btype, val1, val2) => {
+                let r1 = self.value2reg(decl, val1, varenv)?;
+                let r2 = self.value2reg(decl, val2, varenv)?;
+                decl.addinstr(Instr::Binop(btype, r1, r2));
+                decl.addinstr(Instr::Kill(r2));
+                r1
             }
-            let r1 = value2reg(decl, val, varenv);
-            decl.addinstr(Instr::Call(r1, args.clone()));
-            for arg in args {
-                decl.addinstr(Instr::Kill(arg));
+            App(val, vals) => {
+                let mut args = vec![];
+                for val in vals {
+                    let r = self.value2reg(decl, val, varenv)?;
+                    args.push(r);
+                }
+                let r1 = self.value2reg(decl, val, varenv)?;
+                decl.addinstr(Instr::Call(r1, args.clone()));
+                for arg in args {
+                    decl.addinstr(Instr::Kill(arg));
+                }
+                self.have_app = true;
+                r1
             }
-            *HAVE_APP.lock().unwrap() = true;
-            r1
-        }
-        If(val, fexp1, fexp2) => {
-            let t_e1 = next_label();
-            let t_e2 = next_label();
-            let r1 = value2reg(decl, val, varenv);
-            decl.addinstr(Instr::Br(r1, t_e1.clone()));
-            varenv.inc();
-            let r2 = trans_exp(*fexp2, decl, varenv, mallocenv);
-            decl.addinstr(Instr::Mover(r1, r2));
-            decl.addinstr(Instr::Kill(r2));
-            decl.addinstr(Instr::Gt(t_e2.clone()));
-            varenv.dec();
-            decl.addinstr(Instr::Label(t_e1.clone()));
-            varenv.inc();
-            let r3 = trans_exp(*fexp1, decl, varenv, mallocenv);
-            decl.addinstr(Instr::Mover(r1, r3));
-            decl.addinstr(Instr::Kill(r3));
-            decl.addinstr(Instr::Label(t_e2.clone()));
-            varenv.dec();
-            r1
-        }
-        Tuple(vals) => {
-            let mut data = vec![];
-            let mut bsizes = vec![];
-            for val in vals {
-                let r = value2reg(decl, val, varenv);
-                bsizes.push(r.byte);
-                data.push(r);
+            Extcall(symbol, vals) => {
+                let mut args = vec![];
+                for val in vals {
+                    let r = self.value2reg(decl, val, varenv)?;
+                    args.push(r);
+                }
+                let r1 = self.new_reg(4);
+                decl.addinstr(Instr::Callext(r1, symbol, args.clone()));
+                for arg in args {
+                    decl.addinstr(Instr::Kill(arg));
+                }
+                self.have_app = true;
+                r1
             }
-            let r1 = Reg::new(8);
-            decl.addinstr(Instr::Malloc(r1, data.clone()));
-            for d in data {
-                decl.addinstr(Instr::Kill(d));
+            If(val, fexp1, fexp2) => {
+                let t_e1 = self.next_label();
+                let t_e2 = self.next_label();
+                let r1 = self.value2reg(decl, val, varenv)?;
+                decl.addinstr(Instr::Br(r1, t_e1.clone()));
+                varenv.inc();
+                let r2 = self.trans_exp(*fexp2, decl, varenv, mallocenv)?;
+                decl.addinstr(Instr::Mover(r1, r2));
+                decl.addinstr(Instr::Kill(r2));
+                decl.addinstr(Instr::Gt(t_e2.clone()));
+                varenv.dec();
+                decl.addinstr(Instr::Label(t_e1.clone()));
+                varenv.inc();
+                let r3 = self.trans_exp(*fexp1, decl, varenv, mallocenv)?;
+                decl.addinstr(Instr::Mover(r1, r3));
+                decl.addinstr(Instr::Kill(r3));
+                decl.addinstr(Instr::Label(t_e2.clone()));
+                varenv.dec();
+                r1
             }
-            assert!(mallocenv.is_dummy());
-            mallocenv.addval(String::from("$$$dummy"), bsizes);
-            r1
-        }
-        Proj(val, c) => {
-            let mut ofs = 0;
-            let byte;
-            if let flat::Value::Var(id) = &val {
-                let bytelist = mallocenv.find(id).unwrap();
-                for i in 0..c {
-                    ofs += bytelist[i as usize];
+            Tuple(vals) => {
+                let mut data = vec![];
+                let mut bsizes = vec![];
+                for val in vals {
+                    let r = self.value2reg(decl, val, varenv)?;
+                    bsizes.push(r.byte);
+                    data.push(r);
                 }
-                byte = bytelist[c as usize];
-            } else {
-                message_error(&format!("{:?} should be flat::Value::Var", val));
-                panic!("Error");
+                let r1 = self.new_reg(8);
+                decl.addinstr(Instr::Malloc(r1, data.clone()));
+                for d in data {
+                    decl.addinstr(Instr::Kill(d));
+                }
+                if !mallocenv.is_dummy() {
+                    return Err(CompileError::internal("$$$dummy is not defined."));
+                }
+                mallocenv.addval(String::from("$$$dummy"), bsizes);
+                r1
             }
-            let mut r = value2reg(decl, val, varenv);
-            r.byte = byte;
-            decl.addinstr(Instr::Read(r, (ofs, byte)));
-            r
-        }
+            Proj(val, c) => {
+                let mut ofs = 0;
+                let byte;
+                if let flat::Value::Var(id) = &val {
+                    let bytelist = mallocenv.get(id)?;
+                    for i in 0..c {
+                        ofs += bytelist[i as usize];
+                    }
+                    byte = bytelist[c as usize];
+                } else if let (flat::Value::Fun(_), 0) = (&val, c) {
+                    // code pointer of a static closure
+                    byte = 8;
+                } else {
+                    return Err(CompileError::internal(&format!(
+                        "{:?} should be flat::Value::Var",
+                        val
+                    )));
+                }
+                let mut r = self.value2reg(decl, val, varenv)?;
+                r.byte = byte;
+                decl.addinstr(Instr::Read(r, (ofs, byte)));
+                r
+            }
+        })
     }
-}
 
-fn trans_exp(
-    fexp: flat::Exp,
-    decl: &mut Decl,
-    varenv: &mut Env<String, (Ofs, i32)>,
-    mallocenv: &mut Env<String, Vec<i32>>,
-) -> Reg {
-    use flat::Exp::*;
-    match fexp {
-        Compexp(fcexp) => trans_cexp(*fcexp, decl, varenv, mallocenv),
-        Let(id, fcexp, fexp) => {
-            use flat::Cexp::*;
-            let r1;
-            match *fcexp {
-                // let id = (a, b, ...)
-                Tuple(bs) => {
-                    r1 = trans_cexp(Tuple(bs), decl, varenv, mallocenv);
-                    let bytelist = mallocenv.find(&String::from("$$$dummy")).unwrap().clone();
-                    assert_ne!(bytelist, vec![-100]);
-                    mallocenv.addval(id.clone(), bytelist);
-                }
-                // let id1 = id2 ...
-                Val(flat::Value::Var(id)) => {
-                    r1 = trans_cexp(Val(flat::Value::Var(id.clone())), decl, varenv, mallocenv);
-                    let mut bytelist = vec![];
-                    if let Some(bs) = mallocenv.find(&id) {
-                        bytelist = bs.clone();
+    // instructions added while translating `fexp` get its span
+    fn trans_exp(
+        &mut self,
+        fexp: flat::Exp,
+        decl: &mut Decl,
+        varenv: &mut Env<String, (Ofs, i32)>,
+        mallocenv: &mut Env<String, Vec<i32>>,
+    ) -> CompileResult<Reg> {
+        let outer = std::mem::replace(&mut decl.span, fexp.span());
+        let r = self.trans_node(fexp, decl, varenv, mallocenv);
+        decl.span = outer;
+        r
+    }
+
+    fn trans_node(
+        &mut self,
+        fexp: flat::Exp,
+        decl: &mut Decl,
+        varenv: &mut Env<String, (Ofs, i32)>,
+        mallocenv: &mut Env<String, Vec<i32>>,
+    ) -> CompileResult<Reg> {
+        use flat::Exp::*;
+        Ok(match fexp {
+            Compexp(fcexp, _) => self.trans_cexp(*fcexp, decl, varenv, mallocenv)?,
+            Let(id, fcexp, fexp, _) => {
+                use flat::Cexp::*;
+                let r1;
+                match *fcexp {
+                    // let id = (a, b, ...)
+                    Tuple(bs) => {
+                        r1 = self.trans_cexp(Tuple(bs), decl, varenv, mallocenv)?;
+                        let bytelist = mallocenv.get(&String::from("$$$dummy"))?.clone();
+                        if bytelist == vec![-100] {
+                            return Err(CompileError::internal("tuple sizes are not recorded."));
+                        }
+                        mallocenv.addval(id.clone(), bytelist);
                     }
-                    if !bytelist.is_empty() {
-                        mallocenv.addval(id, bytelist);
+                    // let id1 = id2 ...
+                    Val(flat::Value::Var(id)) => {
+                        r1 = self.trans_cexp(
+                            Val(flat::Value::Var(id.clone())),
+                            decl,
+                            varenv,
+                            mallocenv,
+                        )?;
+                        let mut bytelist = vec![];
+                        if let Some(bs) = mallocenv.find(&id) {
+                            bytelist = bs.clone();
+                        }
+                        if !bytelist.is_empty() {
+                            mallocenv.addval(id, bytelist);
+                        }
+                    }
+                    _ => {
+                        r1 = self.trans_cexp(*fcexp, decl, varenv, mallocenv)?;
                     }
                 }
-                _ => {
-                    r1 = trans_cexp(*fcexp, decl, varenv, mallocenv);
-                }
+                let ofs = reg_byte!(self, r1)?;
+                decl.addinstr(Instr::Store(ofs, r1));
+                decl.addinstr(Instr::Kill(r1));
+                varenv.addval(id, (ofs, r1.byte));
+                self.trans_exp(*fexp, decl, varenv, mallocenv)?
             }
-            let ofs = reg_byte!(r1);
-            decl.addinstr(Instr::Store(ofs, r1));
-            decl.addinstr(Instr::Kill(r1));
-            varenv.addval(id, (ofs, r1.byte));
-            trans_exp(*fexp, decl, varenv, mallocenv)
-        }
-        Loop(id, fcexp, fexp) => {
-            use flat::Cexp::*;
-            let loop_l = next_label();
-            decl.addinstr(Instr::Label(loop_l.clone()));
-            let r1;
-            match *fcexp {
-                // let id = (a, b, ...)
-                Tuple(bs) => {
-                    r1 = trans_cexp(Tuple(bs), decl, varenv, mallocenv);
-                    let bytelist = mallocenv.find(&String::from("$$$dummy")).unwrap().clone();
-                    assert_ne!(bytelist, vec![-100]);
-                    mallocenv.addval(id.clone(), bytelist.clone());
-                }
-                // let id1 = id2 ...
-                Val(flat::Value::Var(id)) => {
-                    r1 = trans_cexp(Val(flat::Value::Var(id.clone())), decl, varenv, mallocenv);
-                    let mut bytelist = vec![];
-                    if let Some(bs) = mallocenv.find(&id) {
-                        bytelist = bs.clone();
+            Loop(id, fcexp, fexp, _) => {
+                use flat::Cexp::*;
+                let loop_l = self.next_label();
+                decl.addinstr(Instr::Label(loop_l.clone()));
+                let r1;
+                match *fcexp {
+                    // let id = (a, b, ...)
+                    Tuple(bs) => {
+                        r1 = self.trans_cexp(Tuple(bs), decl, varenv, mallocenv)?;
+                        let bytelist = mallocenv.get(&String::from("$$$dummy"))?.clone();
+                        if bytelist == vec![-100] {
+                            return Err(CompileError::internal("tuple sizes are not recorded."));
+                        }
+                        mallocenv.addval(id.clone(), bytelist.clone());
                     }
-                    if !bytelist.is_empty() {
-                        mallocenv.addval(id, bytelist);
+                    // let id1 = id2 ...
+                    Val(flat::Value::Var(id)) => {
+                        r1 = self.trans_cexp(
+                            Val(flat::Value::Var(id.clone())),
+                            decl,
+                            varenv,
+                            mallocenv,
+                        )?;
+                        let mut bytelist = vec![];
+                        if let Some(bs) = mallocenv.find(&id) {
+                            bytelist = bs.clone();
+                        }
+                        if !bytelist.is_empty() {
+                            mallocenv.addval(id, bytelist);
+                        }
+                    }
+                    _ => {
+                        r1 = self.trans_cexp(*fcexp, decl, varenv, mallocenv)?;
                     }
                 }
-                _ => {
-                    r1 = trans_cexp(*fcexp, decl, varenv, mallocenv);
-                }
+                let id_ofs = reg_byte!(self, r1)?;
+                self.add_loopinfo(loop_l, id_ofs);
+                decl.addinstr(Instr::Store(id_ofs, r1));
+                decl.addinstr(Instr::Kill(r1));
+                varenv.addval(id, (id_ofs, r1.byte));
+                self.trans_exp(*fexp, decl, varenv, mallocenv)?
             }
-            let id_ofs = reg_byte!(r1);
-            add_loopinfo(loop_l, id_ofs);
-            decl.addinstr(Instr::Store(id_ofs, r1));
-            decl.addinstr(Instr::Kill(r1));
-            varenv.addval(id, (id_ofs, r1.byte));
-            trans_exp(*fexp, decl, varenv, mallocenv)
-        }
-        Recur(val) => {
-            let (loop_l, loop_ofs) = get_loopinfo();
-            let r1 = trans_cexp(flat::Cexp::Val(val), decl, varenv, mallocenv);
-            decl.addinstr(Instr::Store(loop_ofs, r1));
-            decl.addinstr(Instr::Gt(loop_l));
-            r1
-        }
+            Recur(val, _) => {
+                let (loop_l, loop_ofs) = self.get_loopinfo()?;
+                let r1 = self.trans_cexp(flat::Cexp::Val(val), decl, varenv, mallocenv)?;
+                decl.addinstr(Instr::Store(loop_ofs, r1));
+                decl.addinstr(Instr::Gt(loop_l));
+                r1
+            }
+        })
     }
 }
 
-pub fn trans_pg(pg: flat::Program) -> Program {
+pub fn trans_pg(pg: flat::Program) -> CompileResult<Program> {
+    let mut tr = Translator::default();
     let mut varenv = Env::new();
     let mut mallocenv = Env::new();
     let mut program = Program::new();
-    for flat::Recdecl(funame, args, body) in pg.recs {
+    for flat::Recdecl(funame, args, body, span) in pg.recs {
         let mut decl = Decl::new(funame, 0, vec![], false);
+        decl.span = span;
         varenv.inc();
-        let mut pari = 0;
-        for arg in args {
-            let ofs = next_stack64();
-            decl.addinstr(Instr::Argst(ofs, Operand::Param(pari)));
+        for (pari, arg) in args.into_iter().enumerate() {
+            let ofs = tr.next_stack64();
+            decl.addinstr(Instr::Argst(ofs, Operand::Param(pari as i32)));
             varenv.addval(arg, (ofs, 8));
-            pari += 1;
         }
-        let r1 = trans_exp(*body, &mut decl, &mut varenv, &mut mallocenv);
+        let r1 = tr.trans_exp(*body, &mut decl, &mut varenv, &mut mallocenv);
         varenv.dec();
-        decl.vc = *STACK_POS.lock().unwrap();
-        let mut ra1 = Reg::new(4);
+        let r1 = r1?;
+        decl.vc = tr.stack_pos;
+        let mut ra1 = tr.new_reg(4);
         ra1.byte = r1.byte;
         decl.addinstr(Instr::Ret(ra1, r1));
         decl.addinstr(Instr::Kill(r1));
-        decl.haveapp = *HAVE_APP.lock().unwrap();
+        decl.haveapp = tr.have_app;
         program.add(decl);
-        *STACK_POS.lock().unwrap() = 0;
+        tr.stack_pos = 0;
+    }
+    Ok(program)
+}
+
+// `r3` or `r3:8`; in allocated code the number is the real register
+fn read_reg(reader: &mut Reader, allocated: bool) -> CompileResult<Reg> {
+    let start = reader.pos;
+    let label = reader.label()?;
+    let num = match label.strip_prefix('r').map(str::parse::<i32>) {
+        Some(Ok(num)) if num >= 0 => num,
+        _ => {
+            reader.pos = start;
+            return Err(reader.error(&format!("expected register, found `{}`.", label)));
+        }
+    };
+    let byte = if reader.eat(":") { reader.int()? } else { 4 };
+    Ok(Reg {
+        vm: num,
+        rm: if allocated { num } else { -1 },
+        byte,
+    })
+}
+
+fn at_reg(reader: &mut Reader) -> bool {
+    let pos = reader.pos;
+    let found = read_reg(reader, false).is_ok();
+    reader.pos = pos;
+    found
+}
+
+// `(ofs)`, as in `local(ofs)`
+fn read_paren_int(reader: &mut Reader) -> CompileResult<i32> {
+    reader.expect("(")?;
+    let num = reader.int()?;
+    reader.expect(")")?;
+    Ok(num)
+}
+
+// `local(c)` reads back as `Load`, which is how values on the stack
+// are moved into registers
+fn read_operand(reader: &mut Reader) -> CompileResult<Operand> {
+    if reader.keyword("param") {
+        Ok(Operand::Param(read_paren_int(reader)?))
+    } else if reader.keyword("local") {
+        let ofs = read_paren_int(reader)?;
+        let byte = if reader.eat(":") { reader.int()? } else { 4 };
+        Ok(Operand::Local(ofs, byte))
+    } else if reader.keyword("labimm") {
+        Ok(Operand::Proc(reader.label()?))
+    } else if reader.keyword("imm") {
+        Ok(Operand::Intv(read_paren_int(reader)?))
+    } else {
+        Err(reader.error("expected operand."))
+    }
+}
+
+fn read_instr(reader: &mut Reader, allocated: bool) -> CompileResult<Instr> {
+    let reg = |reader: &mut Reader| read_reg(reader, allocated);
+    // `.L0:`
+    let start = reader.pos;
+    if let Ok(label) = reader.label() {
+        if reader.eat(":") && reader.at_line_end() {
+            return Ok(Instr::Label(label));
+        }
+    }
+    reader.pos = start;
+    if reader.keyword("kill") {
+        return Ok(Instr::Kill(reg(reader)?));
+    }
+    if reader.keyword("read") {
+        let r = reg(reader)?;
+        reader.expect("<-")?;
+        reader.expect("#")?;
+        let ofs = reader.int()?;
+        reader.expect("~")?;
+        let byte = reader.int()?;
+        reader.expect("(")?;
+        reg(reader)?;
+        reader.expect(")")?;
+        return Ok(Instr::Read(r, (ofs, byte)));
+    }
+    if reader.keyword("if") {
+        let r = reg(reader)?;
+        reader.expect_keyword("then")?;
+        reader.expect_keyword("goto")?;
+        return Ok(Instr::Br(r, reader.label()?));
+    }
+    if reader.keyword("goto") {
+        return Ok(Instr::Gt(reader.label()?));
+    }
+    if reader.keyword("return") {
+        reader.expect("(")?;
+        let r = reg(reader)?;
+        reader.expect(")")?;
+        // completed by the move before it
+        return Ok(Instr::Ret(r, r));
+    }
+    if reader.keyword("begin") {
+        return Ok(Instr::Begin(reader.label()?));
+    }
+    if reader.keyword("end") {
+        return Ok(Instr::End(reader.label()?));
+    }
+    if reader.keyword("nop") {
+        return Ok(Instr::Dummy);
+    }
+    if reader.keyword("local") {
+        let ofs = read_paren_int(reader)?;
+        reader.expect("<-")?;
+        reader.skip();
+        if at_reg(reader) {
+            return Ok(Instr::Store(ofs, reg(reader)?));
+        }
+        return Ok(Instr::Argst(ofs, read_operand(reader)?));
+    }
+    let r = reg(reader)?;
+    if reader.eat("(") {
+        reader.pos -= 1;
+        return Ok(Instr::Call(r, reader.list(reg)?));
+    }
+    reader.expect("<-")?;
+    reader.skip();
+    if reader.keyword("new") {
+        return Ok(Instr::Malloc(r, reader.list_in("[", "]", reg)?));
+    }
+    if reader.eat(":") {
+        return Ok(Instr::Loadf(r, reader.label()?));
+    }
+    if reader.keyword("local") {
+        return Ok(Instr::Load(r, read_paren_int(reader)?));
+    }
+    if at_reg(reader) {
+        return Ok(Instr::Mover(r, reg(reader)?));
+    }
+    let start = reader.pos;
+    if let Some(btype) = reader.bintype() {
+        let regs = reader.list(reg)?;
+        return match regs.as_slice() {
+            [r1, r2] if r1.vm == r.vm && r1.byte == r.byte => Ok(Instr::Binop(btype, r, *r2)),
+            _ => {
+                reader.pos = start;
+                reader.skip_spaces();
+                Err(reader.error(&format!(
+                    "`{}` should take `{}` and one more register.",
+                    btype.bintype_signal(),
+                    r
+                )))
+            }
+        };
+    }
+    let pos = reader.pos;
+    if let Ok(operand) = read_operand(reader) {
+        return Ok(Instr::Move(r, operand));
+    }
+    reader.pos = pos;
+    let symbol = reader.label()?;
+    Ok(Instr::Callext(r, symbol, reader.list(reg)?))
+}
+
+// the program `text` prints, in the syntax of `Display`, or of `{:#}`
+// if `allocated`; spans point into `text`
+pub fn read_program(text: &str, allocated: bool) -> CompileResult<Program> {
+    let mut reader = Reader::new(text);
+    let mut program = Program::new();
+    while !reader.eof() {
+        let start = reader.pos;
+        if reader.keyword("function") {
+            let funlb = reader.label()?;
+            reader.expect("(")?;
+            reader.expect_keyword("frame")?;
+            let vc = reader.int()?;
+            let mut haveapp = false;
+            if reader.eat(",") {
+                reader.expect_keyword("calls")?;
+                haveapp = true;
+            }
+            reader.expect(")")?;
+            reader.expect(":")?;
+            reader.end_line()?;
+            let mut decl = Decl::new(funlb, vc, vec![], haveapp);
+            decl.span = reader.span_from(start);
+            program.add(decl);
+            continue;
+        }
+        let decl = match program.decls.last_mut() {
+            Some(decl) => decl,
+            None => return Err(reader.error("expected `function`.")),
+        };
+        let mut instr = read_instr(&mut reader, allocated)?;
+        let mut span = reader.span_from(start);
+        reader.end_line()?;
+        if let Instr::Ret(r, _) = instr {
+            instr = match decl.instrs.pop() {
+                Some((Instr::Mover(r1, r2), first)) if r1.vm == r.vm => {
+                    span = first.to(span);
+                    Instr::Ret(r1, r2)
+                }
+                _ => {
+                    reader.pos = start;
+                    reader.skip();
+                    return Err(reader.error(&format!(
+                        "`return({})` should follow a move into `{}`.",
+                        r, r
+                    )));
+                }
+            };
+        }
+        decl.instrs.push((instr, span));
+    }
+    Ok(program)
+}
+
+// the JSON form; `reg` is the real register, or null before allocation
+impl Reg {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("vreg", Json::int(self.vm)),
+            (
+                "reg",
+                if self.rm < 0 {
+                    Json::Null
+                } else {
+                    Json::int(self.rm)
+                },
+            ),
+            ("bytes", Json::int(self.byte)),
+        ])
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        Ok(Reg {
+            vm: json.field("vreg")?.as_int()?,
+            rm: match json.field("reg")? {
+                Json::Null => -1,
+                reg => reg.as_int()?,
+            },
+            byte: json.field("bytes")?.as_int()?,
+        })
+    }
+}
+
+fn regs_json(regs: &[Reg]) -> Json {
+    Json::Array(regs.iter().map(Reg::to_json).collect())
+}
+
+fn regs_from_json(json: &Json) -> CompileResult<Vec<Reg>> {
+    json.as_array()?.iter().map(Reg::from_json).collect()
+}
+
+impl Operand {
+    pub fn to_json(&self) -> Json {
+        use Operand::*;
+        match self {
+            Param(c) => Json::node("Param", vec![("index", Json::int(*c))]),
+            Local(ofs, byte) => Json::node(
+                "Local",
+                vec![("offset", Json::int(*ofs)), ("bytes", Json::int(*byte))],
+            ),
+            Proc(lb) => Json::node("Proc", vec![("label", Json::str(lb))]),
+            Intv(c) => Json::node("Intv", vec![("value", Json::int(*c))]),
+        }
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Operand::*;
+        let int = |key| json.field(key)?.as_int();
+        Ok(match json.kind()? {
+            "Param" => Param(int("index")?),
+            "Local" => Local(int("offset")?, int("bytes")?),
+            "Proc" => Proc(json.field("label")?.as_id()?),
+            "Intv" => Intv(int("value")?),
+            kind => return Err(json::unknown_kind("operand", kind)),
+        })
+    }
+}
+
+impl Instr {
+    pub fn to_json(&self) -> Json {
+        use Instr::*;
+        let (kind, fields) = match self {
+            Move(r, op) => ("Move", vec![("dst", r.to_json()), ("src", op.to_json())]),
+            Mover(r1, r2) => ("Mover", vec![("dst", r1.to_json()), ("src", r2.to_json())]),
+            Store(ofs, r) => (
+                "Store",
+                vec![("offset", Json::int(*ofs)), ("src", r.to_json())],
+            ),
+            Load(r, ofs) => (
+                "Load",
+                vec![("dst", r.to_json()), ("offset", Json::int(*ofs))],
+            ),
+            Loadf(r, lb) => (
+                "Loadf",
+                vec![("dst", r.to_json()), ("label", Json::str(lb))],
+            ),
+            Argst(ofs, op) => (
+                "Argst",
+                vec![("offset", Json::int(*ofs)), ("src", op.to_json())],
+            ),
+            Binop(btype, r1, r2) => (
+                "Binop",
+                vec![
+                    ("op", btype.to_json()),
+                    ("dst", r1.to_json()),
+                    ("src", r2.to_json()),
+                ],
+            ),
+            Label(lb) => ("Label", vec![("label", Json::str(lb))]),
+            Br(r, lb) => ("Br", vec![("cond", r.to_json()), ("label", Json::str(lb))]),
+            Gt(lb) => ("Gt", vec![("label", Json::str(lb))]),
+            Call(r, args) => (
+                "Call",
+                vec![("fun", r.to_json()), ("args", regs_json(args))],
+            ),
+            Callext(r, lb, args) => (
+                "Callext",
+                vec![
+                    ("dst", r.to_json()),
+                    ("symbol", Json::str(lb)),
+                    ("args", regs_json(args)),
+                ],
+            ),
+            Ret(r1, r2) => ("Ret", vec![("dst", r1.to_json()), ("src", r2.to_json())]),
+            Malloc(r, datas) => (
+                "Malloc",
+                vec![("dst", r.to_json()), ("values", regs_json(datas))],
+            ),
+            Read(r, (ofs, byte)) => (
+                "Read",
+                vec![
+                    ("reg", r.to_json()),
+                    ("offset", Json::int(*ofs)),
+                    ("bytes", Json::int(*byte)),
+                ],
+            ),
+            Begin(lb) => ("Begin", vec![("label", Json::str(lb))]),
+            End(lb) => ("End", vec![("label", Json::str(lb))]),
+            Kill(r) => ("Kill", vec![("reg", r.to_json())]),
+            Dummy => ("Dummy", vec![]),
+        };
+        Json::node(kind, fields)
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        use Instr::*;
+        let reg = |key| Reg::from_json(json.field(key)?);
+        let regs = |key| regs_from_json(json.field(key)?);
+        let int = |key| json.field(key)?.as_int();
+        let label = || json.field("label")?.as_id();
+        let operand = || Operand::from_json(json.field("src")?);
+        Ok(match json.kind()? {
+            "Move" => Move(reg("dst")?, operand()?),
+            "Mover" => Mover(reg("dst")?, reg("src")?),
+            "Store" => Store(int("offset")?, reg("src")?),
+            "Load" => Load(reg("dst")?, int("offset")?),
+            "Loadf" => Loadf(reg("dst")?, label()?),
+            "Argst" => Argst(int("offset")?, operand()?),
+            "Binop" => Binop(
+                Bintype::from_json(json.field("op")?)?,
+                reg("dst")?,
+                reg("src")?,
+            ),
+            "Label" => Label(label()?),
+            "Br" => Br(reg("cond")?, label()?),
+            "Gt" => Gt(label()?),
+            "Call" => Call(reg("fun")?, regs("args")?),
+            "Callext" => Callext(reg("dst")?, json.field("symbol")?.as_id()?, regs("args")?),
+            "Ret" => Ret(reg("dst")?, reg("src")?),
+            "Malloc" => Malloc(reg("dst")?, regs("values")?),
+            "Read" => Read(reg("reg")?, (int("offset")?, int("bytes")?)),
+            "Begin" => Begin(label()?),
+            "End" => End(label()?),
+            "Kill" => Kill(reg("reg")?),
+            "Dummy" => Dummy,
+            kind => return Err(json::unknown_kind("instruction", kind)),
+        })
+    }
+}
+
+impl Program {
+    pub fn to_json(&self) -> Json {
+        let decls = self
+            .decls
+            .iter()
+            .map(|decl| {
+                let instrs = decl
+                    .instrs
+                    .iter()
+                    .map(|(instr, span)| match instr.to_json() {
+                        Json::Object(mut fields) => {
+                            fields.push((String::from("span"), Json::span(*span)));
+                            Json::Object(fields)
+                        }
+                        json => json,
+                    })
+                    .collect();
+                Json::object(vec![
+                    ("name", Json::str(&decl.funlb)),
+                    ("frame", Json::int(decl.vc)),
+                    ("calls", Json::Bool(decl.haveapp)),
+                    ("instrs", Json::Array(instrs)),
+                ])
+            })
+            .collect();
+        Json::object(vec![("functions", Json::Array(decls))])
+    }
+    pub fn from_json(json: &Json) -> CompileResult<Self> {
+        let mut program = Program::new();
+        for function in json.field("functions")?.as_array()? {
+            let mut decl = Decl::new(
+                function.field("name")?.as_id()?,
+                function.field("frame")?.as_int()?,
+                vec![],
+                function.field("calls")?.as_bool()?,
+            );
+            for instr in function.field("instrs")?.as_array()? {
+                decl.instrs
+                    .push((Instr::from_json(instr)?, instr.span_field()?));
+            }
+            program.add(decl);
+        }
+        Ok(program)
     }
-    program
 }
@@ -0,0 +1,55 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{compile_in, LOOP};
+
+use ruscaml::cfg::{blocks, Block, Edge};
+use ruscaml::vm;
+
+const VM_TEXT: &str = "function f (frame 1):
+ r0 <- local(1)
+ if r0 then goto .L0
+ r1 <- imm(1)
+ goto .L1
+.L0:
+ r1 <- imm(2)
+.L1:
+ r2 <- r1
+ return(r2)
+";
+
+#[test]
+fn vm_code_splits_at_labels_and_jumps() {
+    let program = vm::read_program(VM_TEXT, false).unwrap();
+    let block = |start, end, succs| Block { start, end, succs };
+    assert_eq!(
+        blocks(&program.decls[0]),
+        vec![
+            block(0, 2, vec![(2, Edge::Taken), (1, Edge::Fallthrough)]),
+            block(2, 4, vec![(3, Edge::Taken)]),
+            block(4, 6, vec![(3, Edge::Fallthrough)]),
+            block(6, 8, vec![]),
+        ]
+    );
+}
+
+#[test]
+fn vm_dot_is_emitted_before_and_after_regalloc() {
+    let files = [("main.ml", LOOP)];
+    let (ok, out) = compile_in("vm_dot", &files, &["--emit=vm-dot", "main.ml"]);
+    assert!(ok);
+    assert!(out.starts_with("digraph vm {\n"));
+    assert!(out.contains("\"_toplevel.0\" -> \"_toplevel.2\" [label=\"taken\"];"));
+    assert!(out.contains("\"_toplevel.0\" -> \"_toplevel.1\" [label=\"fallthrough\"];"));
+    assert!(out.contains("if r3 then goto .L1"));
+
+    let args = ["--emit=vm-alloc-dot", "main.ml"];
+    let (ok, out) = compile_in("vm_alloc_dot", &files, &args);
+    assert!(ok);
+    assert!(out.contains("[label=\".L0:\\lr0 <- imm(3)\\l"));
+
+    let args = ["--emit=vm-dot", "--emit-format=json", "main.ml"];
+    let (ok, _) = compile_in("vm_dot_json", &files, &args);
+    assert!(!ok);
+}
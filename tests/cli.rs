@@ -0,0 +1,142 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{compile_in, run_in, PROGRAM};
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn ruscaml(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ruscaml"))
+        .args(args)
+        .current_dir(std::env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to execute ruscaml");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn reads_stdin_and_writes_the_output_file() {
+    let out = std::env::temp_dir().join("ruscaml_cli_out.s");
+    let output = ruscaml(&["-o", out.to_str().unwrap(), "-"], PROGRAM);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let asm = std::fs::read_to_string(&out).unwrap();
+    let _ = std::fs::remove_file(&out);
+    assert!(asm.starts_with(".text\n\t.global _toplevel\n_toplevel:\n"));
+}
+
+#[test]
+fn emits_each_stage() {
+    let files = [("main.ml", PROGRAM)];
+    let (ok, out) = compile_in("cli_tokens", &files, &["--emit=tokens", "main.ml"]);
+    assert!(ok);
+    assert!(out.starts_with("1:1 Let \"let\"\n1:5 Id \"x\"\n1:7 Assign\n1:9 ILit 3\n"));
+    let (ok, out) = compile_in("cli_normal", &files, &["--emit=normal", "main.ml"]);
+    assert!(ok);
+    assert!(out.starts_with("let x = 3 in\nlet @v0 = x<4 in\n"));
+    let (ok, out) = compile_in("cli_alloc", &files, &["--emit=vm-alloc", "main.ml"]);
+    assert!(ok);
+    assert!(out.contains(" r0 <- <(r0, r1)\n"));
+}
+
+#[test]
+fn compiles_every_input_and_fails_if_one_does() {
+    let files = [("a.ml", "1;;\n"), ("b.ml", "1 + true;;\n")];
+    let output = run_in("cli_many", &files, &["a.ml", "b.ml"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(".text\n"));
+    assert!(!stdout.contains("Error"));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Error: expected int, found bool."));
+}
+
+#[test]
+fn usage_errors_exit_with_two() {
+    for args in [
+        &["--emit=bogus", "-"][..],
+        &["--frobnicate", "-"],
+        &[],
+        &["-o", "x.s", "a.ml", "b.ml"],
+        &["-c", "-"],
+        &["missing.ml"],
+    ] {
+        let output = ruscaml(args, "");
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("ruscaml: "));
+    }
+    let output = ruscaml(&["--help"], "");
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("Usage: ruscaml"));
+}
+
+#[test]
+fn compile_errors_exit_with_one() {
+    let output = ruscaml(&["-"], "1 + true;;\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Error: expected int, found bool."));
+}
+
+#[test]
+fn time_passes_and_stats_go_to_stderr() {
+    let output = ruscaml(&["--time-passes", "--stats", "-"], PROGRAM);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with(".text\n"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("lex "));
+    assert!(stderr.contains("\ncodegen "));
+    assert!(stderr.contains("\ntotal "));
+    assert!(stderr.contains("functions (Recdecl): 1\n"));
+    assert!(stderr.contains("\nvm instructions after trans_pg: "));
+    assert!(stderr.contains("\nvm instructions after regalloc: "));
+    assert!(stderr.contains("\n_toplevel "));
+}
+
+#[test]
+fn dumps_go_to_stderr_or_a_file() {
+    let output = ruscaml(
+        &["--dump-after=flat", "--dump-after=regalloc", "-"],
+        PROGRAM,
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with(".text\n"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("=== -: after flat ===\nlet rec _toplevel () = \n"));
+    assert!(stderr
+        .contains("=== -: after regalloc ===\nfunction _toplevel (frame 2):\n r0 <- imm(3)\n"));
+
+    let dump = std::env::temp_dir().join("ruscaml_cli_dump.txt");
+    let _ = std::fs::remove_file(&dump);
+    let dump_to = format!("--dump-to={}", dump.display());
+    let output = ruscaml(&["--dump-after=lex", &dump_to, "-"], "1 + true;;\n");
+    assert_eq!(output.status.code(), Some(1));
+    let text = std::fs::read_to_string(&dump).unwrap();
+    let _ = std::fs::remove_file(&dump);
+    assert!(text.starts_with("=== -: after lex ===\n1:1 ILit 1\n"));
+
+    let output = ruscaml(&["--dump-after=bogus", "-"], PROGRAM);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,96 @@
+use ruscaml::{Artifact, Session, Stage};
+
+use std::fs;
+use std::process::{Command, Output};
+
+// programs the tests share: one with a branch, one whose functions need
+// closures, a loop and a call of an external
+#[allow(dead_code)]
+pub const PROGRAM: &str = "let x = 3 in\nif x < 4 then x * 2 else x + 1;;\n";
+#[allow(dead_code)]
+pub const CLOSURES: &str =
+    "let rec f = fun x -> fun y -> x + y in\nlet rec g = fun z -> z in\n(f 1 2, g 3);;\n";
+#[allow(dead_code)]
+pub const LOOP: &str = "loop l = 3 in\nif l < 10 then recur (l + 1) else l;;\n";
+#[allow(dead_code)]
+pub const EXTERNAL: &str = "external add3 : int -> int -> int -> int = \"c_add3\"\nadd3 1 2 3;;\n";
+
+// what a fresh session makes of `program` at `stage`
+#[allow(dead_code)]
+pub fn compile_to(program: &str, stage: Stage) -> Artifact {
+    Session::new("main.ml").compile_to(program, stage).unwrap()
+}
+
+#[allow(dead_code)]
+pub fn compile(name: &str, program: &str) -> (bool, String) {
+    let path = std::env::temp_dir().join(format!("ruscaml_{}.ml", name));
+    fs::write(&path, program).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ruscaml"))
+        .arg(&path)
+        .output()
+        .expect("failed to execute ruscaml");
+    let _ = fs::remove_file(&path);
+    result(output)
+}
+
+// the output of a run that succeeded, or the diagnostics of one that
+// failed
+fn result(output: Output) -> (bool, String) {
+    let ok = output.status.success();
+    let out = if ok { output.stdout } else { output.stderr };
+    (ok, String::from_utf8(out).unwrap())
+}
+
+// writes `files` into a fresh directory and compiles `args` there
+#[allow(dead_code)]
+pub fn compile_in(dir: &str, files: &[(&str, &str)], args: &[&str]) -> (bool, String) {
+    result(run_in(dir, files, args))
+}
+
+// as `compile_in`, but keeps both streams
+#[allow(dead_code)]
+pub fn run_in(dir: &str, files: &[(&str, &str)], args: &[&str]) -> Output {
+    let dir = std::env::temp_dir().join(format!("ruscaml_{}", dir));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (name, program) in files {
+        fs::write(dir.join(name), program).unwrap();
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_ruscaml"))
+        .args(args)
+        .current_dir(&dir)
+        .output()
+        .expect("failed to execute ruscaml");
+    let _ = fs::remove_dir_all(&dir);
+    output
+}
+
+// assembles `asm` for aarch64 and returns the assembler's errors, or
+// None if neither a cross `as` nor `llvm-mc` is installed
+#[allow(dead_code)]
+pub fn assemble(name: &str, asm: &str) -> Option<Result<(), String>> {
+    let path = std::env::temp_dir().join(format!("ruscaml_{}.s", name));
+    fs::write(&path, asm).unwrap();
+    let assemblers: [(&str, &[&str]); 2] = [
+        ("aarch64-linux-gnu-as", &["-o", "/dev/null"]),
+        (
+            "llvm-mc",
+            &[
+                "--triple=aarch64-linux-gnu",
+                "-filetype=obj",
+                "-o",
+                "/dev/null",
+            ],
+        ),
+    ];
+    let result = assemblers.iter().find_map(|(tool, args)| {
+        let output = Command::new(tool).args(*args).arg(&path).output().ok()?;
+        Some(if output.status.success() {
+            Ok(())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).into_owned())
+        })
+    });
+    let _ = fs::remove_file(&path);
+    result
+}
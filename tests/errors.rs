@@ -0,0 +1,113 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{compile, compile_in};
+use ruscaml::lexer::tokenize;
+use ruscaml::parser::{parse, parse_partial, Ast};
+use ruscaml::ErrorKind;
+
+#[test]
+fn lex_error_is_returned() {
+    let err = tokenize("1 + $;;\n").err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Lex);
+    assert_eq!(err.message, "unexpected character `$`.");
+}
+
+#[test]
+fn syntax_error_is_returned() {
+    let err = parse(tokenize("let x = 1 x;;\n").unwrap()).err().unwrap();
+    assert_eq!(err.kind, ErrorKind::Syntax);
+    assert_eq!(err.message, "expected `in`, found `;;`.");
+    assert_eq!(err.span.map(|span| span.lo), Some(11));
+}
+
+#[test]
+fn reported_with_snippet() {
+    let (ok, out) = compile("errors_expr", "1 + ;;\n");
+    assert!(!ok);
+    assert!(out.contains("Error: expected expression, found `;;`. Line: 1, Column: 5."));
+}
+
+#[test]
+fn parser_recovers_at_synchronization_points() {
+    let program = "let x = 1 + in let y = (2 * ) in if x < then y else 3;;\n";
+    let err = parse(tokenize(program).unwrap()).err().unwrap();
+    let messages: Vec<&str> = err.errors().iter().map(|e| &e.message[..]).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "expected expression, found `in`.",
+            "expected expression, found `)`.",
+            "expected expression, found `then`.",
+        ]
+    );
+}
+
+#[test]
+fn each_error_is_reported_once() {
+    let cases = [
+        ("(((;;\n", vec!["expected expression, found `;;`."]),
+        (
+            "let rec f = 3 in f;;\n",
+            vec!["`let rec` should bind a `fun`."],
+        ),
+        (
+            "let x = in let y = ) in y;;\n",
+            vec![
+                "expected expression, found `in`.",
+                "expected expression, found `)`.",
+            ],
+        ),
+    ];
+    for (program, expected) in cases.iter() {
+        let err = parse(tokenize(program).unwrap()).err().unwrap();
+        let messages: Vec<&str> = err.errors().iter().map(|e| &e.message[..]).collect();
+        assert_eq!(&messages, expected, "{}", program);
+    }
+}
+
+#[test]
+fn partial_ast_has_error_nodes() {
+    let mut tokenset = tokenize("let x = 1 + in x;;\n").unwrap();
+    let program = parse_partial(&mut tokenset).unwrap();
+    assert_eq!(tokenset.errors.len(), 1);
+    match program.body {
+        Ast::Let(_, ast1, ast2, _) => {
+            assert!(matches!(*ast1, Ast::Error(..)));
+            assert!(matches!(*ast2, Ast::Var(..)));
+        }
+        ast => panic!("unexpected {:?}", ast),
+    }
+}
+
+#[test]
+fn unterminated_input_is_an_error() {
+    let err = parse(tokenize("let x = 1 in").unwrap()).err().unwrap();
+    assert_eq!(err.message, "expected expression, found `end of input`.");
+    assert!(err.more.is_empty());
+    let err = parse(tokenize("1 + 2").unwrap()).err().unwrap();
+    assert_eq!(err.message, "expected `;;`, found `end of input`.");
+    let err = tokenize("99999999999;;").err().unwrap();
+    assert_eq!(err.message, "integer literal is too large.");
+}
+
+#[test]
+fn json_diagnostics() {
+    let (ok, out) = compile_in(
+        "json",
+        &[("main.ml", "let f = fun x -> x + 1 in\nf true;;\n")],
+        &["--error-format=json", "main.ml"],
+    );
+    assert!(!ok);
+    assert_eq!(out.lines().count(), 1);
+    assert!(out.starts_with(
+        "{\"severity\":\"error\",\"code\":\"type\",\"message\":\"expected int, found bool.\","
+    ));
+    assert!(out.contains(
+        "{\"file\":\"main.ml\",\"byte_start\":28,\"byte_end\":32,\"line_start\":2,\"column_start\":3,"
+    ));
+    assert!(
+        out.contains("\"primary\":false,\"label\":\"expected type `int` was introduced here.\"}")
+    );
+}
@@ -0,0 +1,149 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{compile_in, EXTERNAL, PROGRAM};
+
+use ruscaml::eval::{eval, eval_closure, eval_flat, Value, Word};
+use ruscaml::flat::read_program;
+use ruscaml::{Artifact, CompileResult, ErrorKind, Linkage, Session, Stage};
+
+// the value of `program` printed, or the error evaluating it
+fn run(program: &str) -> CompileResult<String> {
+    let mut session = Session::new("main.ml");
+    let (ast, link) = session.check(program)?;
+    eval(&ast, &link).map(|value| value.to_string())
+}
+
+// programs with their values, as the source defines them
+const CASES: [(&str, &str); 13] = [
+    ("1 + 3 * 4 + 5;;", "18"),
+    ("let f = fun x -> fun y -> fun z -> x+y+z in f 1 2 3;;", "6"),
+    ("(fun x -> fun y -> x + y) 2 3;;", "5"),
+    ("let a = let a = 1 in a+1 in a;;", "2"),
+    ("let a = (0, (1, (2, (3, 4)))) in a.2.2.2.1;;", "3"),
+    ("(1 < 2, (true, fun x -> x));;", "(true, (true, <fun>))"),
+    (
+        "let a = 1 in\nlet c = 3 in\nlet rec g = fun x -> a * x + c in\ng 4;;",
+        "7",
+    ),
+    (
+        "let rec fib = fun n -> if n < 2 then n else fib (n + 1073741823 * 4 + 3) + \
+             fib (n + 1073741823 * 4 + 2) in fib 10;;",
+        "55",
+    ),
+    (
+        "loop v = (1, 0) in\nif v.1 < 11 then recur (v.1 + 1, v.1 + v.2) else v.2;;",
+        "55",
+    ),
+    (
+        "let sum = fun n -> loop i = (0, 0) in if i.1 < n then recur (i.1 + 1, i.2 + i.1) \
+             else i.2 in sum 5 + sum 4;;",
+        "16",
+    ),
+    (
+        "let pg = loop v = (1, 0) in\nif v.1 < 11 then recur (v.1 + 1, v.1 + v.2) else v.2 in\n\
+             pg + 0;;",
+        "55",
+    ),
+    ("let p = (1, 2) in (p == p, p == (1, 2));;", "(true, false)"),
+    ("2147483647 + 1 == 2147483647 * 2 * 1073741824;;", "true"),
+];
+
+#[test]
+fn programs_evaluate_to_their_source_values() {
+    for (program, value) in CASES.iter() {
+        assert_eq!(run(program).unwrap(), *value, "{}", program);
+    }
+}
+
+// the value of `program` after `stage`, which is closure or flat
+fn run_ir(program: &str, stage: Stage) -> CompileResult<Word> {
+    let mut session = Session::new("main.ml");
+    match session.compile_to(program, stage)? {
+        Artifact::Closure(exp) => eval_closure(&exp, &session.link),
+        Artifact::Flat(flat) => eval_flat(&flat, &session.link),
+        artifact => panic!("unexpected {:?}", artifact),
+    }
+}
+
+#[test]
+fn closure_and_flat_programs_compute_the_source_values() {
+    for (program, value) in CASES.iter() {
+        for stage in [Stage::Closure, Stage::Flat].iter() {
+            let word = run_ir(program, *stage).unwrap();
+            // booleans are words, and functions are closures
+            match (value.parse::<i32>(), *value) {
+                (Ok(n), _) => assert!(matches!(word, Word::Int(m) if m == n), "{}", program),
+                (_, "true") => assert!(matches!(word, Word::Int(1)), "{}", program),
+                _ => assert!(matches!(word, Word::Tuple(_)), "{}", program),
+            }
+        }
+    }
+}
+
+#[test]
+fn closures_are_tuples_with_their_code_first() {
+    let program = "let a = 1 in\nlet c = 3 in\nlet rec g = fun x -> a * x + c in\ng;;";
+    for stage in [Stage::Closure, Stage::Flat].iter() {
+        assert_eq!(
+            run_ir(program, *stage).unwrap().to_string(),
+            "(&$b_g, 1, 3)"
+        );
+    }
+    let program = "(1 < 2, (true, fun x -> x));;";
+    let word = run_ir(program, Stage::Flat).unwrap().to_string();
+    assert!(word.starts_with("(1, (1, (&$b_@v"), "{}", word);
+}
+
+#[test]
+fn flat_code_sees_only_its_parameters() {
+    // `y` is not captured in the closure of `f`
+    let text = "let rec f (f, x) = x+y in\nlet rec _toplevel () = \n\
+                let y = 1 in\nlet g = (&f) in\nlet h = g.0 in\nh (g, 2)";
+    let err = eval_flat(&read_program(text).unwrap(), &Linkage::default()).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Internal);
+    assert_eq!(err.message, "`y` is not bound.");
+    assert_eq!(&text[err.span.unwrap().lo..err.span.unwrap().hi], "x+y");
+
+    let text = "let rec f (f, x) = x+1 in\nlet rec _toplevel () = \n\
+                let g = (&f) in\nlet h = g.1 in\nh (g, 2)";
+    let err = eval_flat(&read_program(text).unwrap(), &Linkage::default()).unwrap_err();
+    assert_eq!(err.message, "`(&f)` has no slot 1.");
+}
+
+#[test]
+fn expect_tests_evaluate_to_zero() {
+    let mut session = Session::new("main.ml");
+    let (ast, link) = session.check("EXPECT(1 + 3 * 4 + 5;; => 18):").unwrap();
+    assert!(matches!(eval(&ast, &link).unwrap(), Value::Int(0)));
+}
+
+#[test]
+fn externals_and_other_units_cannot_be_evaluated() {
+    let program = EXTERNAL;
+    let err = run(program).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Eval);
+    assert_eq!(err.message, "cannot call external `c_add3`.");
+
+    let files = [
+        ("util.mli", "val double : int -> int\n"),
+        ("main.ml", "Util.double 2;;\n"),
+    ];
+    let (ok, out) = compile_in("eval_unit", &files, &["eval", "main.ml"]);
+    assert!(!ok);
+    assert!(out.contains("cannot run `Util.double`, which is defined in another unit."));
+}
+
+#[test]
+fn eval_prints_the_value_of_each_file() {
+    let files = [("a.ml", PROGRAM), ("b.ml", "(1, 2 == 2);;\n")];
+    let (ok, out) = compile_in("eval_files", &files, &["eval", "a.ml", "b.ml"]);
+    assert!(ok);
+    assert_eq!(out, "6\n(1, true)\n");
+
+    let args = ["eval", "--stage=flat", "a.ml", "b.ml"];
+    let (ok, out) = compile_in("eval_flat", &files, &args);
+    assert!(ok);
+    assert_eq!(out, "6\n(1, 1)\n");
+}
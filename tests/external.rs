@@ -0,0 +1,52 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{assemble, compile};
+
+#[test]
+fn direct_call() {
+    let program = "external add3 : int -> int -> int -> int = \"c_add3\"
+let x = 4 in add3 (x + 1) 2 (add3 1 1 1);;
+";
+    let (ok, out) = compile("external_ok", program);
+    assert!(ok);
+    assert_eq!(out.matches("\tbl c_add3\n").count(), 2);
+    assert!(!out.contains("blr"));
+}
+
+#[test]
+fn calls_assemble() {
+    let program = "external add3 : int -> int -> int -> int = \"c_add3\"
+let x = 4 in add3 (x + 1) 2 (add3 1 1 1);;
+";
+    let (ok, out) = compile("external_assemble", program);
+    assert!(ok);
+    assert!(out.contains("\tstp x29, x30, [sp, #-32]!\n\tmov x29, sp\n"));
+    match assemble("external_assemble", &out) {
+        Some(result) => result.unwrap(),
+        None => eprintln!("no aarch64 assembler; the output was not assembled"),
+    }
+}
+
+#[test]
+fn partial_application() {
+    let (ok, out) = compile(
+        "external_partial",
+        "external add3 : int -> int -> int -> int = \"c_add3\"\nlet f = add3 1 in f 2 3;;\n",
+    );
+    assert!(!ok);
+    assert!(
+        out.contains("Error: external `add3` must be applied to 3 arguments. Line: 2, Column: 9.")
+    );
+}
+
+#[test]
+fn function_argument() {
+    let (ok, out) = compile(
+        "external_fun_arg",
+        "external apply : (int -> int) -> int = \"c_apply\"\n1;;\n",
+    );
+    assert!(!ok);
+    assert!(out.contains("Error: external arguments must be int or bool. Line: 1, Column: 1."));
+}
@@ -0,0 +1,160 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{compile_in, PROGRAM};
+
+use ruscaml::format::format;
+use ruscaml::lexer::tokenize;
+use ruscaml::parser::{parse, parse_unit, Item};
+use ruscaml::Json;
+
+// the syntax tree of a program or unit without its spans, which the
+// formatter moves
+fn tree(source: &str) -> Json {
+    fn strip(json: Json) -> Json {
+        match json {
+            Json::Object(fields) => Json::Object(
+                fields
+                    .into_iter()
+                    .filter(|(key, _)| key != "span")
+                    .map(|(key, value)| (key, strip(value)))
+                    .collect(),
+            ),
+            Json::Array(items) => Json::Array(items.into_iter().map(strip).collect()),
+            json => json,
+        }
+    }
+    let json = match parse(tokenize(source).unwrap()) {
+        Ok(program) => program.to_json(),
+        Err(_) => {
+            let items = parse_unit(tokenize(source).unwrap()).unwrap();
+            Json::Array(items.iter().map(Item::to_json).collect())
+        }
+    };
+    strip(json)
+}
+
+const SOURCES: [&str; 6] = [
+    PROGRAM,
+    "let a = let a = 1 in a+1 in a;;",
+    "let a = (0, (1, (2, (3, 4)))) in a.2.2.2.1;;",
+    "(fun x -> fun y -> x + y) 2 3;;",
+    "let x = ((1 + 2) + 3) * (4 * 5) in (x == (1 < 2)) == (f (g x) (recur 1)) ;;",
+    "module M = struct\n  let rec f = fun x -> x + 1\n\n  let y = 4\nend\nexternal add3 : int -> int -> int -> int = \"c_add3\"\nadd3 (M.f M.y) 2 3;;\n",
+];
+
+#[test]
+fn formatting_keeps_the_tree_and_is_stable() {
+    for source in SOURCES.iter() {
+        let formatted = format(source).unwrap();
+        assert_eq!(tree(&formatted), tree(source), "{}", formatted);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+}
+
+#[test]
+fn parentheses_follow_precedence() {
+    let formatted = format(SOURCES[4]).unwrap();
+    assert_eq!(
+        formatted,
+        "let x = ((1 + 2) + 3) * (4 * 5) in\n(x == 1 < 2) == f (g x) (recur 1);;\n"
+    );
+}
+
+#[test]
+fn long_expressions_are_laid_out_in_blocks() {
+    let source = "loop v = (1, 0) in if v.1 < 11 then recur (v.1 + 1, v.1 + v.2) else \
+                  if v.2 == 0 then let z = v.1 * 2 in z + 1 else (fun q -> q) v.2;;";
+    assert_eq!(
+        format(source).unwrap(),
+        "loop v = (1, 0) in
+if v.1 < 11 then
+  recur (v.1 + 1, v.1 + v.2)
+else if v.2 == 0 then
+  let z = v.1 * 2 in
+  z + 1
+else
+  (fun q -> q) v.2;;
+"
+    );
+    let source = "let f = let y = 1 in fun x -> x + y in f 2;;";
+    assert_eq!(
+        format(source).unwrap(),
+        "let f =\n  let y = 1 in\n  fun x -> x + y\nin\nf 2;;\n"
+    );
+}
+
+#[test]
+fn comments_are_kept() {
+    let source =
+        "(* units *)\nmodule M = struct\n  (* the answer *)\n  let y = 42 (* exactly *)\nend\n\
+                  let x = M.y in (* x *)\n(* (* nested *) *)\nx;; (* done *)\n";
+    let formatted = format(source).unwrap();
+    assert_eq!(
+        formatted,
+        "(* units *)\nmodule M = struct\n  (* the answer *)\n  let y = 42 (* exactly *)\nend\n\
+         let x = M.y in (* x *)\n(* (* nested *) *)\nx;; (* done *)\n"
+    );
+    assert_eq!(format(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn comments_inside_expressions_stay_in_place() {
+    let cases = [
+        ("1 + (* c *) 2;;", "1 + (* c *) 2;;\n"),
+        (
+            "if (* c *) true then 1 else 2;;",
+            "if (* c *) true then\n  1\nelse\n  2;;\n",
+        ),
+        (
+            "f 1 (* a *) (g (* b *) 2);;",
+            "f 1 (* a *) (g (* b *) 2);;\n",
+        ),
+    ];
+    for (source, expected) in cases.iter() {
+        let formatted = format(source).unwrap();
+        assert_eq!(formatted, *expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+}
+
+#[test]
+fn units_are_formatted_too() {
+    let source = "let rec f = fun x -> x+1\nlet y = f 3\n\n\nopen M\n";
+    assert_eq!(
+        format(source).unwrap(),
+        "let rec f = fun x -> x + 1\nlet y = f 3\n\nopen M\n"
+    );
+    let err = format("let x = 1 in x").unwrap_err();
+    assert_eq!(err.message, "expected `;;`, found `end of input`.");
+}
+
+#[test]
+fn fmt_rewrites_files_and_checks_them() {
+    let files = [("main.ml", "let x=1 in x+1;;")];
+    let (ok, out) = compile_in("fmt_check", &files, &["fmt", "--check", "main.ml"]);
+    assert!(!ok);
+    assert_eq!(out, "ruscaml: `main.ml` is not formatted.\n");
+
+    let dir = std::env::temp_dir().join("ruscaml_fmt_write");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.ml");
+    std::fs::write(&file, files[0].1).unwrap();
+    let fmt = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_ruscaml"))
+            .args(args)
+            .arg(&file)
+            .status()
+            .unwrap()
+            .success()
+    };
+    assert!(fmt(&["fmt"]));
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "let x = 1 in\nx + 1;;\n"
+    );
+    assert!(fmt(&["fmt", "--check"]));
+    let _ = std::fs::remove_dir_all(&dir);
+}
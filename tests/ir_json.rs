@@ -0,0 +1,115 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{compile_in, compile_to, CLOSURES, PROGRAM};
+
+use ruscaml::verify::verify_flat;
+use ruscaml::vm::trans_pg;
+use ruscaml::{closure, flat, normal, vm};
+use ruscaml::{Artifact, Json, Linkage, Stage};
+
+// the JSON of an artifact, printed and parsed again
+fn reparsed(artifact: &Artifact) -> Json {
+    Json::parse(&artifact.json().unwrap().to_string()).unwrap()
+}
+
+#[test]
+fn irs_read_back_from_json() {
+    for program in [PROGRAM, CLOSURES].iter() {
+        let artifact = compile_to(program, Stage::Normal);
+        let exp = normal::Exp::from_json(&reparsed(&artifact)).unwrap();
+        assert_eq!(Some(exp.to_json()), artifact.json());
+        assert_eq!(format!("{}\n", exp), artifact.text(""));
+
+        let artifact = compile_to(program, Stage::Closure);
+        let exp = closure::Exp::from_json(&reparsed(&artifact)).unwrap();
+        assert_eq!(Some(exp.to_json()), artifact.json());
+
+        let artifact = compile_to(program, Stage::Flat);
+        let flat = flat::Program::from_json(&reparsed(&artifact)).unwrap();
+        assert_eq!(Some(flat.to_json()), artifact.json());
+        assert_eq!(format!("{}\n", flat), artifact.text(""));
+    }
+    // the backend takes an imported program like any other
+    let artifact = compile_to(PROGRAM, Stage::Flat);
+    let flat = flat::Program::from_json(&reparsed(&artifact)).unwrap();
+    let vm = trans_pg(flat).unwrap();
+    assert_eq!(Some(vm.to_json()), compile_to(PROGRAM, Stage::Vm).json());
+    for stage in [Stage::Vm, Stage::VmAlloc].iter() {
+        let artifact = compile_to(PROGRAM, *stage);
+        let vm = vm::Program::from_json(&reparsed(&artifact)).unwrap();
+        assert_eq!(Some(vm.to_json()), artifact.json());
+        let read = match stage {
+            Stage::Vm => Artifact::Vm(vm),
+            _ => Artifact::VmAlloc(vm),
+        };
+        assert_eq!(read.text(""), artifact.text(""));
+    }
+}
+
+#[test]
+fn flat_json_distinguishes_function_labels() {
+    let text = r#"{"recdecls": [
+        {"name": "f", "params": ["x"],
+         "body": {"kind": "Compexp", "exp": {"kind": "Val", "value": "x"}}},
+        {"name": "_toplevel", "params": [],
+         "body": {"kind": "Compexp", "exp": {"kind": "Tuple", "values": [{"fun": "f"}, -1]}}}
+    ]}"#;
+    let program = flat::Program::from_json(&Json::parse(text).unwrap()).unwrap();
+    assert_eq!(
+        program.to_string(),
+        "let rec f (x) = x in\nlet rec _toplevel () = (&f, -1)"
+    );
+    assert!(verify_flat(&program, &Linkage::default()).is_ok());
+}
+
+#[test]
+fn malformed_json_is_reported() {
+    let err = Json::parse("{\"a\": [1, 2,]}").unwrap_err();
+    assert_eq!(err.message, "JSON: expected a value.");
+    assert_eq!(err.span.unwrap().lo, 12);
+    assert!(Json::parse("1.5").is_err());
+    assert!(Json::parse("\"open").is_err());
+    assert_eq!(
+        Json::parse(r#"["é😀\n", null, true, -3]"#).unwrap(),
+        Json::Array(vec![
+            Json::str("é😀\n"),
+            Json::Null,
+            Json::Bool(true),
+            Json::Num(-3)
+        ])
+    );
+
+    let exp = Json::parse(r#"{"kind": "Let", "name": "x", "value": {"kind": "Val", "value": 1}}"#);
+    let err = normal::Exp::from_json(&exp.unwrap()).unwrap_err();
+    assert_eq!(err.message, "JSON: missing field `body`.");
+    let err = vm::Program::from_json(
+        &Json::parse(
+            r#"{"functions": [{"name": "f",
+        "frame": 0, "calls": false, "instrs": [{"kind": "Jump"}]}]}"#,
+        )
+        .unwrap(),
+    )
+    .unwrap_err();
+    assert_eq!(err.message, "JSON: unknown instruction kind `Jump`.");
+}
+
+#[test]
+fn stages_are_emitted_as_json() {
+    let files = [("main.ml", PROGRAM)];
+    let args = ["--emit=ast", "--emit-format=json", "main.ml"];
+    let (ok, out) = compile_in("json_ast", &files, &args);
+    assert!(ok);
+    let ast = Json::parse(&out).unwrap();
+    assert_eq!(ast.field("body").unwrap().kind().unwrap(), "Let");
+
+    let args = ["--emit=vm", "--emit-format=json", "main.ml"];
+    let (ok, out) = compile_in("json_vm", &files, &args);
+    assert!(ok);
+    assert!(vm::Program::from_json(&Json::parse(&out).unwrap()).is_ok());
+
+    let args = ["--emit=asm", "--emit-format=json", "main.ml"];
+    let (ok, _) = compile_in("json_asm", &files, &args);
+    assert!(!ok);
+}
@@ -0,0 +1,161 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{compile_to, CLOSURES, EXTERNAL, LOOP, PROGRAM};
+
+use ruscaml::codegen::codegen;
+use ruscaml::flat::{read_program, Cexp, Exp, Value};
+use ruscaml::regalloc::regalloc;
+use ruscaml::vm::{self, trans_pg};
+use ruscaml::{Artifact, ErrorKind, Linkage, Stage};
+
+const PROGRAMS: [&str; 4] = [
+    PROGRAM,
+    CLOSURES,
+    "loop v = (1, 0) in\nif v.1 < 11 then recur (v.1 + 1, v.1 + v.2) else v.2;;\n",
+    "module M = struct\n  let rec f = fun x -> x + 1\n  let y = 4\nend\nexternal add3 : int -> int -> int -> int = \"c_add3\"\nadd3 (M.f M.y) 2 3;;\n",
+];
+
+fn flat_text(program: &str) -> String {
+    match compile_to(program, Stage::Flat) {
+        Artifact::Flat(flat) => flat.to_string(),
+        artifact => panic!("unexpected {:?}", artifact),
+    }
+}
+
+#[test]
+fn flat_programs_read_back_as_printed() {
+    for program in PROGRAMS.iter() {
+        let text = flat_text(program);
+        let read = read_program(&text).unwrap();
+        assert_eq!(read.to_string(), text);
+    }
+}
+
+#[test]
+fn flat_text_tells_functions_from_variables() {
+    let text =
+        "let rec f (x) = x in\nlet rec _toplevel () = \nlet g = (&f) in\nlet h = g.0 in\nh (g, -2)";
+    let program = read_program(text).unwrap();
+    assert_eq!(program.recs.len(), 2);
+    match &*program.recs[1].2 {
+        Exp::Let(id, cexp, _, span) => {
+            assert_eq!(id, "g");
+            assert!(matches!(&**cexp, Cexp::Tuple(vals) if vals == &[Value::Fun("f".into())]));
+            assert_eq!(&text[span.lo..span.hi], "(&f)");
+        }
+        exp => panic!("unexpected {:?}", exp),
+    }
+    assert_eq!(program.to_string(), text);
+}
+
+#[test]
+fn hand_written_flat_programs_reach_the_backend() {
+    let text = "let rec _toplevel () = \nlet x = 20 in\nlet y = x+1 in\ny*2";
+    let vm = trans_pg(read_program(text).unwrap()).unwrap();
+    assert_eq!(vm.decls.len(), 1);
+    assert_eq!(vm.decls[0].funlb, "_toplevel");
+}
+
+#[test]
+fn malformed_flat_text_is_a_syntax_error() {
+    let text = "let rec _toplevel () = \nlet x = in\nx";
+    let err = read_program(text).unwrap_err();
+    assert_eq!(err.message, "expected name, found `in`.");
+    assert_eq!(err.span.unwrap().lo, text.find("in\n").unwrap());
+    assert!(read_program("let rec f () = 1 2").is_err());
+}
+
+fn vm_program(program: &str, stage: Stage) -> ruscaml::vm::Program {
+    match compile_to(program, stage) {
+        Artifact::Vm(vm) | Artifact::VmAlloc(vm) => vm,
+        artifact => panic!("unexpected {:?}", artifact),
+    }
+}
+
+// programs the backend gets through; it does not handle closures and
+// tuples yet
+const VM_PROGRAMS: [&str; 3] = [PROGRAM, LOOP, EXTERNAL];
+
+#[test]
+fn vm_programs_read_back_as_printed() {
+    for program in VM_PROGRAMS.iter() {
+        let text = vm_program(program, Stage::Vm).to_string();
+        assert_eq!(vm::read_program(&text, false).unwrap().to_string(), text);
+        let text = format!("{:#}", vm_program(program, Stage::VmAlloc));
+        let read = vm::read_program(&text, true).unwrap();
+        assert_eq!(format!("{:#}", read), text);
+    }
+}
+
+const VM_TEXT: &str = "function f (frame 2):
+ local(2) <- param(0)
+ r5:8 <- local(2)
+ r6 <- imm(1)
+ r5:8 <- +(r5:8, r6)
+kill r6
+ r7:8 <- r5:8
+ return(r7:8)
+kill r5:8
+";
+
+#[test]
+fn hand_written_vm_code_is_allocated_and_compiled() {
+    let mut program = vm::read_program(VM_TEXT, false).unwrap();
+    assert_eq!(program.to_string(), VM_TEXT);
+    regalloc(&mut program).unwrap();
+    assert_eq!(
+        format!("{:#}", program),
+        "function f (frame 2):
+ local(2) <- param(0)
+ r0:8 <- local(2)
+ r1 <- imm(1)
+ r0:8 <- +(r0:8, r1)
+kill r1
+ r0:8 <- r0:8
+ return(r0:8)
+kill r0:8
+"
+    );
+    let asm = codegen(program, &Linkage::default()).unwrap();
+    assert!(asm.contains("f:\n\tsub sp, sp, #16\n\tstr x0, [sp, 8]\n\tldr x0, [sp, 8]\n"));
+    assert!(asm.ends_with("\tmov x0, x0\n\tadd sp, sp, #16\n\tret\n"));
+}
+
+#[test]
+fn external_arguments_in_swapped_registers_are_moved_at_once() {
+    let text = "function f (frame 0):\n r0 <- imm(1)\n r1 <- imm(2)\n r2 <- c_sub(r1, r0)\n";
+    let mut program = vm::read_program(text, false).unwrap();
+    regalloc(&mut program).unwrap();
+    let asm = codegen(program, &Linkage::default()).unwrap();
+    assert!(
+        asm.contains("\tmov x16, x0\n\tmov x0, x1\n\tmov x1, x16\n\tbl c_sub\n"),
+        "{}",
+        asm
+    );
+}
+
+#[test]
+fn killing_a_register_never_allocated_is_an_error() {
+    let text = "function f (frame 0):\n r0 <- imm(1)\nkill r0\nkill r1\n";
+    let mut program = vm::read_program(text, false).unwrap();
+    let err = regalloc(&mut program).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Internal);
+    assert_eq!(
+        err.message,
+        "`r1` cannot be killed, as it holds no register."
+    );
+    assert_eq!(err.span.unwrap().lo, text.find("kill r1").unwrap());
+}
+
+#[test]
+fn malformed_vm_text_is_a_syntax_error() {
+    let err = vm::read_program(" r0 <- imm(1)\n", false).unwrap_err();
+    assert_eq!(err.message, "expected `function`.");
+    let text = "function f (frame 0):\n r0 <- +(r1, r2)\n";
+    let err = vm::read_program(text, false).unwrap_err();
+    assert_eq!(err.span.unwrap().lo, text.find('+').unwrap());
+    let err = vm::read_program("function f (frame 0):\n return(r0)\n", false).unwrap_err();
+    assert_eq!(err.message, "`return(r0)` should follow a move into `r0`.");
+}
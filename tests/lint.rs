@@ -0,0 +1,66 @@
+extern crate ruscaml;
+
+use ruscaml::lexer::tokenize;
+use ruscaml::lint::lint;
+use ruscaml::parser::parse;
+use ruscaml::{ErrorKind, Severity};
+
+fn warnings(source: &str) -> Vec<(ErrorKind, String, usize)> {
+    let program = parse(tokenize(source).unwrap()).unwrap();
+    lint(&program)
+        .into_iter()
+        .map(|w| {
+            assert_eq!(w.severity, Severity::Warning);
+            (w.kind, w.message, w.span.unwrap().lo)
+        })
+        .collect()
+}
+
+#[test]
+fn shadowing_inside_a_definition() {
+    let found = warnings("let a = let a = 1 in a+1 in a;;\n");
+    assert_eq!(
+        found,
+        vec![(
+            ErrorKind::Shadowed,
+            String::from("`a` shadows an earlier binding."),
+            12
+        )]
+    );
+}
+
+#[test]
+fn unused_bindings() {
+    let found = warnings("let x = 1 in let f = fun z -> 3 in let rec g = fun n -> g n in f 0;;\n");
+    let messages: Vec<&str> = found.iter().map(|(_, m, _)| &m[..]).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "unused variable `x`; prefix it with `_` to silence this.",
+            "unused parameter `z`; prefix it with `_` to silence this.",
+            "unused function `g`; prefix it with `_` to silence this.",
+        ]
+    );
+    assert!(found.iter().all(|(kind, _, _)| *kind == ErrorKind::Unused));
+}
+
+#[test]
+fn underscore_and_module_members_are_silent() {
+    let source = "module M = struct let y = 2 let rec f = fun _n -> 1 end\nlet _x = 1 in let _x = 2 in M.f 0;;\n";
+    assert!(warnings(source).is_empty());
+}
+
+#[test]
+fn warnings_point_at_the_binder() {
+    let source = "module M = struct let rec f = fun n -> 1 end\n\
+                  let rec g = fun m -> 2 in loop v = 0 in g (M.f 0);;\n";
+    let spans: Vec<usize> = warnings(source).iter().map(|(_, _, lo)| *lo).collect();
+    assert_eq!(
+        spans,
+        vec![
+            source.find("n ->").unwrap(),
+            source.find("m ->").unwrap(),
+            source.find("v =").unwrap(),
+        ]
+    );
+}
@@ -0,0 +1,37 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::compile;
+
+#[test]
+fn recur_in_nested_function() {
+    let (ok, out) = compile("loops_fun", "loop x = 0 in fun y -> recur x;;\n");
+    assert!(!ok);
+    assert!(out.contains(
+        "Error: `recur` is not inside a loop of the same function. Line: 1, Column: 24."
+    ));
+    assert!(out.contains("Note: the enclosing function starts here. Line: 1, Column: 15."));
+}
+
+#[test]
+fn recur_not_in_tail_position() {
+    let (ok, out) = compile("loops_tail", "loop x = 0 in 1 + recur x;;\n");
+    assert!(!ok);
+    assert!(
+        out.contains("Error: `recur` must be in tail position of its loop. Line: 1, Column: 19.")
+    );
+}
+
+#[test]
+fn recur_shape_mismatch() {
+    let (ok, out) = compile(
+        "loops_shape",
+        "loop v = (1, 0) in\nif v.1 < 3 then recur 4 else v.2;;\n",
+    );
+    assert!(!ok);
+    assert!(out.contains(
+        "Error: `recur` value has shape _, but loop variable `v` has shape (_, _). Line: 2, Column: 23."
+    ));
+    assert!(out.contains("Note: loop variable `v` is bound here. Line: 1, Column: 10."));
+}
@@ -0,0 +1,56 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::compile;
+
+#[test]
+fn qualified_and_opened_names() {
+    let program = "module M = struct
+  let x = 1
+  module N = struct
+    let y = x + 2
+  end
+end
+open M
+M.N.y + N.y + x;;
+";
+    let (ok, _) = compile("module_ok", program);
+    assert!(ok);
+}
+
+#[test]
+fn unbound_member() {
+    let (ok, out) = compile(
+        "module_unbound",
+        "module M = struct\n  let x = 1\nend\nM.y;;\n",
+    );
+    assert!(!ok);
+    assert!(out.contains("Error: unbound value `M.y`. Line: 4, Column: 1."));
+}
+
+#[test]
+fn local_binding_shadows_opened_member() {
+    let program = "module M = struct\n  let x = true\nend\nopen M\nlet x = 1 in x + 1;;\n";
+    let (ok, _) = compile("module_shadow", program);
+    assert!(ok);
+}
+
+#[test]
+fn opened_members_are_not_exported() {
+    let program = "module A = struct\n  let x = 1\nend\n\
+                   module B = struct\n  open A\n  let y = x\nend\nB.x;;\n";
+    let (ok, out) = compile("module_open_export", program);
+    assert!(!ok);
+    assert!(out.contains("Error: unbound value `B.x`"), "{}", out);
+}
+
+#[test]
+fn duplicate_module_does_not_hide_later_errors() {
+    let program = "module M = struct let x = 1 end\nmodule M = struct let y = z end\nM.w;;\n";
+    let (ok, out) = compile("module_duplicate", program);
+    assert!(!ok);
+    assert!(out.contains("Error: module `M` is already defined. Line: 2, Column: 1."));
+    assert!(out.contains("Error: unbound variable `z`."));
+    assert!(out.contains("Error: unbound value `M.w`."));
+}
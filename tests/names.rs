@@ -0,0 +1,46 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::compile;
+
+#[test]
+fn every_unbound_name_is_reported() {
+    let (ok, out) = compile("names_all", "let length = 3 in\nlenght + widht;;\n");
+    assert!(!ok);
+    assert!(out
+        .contains("Error: unbound variable `lenght`; did you mean `length`? Line: 2, Column: 1."));
+    assert!(out.contains("Error: unbound variable `widht`. Line: 2, Column: 10."));
+}
+
+#[test]
+fn innermost_local_is_suggested() {
+    let program = "let rec count = fun n -> n in\nlet counts = 1 in\ncont counts;;\n";
+    let (ok, out) = compile("names_local", program);
+    assert!(!ok);
+    assert!(out.contains("unbound variable `cont`; did you mean `count`?"));
+}
+
+#[test]
+fn members_are_suggested() {
+    let program = "module Shape = struct\n  let width = 1\nend\nShape.widht;;\n";
+    let (ok, out) = compile("names_member", program);
+    assert!(!ok);
+    assert!(out.contains("unbound value `Shape.widht`; did you mean `Shape.width`?"));
+}
+
+#[test]
+fn unbound_module_suggests_a_sibling() {
+    let program = "module Shape = struct\n  let width = 1\nend\nShap.width;;\n";
+    let (ok, out) = compile("names_module", program);
+    assert!(!ok);
+    assert!(out.contains("unbound module `Shap`; did you mean `Shape`? Line: 4, Column: 1."));
+}
+
+#[test]
+fn every_unbound_module_is_reported() {
+    let (ok, out) = compile("names_modules", "A.x + B.y;;\n");
+    assert!(!ok);
+    assert!(out.contains("Error: unbound module `A`. Line: 1, Column: 1."));
+    assert!(out.contains("Error: unbound module `B`. Line: 1, Column: 7."));
+}
@@ -0,0 +1,68 @@
+extern crate ruscaml;
+
+use ruscaml::normal::{Cexp, Exp, Value};
+use ruscaml::verify::verify_normal;
+use ruscaml::{Artifact, Linkage, Session, Span, Stage};
+
+fn normal_text(program: &str) -> String {
+    let mut session = Session::new("main.ml");
+    session.verify_ir = true;
+    match session.compile_to(program, Stage::Normal) {
+        Ok(artifact @ Artifact::Normal(_)) => artifact.text(""),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn shadowing_binders_get_names_of_their_own() {
+    assert_eq!(
+        normal_text("let a = let a = 1 in a+1 in a;;"),
+        "let a = 1 in\nlet a$1 = a+1 in\na$1\n"
+    );
+    // the inner `f` is floated out of the value of `g` and must not
+    // capture the call of the outer one
+    let mut session = Session::new("main.ml");
+    let program = "let rec f = fun x -> x + 1 in\n\
+                   let g = let rec f = fun y -> y * 2 in f in\nf (g 3);;\n";
+    match session.compile_to(program, Stage::Flat) {
+        Ok(Artifact::Flat(flat)) => {
+            let text = flat.to_string();
+            assert!(text.contains("let rec $b_f$1 (f$1, y) = y*2 in"));
+            assert!(text.ends_with("let $r_f0 = f.0 in\n$r_f0 (f, @v0)"));
+        }
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(session.renames.original("f$1"), "f");
+    assert_eq!(session.renames.original("g"), "g");
+}
+
+#[test]
+fn errors_name_binders_as_in_the_source() {
+    let mut session = Session::new("main.ml");
+    let program = "let v = 1 in\nloop v = (1, 2) in\nif v.1 < 3 then recur 4 else v.2;;\n";
+    let err = session.compile(program).unwrap_err();
+    assert_eq!(
+        err.message,
+        "`recur` value has shape _, but loop variable `v` has shape (_, _)."
+    );
+    assert_eq!(err.note.unwrap().1, "loop variable `v` is bound here.");
+    assert_eq!(session.renames.original("v$1"), "v");
+}
+
+#[test]
+fn binders_bound_twice_fail_verification() {
+    let val = |v| Box::new(Cexp::Val(Value::Intv(v)));
+    let exp = Exp::Let(
+        "x".to_string(),
+        val(1),
+        Box::new(Exp::Let(
+            "x".to_string(),
+            val(2),
+            Box::new(Exp::Compexp(val(3), Span::default())),
+            Span::default(),
+        )),
+        Span::default(),
+    );
+    let err = verify_normal(&exp, &Linkage::default()).unwrap_err();
+    assert_eq!(err.message, "normal IR: `x` is bound twice.");
+}
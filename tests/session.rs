@@ -0,0 +1,140 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{CLOSURES, PROGRAM};
+
+use ruscaml::{ErrorKind, Session, Stage};
+
+use std::fs;
+use std::thread;
+
+#[test]
+fn compiling_again_gives_the_same_assembly() {
+    let mut session = Session::new("main.ml");
+    let first = session.compile(PROGRAM).unwrap();
+    let second = session.compile(PROGRAM).unwrap();
+    assert_eq!(first, second);
+    assert!(first.starts_with(".text\n\t.global _toplevel\n"));
+    assert_eq!(first, Session::new("other.ml").compile(PROGRAM).unwrap());
+}
+
+#[test]
+fn sessions_compile_in_parallel() {
+    let expected = Session::new("main.ml").compile(PROGRAM).unwrap();
+    let threads: Vec<_> = (0..8)
+        .map(|_| thread::spawn(|| Session::new("main.ml").compile(PROGRAM).unwrap()))
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn errors_and_warnings_stay_with_their_session() {
+    let mut failing = Session::new("main.ml");
+    let err = failing
+        .compile("let unused = 1 in\n1 + true;;\n")
+        .unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Type);
+    assert_eq!(failing.warnings.len(), 1);
+    assert!(err.text(&failing.source).contains("Line: 2, Column: 5."));
+
+    let mut passing = Session::new("main.ml");
+    passing.compile("1;;\n").unwrap();
+    assert!(passing.warnings.is_empty());
+    failing.compile("1;;\n").unwrap();
+    assert!(failing.warnings.is_empty());
+}
+
+#[test]
+fn units_are_found_next_to_the_file() {
+    let dir = std::env::temp_dir().join("ruscaml_session_units");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("util.mli"), "val double : int -> int\n").unwrap();
+
+    let mut unit = Session::new(dir.join("util.ml"));
+    unit.unit = true;
+    let asm = unit.compile("let rec double = fun x -> x * 2\n").unwrap();
+    assert!(asm.contains("\t.global ruscaml_Util_double\n"));
+
+    let mut main = Session::new(dir.join("main.ml"));
+    let asm = main.compile("Util.double 21;;\n").unwrap();
+    assert!(asm.contains("ruscaml_Util_double_clo"));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn stats_and_pass_times_are_recorded() {
+    let mut session = Session::new("main.ml");
+    session.compile(PROGRAM).unwrap();
+    let passes: Vec<&str> = session.passes.iter().map(|(pass, _)| *pass).collect();
+    assert_eq!(
+        passes,
+        [
+            "lex",
+            "parse",
+            "lint",
+            "resolve",
+            "rename",
+            "check_loops",
+            "typecheck",
+            "normalize",
+            "closure",
+            "flat",
+            "trans_pg",
+            "regalloc",
+            "codegen"
+        ]
+    );
+    assert_eq!(session.stats.recdecls, 1);
+    let vm: Vec<&str> = session.stats.vm.iter().map(|(pass, _)| *pass).collect();
+    assert_eq!(vm, ["trans_pg", "regalloc"]);
+    let before = &session.stats.after("trans_pg").unwrap()[0];
+    let after = &session.stats.after("regalloc").unwrap()[0];
+    assert_eq!(after.name, "_toplevel");
+    assert_eq!(before.regs, 0);
+    assert!(after.instrs > 0 && after.frame > 0 && after.regs <= after.vregs);
+
+    session.compile_to(CLOSURES, Stage::Closure).unwrap();
+    assert_eq!(session.stats.closures, 3);
+    assert_eq!(session.passes.last().unwrap().0, "closure");
+}
+
+#[test]
+fn dumps_are_kept_even_if_a_later_pass_fails() {
+    let mut session = Session::new("main.ml");
+    session.dump_after = vec!["normalize".to_string(), "parse".to_string()];
+    let text = session
+        .compile_to(PROGRAM, Stage::Normal)
+        .unwrap()
+        .text(&session.source);
+    let passes: Vec<&str> = session.dumps.iter().map(|(pass, _)| *pass).collect();
+    assert_eq!(passes, ["parse", "normalize"]);
+    assert_eq!(session.dumps[0].1, PROGRAM);
+    assert_eq!(session.dumps[1].1, text);
+    assert!(text.starts_with("let x = 3 in\nlet @v0 = x<4 in\n"));
+
+    session.dump_after = vec!["lint".to_string()];
+    assert!(session.compile("1 + true;;\n").is_err());
+    assert_eq!(session.dumps.len(), 1);
+    assert_eq!(session.dumps[0].1, "1 + true;;\n");
+}
+
+#[test]
+fn syntax_trees_are_dumped_as_source() {
+    let mut session = Session::new("main.ml");
+    session.dump_after = vec!["rename".to_string()];
+    let program = "let x = 1 in\nlet x = x + 1 in\n(* the second x *)\nx;;\n";
+    session.compile_to(program, Stage::Normal).unwrap();
+    assert_eq!(
+        session.dumps[0].1,
+        "let x = 1 in\nlet x$1 = x + 1 in\nx$1;;\n"
+    );
+    let ast = session.compile_to(program, Stage::Ast).unwrap();
+    assert_eq!(
+        ast.text(&session.source),
+        "let x = 1 in\nlet x = x + 1 in\nx;;\n"
+    );
+}
@@ -0,0 +1,45 @@
+extern crate ruscaml;
+
+use ruscaml::lexer::tokenize;
+use ruscaml::normal::{normalize, Exp};
+use ruscaml::parser::parse;
+use ruscaml::{line_col, Linkage, Span};
+
+#[test]
+fn tokens_cover_their_text() {
+    let tokenset = tokenize("let xs = 12 -> xs;;\n").unwrap();
+    let spans: Vec<Span> = tokenset.tokens.iter().map(|token| token.span).collect();
+    assert_eq!(spans[0], Span::new(0, 3));
+    assert_eq!(spans[1], Span::new(4, 6));
+    assert_eq!(spans[3], Span::new(9, 11));
+    assert_eq!(spans[4], Span::new(12, 14));
+}
+
+#[test]
+fn normal_form_keeps_source_spans() {
+    let program = parse(tokenize("let x = 1 + 2 in x;;\n").unwrap()).unwrap();
+    match normalize(program.body, &Linkage::default()).unwrap() {
+        Exp::Let(_, _, body, span) => {
+            assert_eq!(span, Span::new(8, 13));
+            assert!(matches!(*body, Exp::Compexp(_, span) if span == Span::new(17, 18)));
+        }
+        exp => panic!("unexpected {:?}", exp),
+    }
+}
+
+#[test]
+fn utf8_tabs_and_crlf_are_skipped() {
+    let program = "(* héllo → wörld *)\r\n\tlet x = 1 in\r\n\tx;;\r\n";
+    let tokenset = tokenize(program).unwrap();
+    let x = &tokenset.tokens[5];
+    assert_eq!(&program[x.span.lo..x.span.hi], "x");
+    assert_eq!(line_col(program, x.span.lo), (3, 2));
+}
+
+#[test]
+fn columns_count_characters() {
+    let program = "let s = \"é\" in\nλ;;\n";
+    let err = tokenize(program).err().unwrap();
+    assert_eq!(line_col(program, err.span.unwrap().lo), (2, 1));
+    assert_eq!(line_col("\"é\" + y", 7), (1, 7));
+}
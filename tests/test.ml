@@ -1,6 +1,6 @@
 EXPECT(1+3;; => 4):
 EXPECT(1 + 3 * 4 + 5;; => 18):
-EXPECT(if 1 then 4 else 6;; => 4):
+EXPECT(if true then 4 else 6;; => 4):
 EXPECT(let x = 3 in (x + 1) * 2 + (3 + 1);; => 12):
 EXPECT(let f = fun x -> fun y -> fun z -> x+y+z in f 1 2 3;; => 6):
 EXPECT(let a = 1 in let b = 3 in a+b*3;; => 10):
@@ -24,7 +24,7 @@ let d = 4 in
 let rec g = fun x -> a * x + c + d in
 g 4;; => 12):
 EXPECT((fun x -> fun y -> x + y) 2 3;; => 3):
-EXPECT(let a = let a = 1 in a+1 in a;; => 3):
+EXPECT(let a = let a = 1 in a+1 in a;; => 2):
 
 (* recur check violataion *)
 (* let a = 4 in recur 5;; *)
@@ -14,21 +14,22 @@ fn unittest() -> Result<(), Box<dyn std::error::Error>> {
 
     for program in programs {
         let mut f = BufWriter::new(fs::File::create("./tests/onetest.ml").unwrap());
-        f.write(program.as_bytes()).unwrap();
+        f.write_all(program.as_bytes()).unwrap();
         f.flush()?;
-        print!("{}\n", program);
+        println!("{}", program);
         let output = Command::new("./target/debug/ruscaml")
-            .args(&["./tests/onetest.ml"])
+            .args(["./tests/onetest.ml"])
             .output()
             .expect("failed to execute unit test");
 
         let test_stdout = output.stdout;
         let error_stdout = output.stderr;
 
-        if !(std::str::from_utf8(&error_stdout).unwrap() == "") {
+        // warnings are printed to stderr, so only the status tells a failure
+        if !output.status.success() {
             println!("{}", std::str::from_utf8(&error_stdout).unwrap());
             let failed = Command::new("echo")
-                .args(&["-e", &format!("\\e[31m FAILED COMPILE\\e[m [{}]", program)])
+                .args(["-e", &format!("\\e[31m FAILED COMPILE\\e[m [{}]", program)])
                 .output()
                 .expect("");
             let failed_stdout = failed.stdout;
@@ -41,16 +42,16 @@ fn unittest() -> Result<(), Box<dyn std::error::Error>> {
         }
 
         let mut f2 = BufWriter::new(fs::File::create("a.s").unwrap());
-        f2.write(&test_stdout).unwrap();
+        f2.write_all(&test_stdout).unwrap();
         f2.flush()?;
 
         let _ = Command::new("aarch64-linux-gnu-gcc")
-            .args(&["-c", "src/exe.c"])
+            .args(["-c", "src/exe.c"])
             .output()
             .expect("this command should always sucess");
 
         let arm64assemble = Command::new("aarch64-linux-gnu-gcc")
-            .args(&["exe.o", "a.s", "-o", "a"])
+            .args(["exe.o", "a.s", "-o", "a"])
             .output()
             .expect("");
 
@@ -70,7 +71,7 @@ fn unittest() -> Result<(), Box<dyn std::error::Error>> {
         }
 
         let qemu_exe = Command::new("qemu-aarch64")
-            .args(&["-L", "/usr/aarch64-linux-gnu", "a"])
+            .args(["-L", "/usr/aarch64-linux-gnu", "a"])
             .output()
             .expect("");
 
@@ -86,7 +87,7 @@ fn unittest() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let _ = Command::new("rm")
-        .args(&["./tests/onetest.ml", "a", "exe.o"])
+        .args(["./tests/onetest.ml", "a", "exe.o"])
         .output()
         .expect("failed to delete onetest.ml");
 
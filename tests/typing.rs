@@ -0,0 +1,28 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::compile;
+
+#[test]
+fn type_error_expected_found() {
+    let (ok, out) = compile("binop", "let a = 1 in\na + true;;\n");
+    assert!(!ok);
+    assert!(out.contains("Error: expected int, found bool. Line: 2, Column: 5."));
+    assert!(out.contains("\ta + true;;\n\t    ^^^^\n"));
+}
+
+#[test]
+fn type_error_note() {
+    let (ok, out) = compile("note", "let f = fun x -> x + 1 in\nf true;;\n");
+    assert!(!ok);
+    assert!(out.contains("Error: expected int, found bool. Line: 2, Column: 3."));
+    assert!(out.contains("Note: expected type `int` was introduced here. Line: 1, Column: 18."));
+    assert!(out.contains("\tlet f = fun x -> x + 1 in\n\t                 ^\n"));
+}
+
+#[test]
+fn well_typed() {
+    let (ok, _) = compile("ok", "let a = 1 in\nif a < 3 == true then a + 1 else 2;;\n");
+    assert!(ok);
+}
@@ -0,0 +1,54 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::compile_in;
+
+const UTIL: &str = "let rec double = fun x -> x * 2\nlet rec quad = fun x -> double (double x)\n";
+
+#[test]
+fn unit_exports_declared_functions() {
+    let files = [("util.ml", UTIL), ("util.mli", "val quad : int -> int\n")];
+    let (ok, out) = compile_in("unit_export", &files, &["-c", "util.ml"]);
+    assert!(ok);
+    assert!(out.contains("\t.global ruscaml_Util_quad\n"));
+    assert!(!out.contains("\t.global ruscaml_Util_double\n"));
+    assert!(!out.contains("_toplevel"));
+    assert!(out.contains("ruscaml_Util_double_clo:\n\t.quad ruscaml_Util_double\n"));
+}
+
+#[test]
+fn interface_mismatch() {
+    let files = [("util.ml", UTIL), ("util.mli", "val quad : int -> bool\n")];
+    let (ok, out) = compile_in("unit_mismatch", &files, &["-c", "util.ml"]);
+    assert!(!ok);
+    assert!(out.contains("`quad` has type int -> int but the interface declares int -> bool."));
+}
+
+#[test]
+fn main_uses_interface() {
+    let files = [
+        ("util.mli", "val quad : int -> int\n"),
+        ("main.ml", "Util.quad 3;;\n"),
+    ];
+    let (ok, out) = compile_in("unit_main", &files, &["main.ml"]);
+    assert!(ok);
+    assert!(out.contains("adrp x0, ruscaml_Util_quad_clo"));
+
+    let files = [
+        ("util.mli", "val quad : int -> int\n"),
+        ("main.ml", "Util.quad true;;\n"),
+    ];
+    let (ok, out) = compile_in("unit_main_type", &files, &["main.ml"]);
+    assert!(!ok);
+    assert!(out.contains("expected int, found bool."));
+}
+
+#[test]
+fn nested_module_in_unit() {
+    let program = "let rec g = fun y -> y\nmodule M = struct\n  let rec f = fun x -> g x\nend\n";
+    let (ok, out) = compile_in("unit_nested", &[("cap.ml", program)], &["-c", "cap.ml"]);
+    assert!(ok);
+    assert!(out.contains("\t.global ruscaml_Cap_M_f\n"));
+    assert!(out.contains("ruscaml_Cap_M_f:\n"));
+}
@@ -0,0 +1,141 @@
+extern crate ruscaml;
+
+mod common;
+
+use common::{CLOSURES, EXTERNAL, LOOP, PROGRAM};
+
+use ruscaml::closure;
+use ruscaml::flat;
+use ruscaml::normal::{self, Value};
+use ruscaml::verify::{verify_closure, verify_flat, verify_normal, verify_vm};
+use ruscaml::vm;
+use ruscaml::{Linkage, Session, Span, Stage};
+
+#[test]
+fn compiled_programs_verify() {
+    for program in [PROGRAM, LOOP, EXTERNAL].iter() {
+        let mut session = Session::new("main.ml");
+        session.verify_ir = true;
+        session.compile(program).unwrap();
+    }
+    // the backend does not handle closures yet, but the IRs before it do
+    let mut session = Session::new("main.ml");
+    session.verify_ir = true;
+    session.compile_to(CLOSURES, Stage::Flat).unwrap();
+}
+
+fn var(id: &str) -> Value {
+    Value::Var(id.to_string())
+}
+
+#[test]
+fn unbound_normal_variables_are_reported() {
+    let span = Span::new(4, 5);
+    let exp = normal::Exp::Let(
+        "x".to_string(),
+        Box::new(normal::Cexp::Val(Value::Intv(1))),
+        Box::new(normal::Exp::Compexp(
+            Box::new(normal::Cexp::Binop(
+                normal::Bintype::Plus,
+                var("x"),
+                var("y"),
+            )),
+            span,
+        )),
+        Span::default(),
+    );
+    let err = verify_normal(&exp, &Linkage::default()).unwrap_err();
+    assert_eq!(err.message, "normal IR: `y` is not bound.");
+    assert_eq!(err.span, Some(span));
+}
+
+#[test]
+fn free_variables_of_closure_bodies_are_reported() {
+    let exp = |body_var: &str| {
+        closure::Exp::Let(
+            "a".to_string(),
+            Box::new(closure::Cexp::Val(Value::Intv(1))),
+            Box::new(closure::Exp::Letrec(
+                "f".to_string(),
+                vec!["env".to_string(), "x".to_string()],
+                Box::new(closure::Exp::Compexp(
+                    Box::new(closure::Cexp::Binop(
+                        normal::Bintype::Plus,
+                        var("x"),
+                        var(body_var),
+                    )),
+                    Span::default(),
+                )),
+                Box::new(closure::Exp::Compexp(
+                    Box::new(closure::Cexp::Tuple(vec![var("f"), var("a")])),
+                    Span::default(),
+                )),
+                Span::default(),
+            )),
+            Span::default(),
+        )
+    };
+    assert!(verify_closure(&exp("env"), &Linkage::default()).is_ok());
+    let err = verify_closure(&exp("a"), &Linkage::default()).unwrap_err();
+    assert_eq!(
+        err.message,
+        "closure IR: function `f` has free variable `a`."
+    );
+}
+
+#[test]
+fn flat_functions_may_not_use_outer_variables() {
+    let text = "let rec f (x) = x+y in\nlet rec _toplevel () = \nlet y = 1 in\n(&f, y)";
+    let err = verify_flat(&flat::read_program(text).unwrap(), &Linkage::default()).unwrap_err();
+    assert_eq!(err.message, "flat IR: `y` is not bound in `f`.");
+    assert_eq!(&text[err.span.unwrap().lo..err.span.unwrap().hi], "x+y");
+
+    let text = "let rec _toplevel () = \nlet g = (&f) in\ng.0";
+    let err = verify_flat(&flat::read_program(text).unwrap(), &Linkage::default()).unwrap_err();
+    assert_eq!(
+        err.message,
+        "flat IR: `_toplevel` uses unknown function `f`."
+    );
+}
+
+#[test]
+fn vm_registers_and_labels_are_checked() {
+    let verify = |text: &str| verify_vm(&vm::read_program(text, false).unwrap());
+    assert!(verify("function f (frame 0):\n r0 <- imm(1)\n r1 <- r0\nkill r0\n").is_ok());
+    let err = verify("function f (frame 0):\n r0 <- imm(1)\n r0 <- +(r0, r1)\n").unwrap_err();
+    assert_eq!(
+        err.message,
+        "vm IR: `r1` is used before it is defined in `f`."
+    );
+    let err = verify("function f (frame 0):\n r0 <- imm(1)\nkill r0\n r1 <- r0\n").unwrap_err();
+    assert_eq!(
+        err.message,
+        "vm IR: `r0` is used before it is defined in `f`."
+    );
+    let err = verify("function f (frame 0):\n goto .L3\n.L2:\n").unwrap_err();
+    assert_eq!(err.message, "vm IR: jump to missing label `.L3` in `f`.");
+}
+
+#[test]
+fn vm_registers_are_checked_along_each_branch() {
+    let verify = |text: &str| verify_vm(&vm::read_program(text, false).unwrap());
+    let branch = |other: &str| {
+        format!(
+            "function f (frame 1):\n r0 <- local(1)\n if r0 then goto .L0\n{} goto .L1\n.L0:\n r1 <- imm(2)\n.L1:\n r2 <- r1\n return(r2)\n",
+            other
+        )
+    };
+    assert!(verify(&branch(" r1 <- imm(1)\n")).is_ok());
+    // `r1` comes before the read in the layout, but not on the path
+    // that falls through
+    let err = verify(&branch("")).unwrap_err();
+    assert_eq!(
+        err.message,
+        "vm IR: `r1` is used before it is defined in `f`."
+    );
+    let err = verify(&branch(" r1 <- imm(1)\nkill r1\n")).unwrap_err();
+    assert_eq!(
+        err.message,
+        "vm IR: `r1` is used before it is defined in `f`."
+    );
+}